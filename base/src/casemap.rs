@@ -0,0 +1,96 @@
+//! IRC servers consider some nicks and channels equal that differ in bytes.
+//! Which ones, they announce as `CASEMAPPING` in `RPL_ISUPPORT`.
+
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CaseMapping {
+    /// Only `A-Z` and `a-z` are equivalent
+    Ascii,
+    /// Additionally, `[]\~` are the uppercase versions of `{}|^`
+    Rfc1459,
+    /// Like `Rfc1459`, except for `~` and `^`
+    StrictRfc1459,
+}
+
+impl Default for CaseMapping {
+    /// This is what RFC 1459 mandates, and what servers assume if they don't announce anything.
+    fn default() -> CaseMapping {
+        CaseMapping::Rfc1459
+    }
+}
+
+impl FromStr for CaseMapping {
+    type Err = ();
+    fn from_str(s: &str) -> Result<CaseMapping, ()> {
+        match s {
+            "ascii" => Ok(CaseMapping::Ascii),
+            "rfc1459" => Ok(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Ok(CaseMapping::StrictRfc1459),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CaseMapping {
+    pub fn lower_char(&self, c: char) -> char {
+        use self::CaseMapping::*;
+        match (*self, c) {
+            (_, 'A'...'Z') => ((c as u8) + (b'a' - b'A')) as char,
+            (Rfc1459, '[') | (StrictRfc1459, '[') => '{',
+            (Rfc1459, ']') | (StrictRfc1459, ']') => '}',
+            (Rfc1459, '\\') | (StrictRfc1459, '\\') => '|',
+            (Rfc1459, '~') => '^',
+            _ => c,
+        }
+    }
+
+    /// The canonical form of a nick or channel, suitable for hashing.
+    pub fn to_lower(&self, s: &str) -> String {
+        s.chars().map(|c| self.lower_char(c)).collect()
+    }
+
+    pub fn eq(&self, a: &str, b: &str) -> bool {
+        a.len() == b.len() &&
+        a.chars().zip(b.chars()).all(|(a, b)| self.lower_char(a) == self.lower_char(b))
+    }
+
+    pub fn contains(&self, haystack: &str, needle: &str) -> bool {
+        self.to_lower(haystack).contains(&self.to_lower(needle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaseMapping::*;
+
+    #[test]
+    fn lower() {
+        assert_eq!(Ascii.to_lower("Foo[]\\~"), "foo[]\\~");
+        assert_eq!(Rfc1459.to_lower("Foo[]\\~"), "foo{}|^");
+        assert_eq!(StrictRfc1459.to_lower("Foo[]\\~"), "foo{}|~");
+    }
+
+    #[test]
+    fn eq() {
+        assert!(Ascii.eq("FOO", "foo"));
+        assert!(!Ascii.eq("foo[", "foo{"));
+        assert!(Rfc1459.eq("foo[", "FOO{"));
+        assert!(Rfc1459.eq("a~", "a^"));
+        assert!(!StrictRfc1459.eq("a~", "a^"));
+        assert!(!Rfc1459.eq("foo", "fooo"));
+    }
+
+    #[test]
+    fn contains() {
+        assert!(Rfc1459.contains("hi [Foo]!", "{foo}"));
+        assert!(!Ascii.contains("hi [Foo]!", "{foo}"));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("strict-rfc1459".parse(), Ok(StrictRfc1459));
+        assert_eq!("ascii".parse(), Ok(Ascii));
+        assert!("utf-8".parse::<super::CaseMapping>().is_err());
+    }
+}
@@ -1,11 +1,20 @@
 use chrono::naive::date::NaiveDate;
 use chrono::offset::fixed::FixedOffset;
 
+use casemap::CaseMapping;
+use event::Time;
+use policy::{Losses, Outcome, Policy};
+use error::{Error, Result};
+
 pub struct Context {
     pub timezone_in: FixedOffset,
     pub timezone_out: FixedOffset,
     pub override_date: Option<NaiveDate>,
     pub channel: Option<String>,
+    /// How the network the log comes from compares nicks and channels
+    pub casemapping: CaseMapping,
+    pub policy: Policy,
+    pub losses: Losses,
 }
 
 impl Default for Context {
@@ -15,6 +24,82 @@ impl Default for Context {
             timezone_out: FixedOffset::west(0),
             override_date: None,
             channel: None,
+            casemapping: CaseMapping::default(),
+            policy: Policy::default(),
+            losses: Losses::default(),
+        }
+    }
+}
+
+impl Context {
+    /// The same settings, without any losses recorded, e.g. for another thread.
+    /// Its losses can be added back with `Losses::absorb`.
+    pub fn fork(&self) -> Context {
+        Context {
+            timezone_in: self.timezone_in,
+            timezone_out: self.timezone_out,
+            override_date: self.override_date,
+            channel: self.channel.clone(),
+            casemapping: self.casemapping,
+            policy: self.policy,
+            losses: Losses::default(),
+        }
+    }
+
+    /// Check a field an encoder needs for events of type `ty`.
+    /// `Ok(None)` means the event should be skipped.
+    pub fn require<'b>(&self,
+                       ty: &'static str,
+                       field: &'static str,
+                       value: Option<&'b str>,
+                       fallback: &'b str)
+                       -> Result<Option<&'b str>> {
+        match value {
+            Some(v) => Ok(Some(v)),
+            None => self.degrade(ty, field).map(|o| o.map(|()| fallback)),
+        }
+    }
+
+    /// Format `time` for output, falling back to the epoch if it's unknown
+    /// or lacks the date the format asks for.
+    pub fn time(&self, ty: &'static str, time: &Time, format: &str) -> Result<Option<String>> {
+        match time.try_format(&self.timezone_out, format) {
+            Some(t) => Ok(Some(t)),
+            None => {
+                self.degrade(ty, "time").map(|o| {
+                    o.map(|()| Time::Timestamp(0).with_format(&self.timezone_out, format))
+                })
+            }
+        }
+    }
+
+    /// Decide what to do with an event type the encoder can't represent at all.
+    /// `Ok(Some(()))` asks for a best-effort rendering.
+    pub fn unsupported(&self, ty: &'static str) -> Result<Option<()>> {
+        match self.policy {
+            Policy::Fail => Err(Error::Unsupported(ty)),
+            Policy::Skip => {
+                self.losses.record(ty, "type", Outcome::Skipped);
+                Ok(None)
+            }
+            Policy::Fallback => {
+                self.losses.record(ty, "type", Outcome::Substituted);
+                Ok(Some(()))
+            }
+        }
+    }
+
+    fn degrade(&self, ty: &'static str, field: &'static str) -> Result<Option<()>> {
+        match self.policy {
+            Policy::Fail => Err(Error::Incomplete(ty, field)),
+            Policy::Skip => {
+                self.losses.record(ty, field, Outcome::Skipped);
+                Ok(None)
+            }
+            Policy::Fallback => {
+                self.losses.record(ty, field, Outcome::Substituted);
+                Ok(Some(()))
+            }
         }
     }
 }
@@ -10,12 +10,25 @@ pub enum Error {
     Parse(String),
     Chrono(ParseError),
     Io(io::Error),
-    Custom(Box<error::Error>),
+    /// An event of the given type lacks a field the output format requires
+    Incomplete(&'static str, &'static str),
+    /// The output format has no way to represent events of the given type
+    Unsupported(&'static str),
+    /// Boxed as `Send + Sync`, so errors can be passed between threads
+    Custom(Box<error::Error + Send + Sync>),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.write_str(self.description())
+        match self {
+            &Error::Incomplete(ty, field) => {
+                write!(fmt, "{} event without {}, but the output format requires it", ty, field)
+            }
+            &Error::Unsupported(ty) => {
+                write!(fmt, "{} events are not supported by the output format", ty)
+            }
+            _ => fmt.write_str(self.description()),
+        }
     }
 }
 
@@ -26,6 +39,8 @@ impl error::Error for Error {
             &Parse(_) => "error while parsing",
             &Chrono(_) => "error while parsing time strings",
             &Io(_) => "error during input/output",
+            &Incomplete(..) => "event lacks information required by the output format",
+            &Unsupported(_) => "event type not supported by the output format",
             &Custom(ref e) => e.description(),
         }
     }
@@ -36,6 +51,7 @@ impl error::Error for Error {
             &Parse(ref _e) => None,
             &Chrono(ref e) => Some(e),
             &Io(ref e) => Some(e),
+            &Incomplete(..) | &Unsupported(_) => None,
             &Custom(ref e) => e.cause(),
         }
     }
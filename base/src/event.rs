@@ -3,12 +3,15 @@
 
 use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::fmt::Write;
 
 use chrono::naive::time::NaiveTime;
 use chrono::offset::fixed::FixedOffset;
 use chrono::offset::local::Local;
 use chrono::offset::TimeZone;
 
+use casemap::CaseMapping;
+
 /// A whole log, in memory. This structure does not specify its
 /// use. It may represent a private query, or the log of a channel.
 pub struct Log<'a> {
@@ -33,15 +36,27 @@ impl Time {
           .unwrap_or(Time::Unknown)
     }
 
+    /// Panics if the time is unknown, or lacks information `f` asks for.
+    /// See `try_format` for the non-panicking version.
     pub fn with_format(&self, tz: &FixedOffset, f: &str) -> String {
-        match self {
-            &Time::Unknown => panic!("Time data for this event is not present"),
+        match self.try_format(tz, f) {
+            Some(s) => s,
+            None => panic!("Time data for this event is not present"),
+        }
+    }
+
+    pub fn try_format(&self, tz: &FixedOffset, f: &str) -> Option<String> {
+        let mut out = String::new();
+        let res = match self {
+            &Time::Unknown => return None,
             &Time::Hms(h, m, s) => {
-                format!("{}",
-                        NaiveTime::from_hms(h as u32, m as u32, s as u32).format(f))
+                write!(&mut out,
+                       "{}",
+                       NaiveTime::from_hms(h as u32, m as u32, s as u32).format(f))
             }
-            &Time::Timestamp(t) => format!("{}", tz.timestamp(t, 0).format(f)),
-        }
+            &Time::Timestamp(t) => write!(&mut out, "{}", tz.timestamp(t, 0).format(f)),
+        };
+        res.ok().map(|()| out)
     }
 
     pub fn as_timestamp(&self) -> i64 {
@@ -89,6 +104,17 @@ pub struct Event<'a> {
     pub channel: Option<Cow<'a, str>>,
 }
 
+impl<'a> Event<'a> {
+    /// A copy that doesn't borrow from the input anymore.
+    pub fn into_owned(self) -> Event<'static> {
+        Event {
+            ty: self.ty.map_nicks(str::to_owned),
+            time: self.time,
+            channel: self.channel.map(|c| Cow::Owned(c.into_owned())),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash, RustcEncodable, RustcDecodable)]
 pub struct User<'a> {
     nicks: Cow<'a, str>,
@@ -147,6 +173,14 @@ pub enum Type<'a> {
         mode: Cow<'a, str>,
         masks: Cow<'a, str>,
     },
+    Away {
+        nick: Cow<'a, str>,
+        reason: Option<Cow<'a, str>>,
+    },
+    Ctcp {
+        from: Cow<'a, str>,
+        content: Cow<'a, str>,
+    },
 }
 
 impl<'a> Type<'a> {
@@ -163,39 +197,138 @@ impl<'a> Type<'a> {
             &Kick { ref kicking_nick, .. } => kicking_nick.as_ref().map(|s| &*s as &str),
             &TopicChange { ref nick, .. } => nick.as_ref().map(|s| &*s as &str),
             &Mode { ref nick, .. } => nick.as_ref().map(|s| &*s as &str),
+            &Away { ref nick, .. } => Some(nick),
+            &Ctcp { ref from, .. } => Some(from),
             _ => None,
         }
     }
 
-    pub fn involves(&self, needle: &str) -> bool {
+    /// Whether `needle` is one of the nicks taking part in this event, or is mentioned in its text.
+    /// Nicks are compared according to `casemap`.
+    pub fn involves(&self, needle: &str, casemap: CaseMapping) -> bool {
         use self::Type::*;
+        let is = |s: &str| casemap.eq(s, needle);
+        let has = |s: &Option<Cow<str>>| s.as_ref().map_or(false, |s| casemap.contains(s, needle));
         match self {
-            &Msg { ref from, ref content, .. } => from == needle || content.contains(needle),
-            &Action { ref from, ref content, .. } => from == needle || content.contains(needle),
-            &Join { ref nick, .. } => nick == needle,
-            &Part { ref nick, ref reason, .. } => {
-                nick == needle || reason.as_ref().map_or(false, |r| r.contains(needle))
-            }
-            &Quit { ref nick, ref reason, .. } => {
-                nick == needle || reason.as_ref().map_or(false, |r| r.contains(needle))
-            }
-            &Nick { ref old_nick, ref new_nick, .. } => old_nick == needle || new_nick == needle,
-            &Notice { ref from, ref content, .. } => from == needle || content.contains(needle),
+            &Msg { ref from, ref content, .. } => is(from) || casemap.contains(content, needle),
+            &Action { ref from, ref content, .. } => is(from) || casemap.contains(content, needle),
+            &Join { ref nick, .. } => is(nick),
+            &Part { ref nick, ref reason, .. } => is(nick) || has(reason),
+            &Quit { ref nick, ref reason, .. } => is(nick) || has(reason),
+            &Nick { ref old_nick, ref new_nick, .. } => is(old_nick) || is(new_nick),
+            &Notice { ref from, ref content, .. } => is(from) || casemap.contains(content, needle),
             &Kick { ref kicked_nick, ref kicking_nick, ref kick_message, .. } => {
-                *kicked_nick == Cow::Borrowed(needle) ||
-                kicking_nick.as_ref().map_or(false, |k| k.as_ref() == Cow::Borrowed(needle)) ||
-                kick_message.as_ref().map_or(false, |k| k.as_ref() == Cow::Borrowed(needle))
+                is(kicked_nick) || kicking_nick.as_ref().map_or(false, |k| is(k)) ||
+                has(kick_message)
             }
             &TopicChange { ref nick, ref new_topic, .. } => {
-                nick.as_ref().map_or(false, |k| k.as_ref() == needle) || new_topic.contains(needle)
+                nick.as_ref().map_or(false, |k| is(k)) || casemap.contains(new_topic, needle)
             }
-            &Mode { ref nick, .. } => {
-                nick.as_ref().map_or(false, |k| k.as_ref() == Cow::Borrowed(needle))
+            &Mode { ref nick, ref masks, .. } => {
+                nick.as_ref().map_or(false, |k| is(k)) || masks.split(' ').any(|m| is(m))
             }
+            &Away { ref nick, ref reason, .. } => is(nick) || has(reason),
+            &Ctcp { ref from, ref content, .. } => is(from) || casemap.contains(content, needle),
             _ => false,
         }
     }
 
+    /// Copy this event type, passing every nick through `f`, e.g. to bring them into
+    /// a canonical form.
+    pub fn map_nicks<F>(&self, f: F) -> Type<'static>
+        where F: Fn(&str) -> String
+    {
+        use self::Type::*;
+        fn own(s: &str) -> Cow<'static, str> {
+            Cow::Owned(s.to_owned())
+        }
+        fn own_opt(s: &Option<Cow<str>>) -> Option<Cow<'static, str>> {
+            s.as_ref().map(|s| own(s))
+        }
+        let nick = |s: &str| Cow::Owned(f(s));
+        match self {
+            &Connect => Connect,
+            &Disconnect => Disconnect,
+            &Msg { ref from, ref content } => {
+                Msg {
+                    from: nick(from),
+                    content: own(content),
+                }
+            }
+            &Action { ref from, ref content } => {
+                Action {
+                    from: nick(from),
+                    content: own(content),
+                }
+            }
+            &Join { nick: ref n, ref mask } => {
+                Join {
+                    nick: nick(n),
+                    mask: own_opt(mask),
+                }
+            }
+            &Part { nick: ref n, ref mask, ref reason } => {
+                Part {
+                    nick: nick(n),
+                    mask: own_opt(mask),
+                    reason: own_opt(reason),
+                }
+            }
+            &Quit { nick: ref n, ref mask, ref reason } => {
+                Quit {
+                    nick: nick(n),
+                    mask: own_opt(mask),
+                    reason: own_opt(reason),
+                }
+            }
+            &Nick { ref old_nick, ref new_nick } => {
+                Nick {
+                    old_nick: nick(old_nick),
+                    new_nick: nick(new_nick),
+                }
+            }
+            &Notice { ref from, ref content } => {
+                Notice {
+                    from: nick(from),
+                    content: own(content),
+                }
+            }
+            &Kick { ref kicked_nick, ref kicking_nick, ref kick_message } => {
+                Kick {
+                    kicked_nick: nick(kicked_nick),
+                    kicking_nick: kicking_nick.as_ref().map(|n| nick(n)),
+                    kick_message: own_opt(kick_message),
+                }
+            }
+            &Topic { ref topic } => Topic { topic: own(topic) },
+            &TopicChange { nick: ref n, ref new_topic } => {
+                TopicChange {
+                    nick: n.as_ref().map(|n| nick(n)),
+                    new_topic: own(new_topic),
+                }
+            }
+            &Mode { nick: ref n, ref mode, ref masks } => {
+                Mode {
+                    nick: n.as_ref().map(|n| nick(n)),
+                    mode: own(mode),
+                    masks: own(masks),
+                }
+            }
+            &Away { nick: ref n, ref reason } => {
+                Away {
+                    nick: nick(n),
+                    reason: own_opt(reason),
+                }
+            }
+            &Ctcp { ref from, ref content } => {
+                Ctcp {
+                    from: nick(from),
+                    content: own(content),
+                }
+            }
+        }
+    }
+
     pub fn type_desc(&self) -> &'static str {
         use self::Type::*;
         match self {
@@ -210,6 +343,8 @@ impl<'a> Type<'a> {
             &TopicChange { .. } => "topic_change",
             &Kick { .. } => "kick",
             &Mode { .. } => "mode",
+            &Away { .. } => "away",
+            &Ctcp { .. } => "ctcp",
             &Connect => "connect",
             &Disconnect => "disconnect",
         }
@@ -226,6 +361,8 @@ impl<'a> Type<'a> {
             &Kick { ref kick_message, .. } => kick_message.as_ref().map(|s| s as &str),
             &Topic { ref topic, .. } => Some(topic),
             &TopicChange { ref new_topic, .. } => Some(new_topic),
+            &Away { ref reason, .. } => reason.as_ref().map(|s| s as &str),
+            &Ctcp { ref content, .. } => Some(content),
             _ => None,
         }
     }
@@ -3,6 +3,8 @@ extern crate chrono;
 extern crate log;
 extern crate rustc_serialize;
 
+pub mod policy;
+pub mod casemap;
 pub mod event;
 pub mod context;
 pub mod error;
@@ -14,6 +16,8 @@ use std::io::{BufRead, Write};
 pub use context::Context;
 pub use event::{Event, Time};
 pub use error::*;
+pub use policy::Policy;
+pub use casemap::CaseMapping;
 
 pub trait Encode {
     fn encode<'a>(&'a self,
@@ -0,0 +1,113 @@
+//! What encoders do with events they can't represent faithfully, and
+//! bookkeeping of what got lost along the way.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Not every format can hold every event, and not every event carries all the
+/// information a format requires (e.g. a Weechat quit without a hostmask, written
+/// as Energymech).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Abort with `Error::Incomplete` or `Error::Unsupported`.
+    Fail,
+    /// Drop the event and count it.
+    Skip,
+    /// Write the event anyway, substituting placeholders for missing data.
+    Fallback,
+}
+
+/// Conversions stay as lossy as they always were, writing what they can; `Fail`
+/// and `Skip` are opt-in.
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy::Fallback
+    }
+}
+
+impl FromStr for Policy {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Policy, ()> {
+        match s {
+            "fail" => Ok(Policy::Fail),
+            "skip" => Ok(Policy::Skip),
+            "fallback" => Ok(Policy::Fallback),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Outcome {
+    Skipped,
+    Substituted,
+}
+
+/// Counts of degraded events, keyed by event type, the missing piece of
+/// information (or `"type"` if the whole event type is unsupported) and what was done about it.
+///
+/// This lives in the `Context`, so encoders can record into it through a shared reference.
+#[derive(Debug, Default)]
+pub struct Losses {
+    counts: RefCell<BTreeMap<(&'static str, &'static str, Outcome), u64>>,
+}
+
+impl Losses {
+    pub fn record(&self, ty: &'static str, missing: &'static str, outcome: Outcome) {
+        *self.counts.borrow_mut().entry((ty, missing, outcome)).or_insert(0) += 1;
+    }
+
+    /// Add the counts of `other`, e.g. from a forked `Context`.
+    pub fn absorb(&self, other: Losses) {
+        let mut counts = self.counts.borrow_mut();
+        for (key, n) in other.counts.into_inner() {
+            *counts.entry(key).or_insert(0) += n;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.borrow().is_empty()
+    }
+
+    pub fn entries(&self) -> Vec<(&'static str, &'static str, Outcome, u64)> {
+        self.counts
+            .borrow()
+            .iter()
+            .map(|(&(ty, missing, outcome), &n)| (ty, missing, outcome, n))
+            .collect()
+    }
+}
+
+impl fmt::Display for Losses {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (ty, missing, outcome, n) in self.entries() {
+            let what = if missing == "type" {
+                "unsupported by the output format".to_owned()
+            } else {
+                format!("without {}", missing)
+            };
+            match outcome {
+                Outcome::Skipped => try!(writeln!(fmt, "skipped {} {} events {}", n, ty, what)),
+                Outcome::Substituted => {
+                    try!(writeln!(fmt, "wrote {} {} events {}, using placeholders", n, ty, what))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Unwrap the `Ok(Some(_))` of one of the degradation helpers on `Context`, or return early
+/// from the surrounding encoder, either propagating the error or skipping the event.
+#[macro_export]
+macro_rules! degrade {
+    ($e:expr) => {
+        match $e {
+            Ok(Some(v)) => v,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use clap::ArgMatches;
+
+use std::io::Write;
+
+use ilc_base::{self, Context};
+use ilc_ops::audit::{Kind, Operator};
+use Environment;
+use format_time;
+
+/// Like `3d 4h`, `2h 5m` or `40s`, leaving out smaller units.
+pub fn duration(secs: i64) -> String {
+    let (d, h, m) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    if d > 0 {
+        format!("{}d {}h", d, h)
+    } else if h > 0 {
+        format!("{}h {}m", h, m)
+    } else if m > 0 {
+        format!("{}m", m)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// A heading per operator with their number of actions, then one row per action.
+pub fn output(args: &ArgMatches, ctx: &Context, operators: &[Operator]) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    for op in operators {
+        let nick = if op.nick.is_empty() { "(unknown)" } else { &op.nick };
+        try!(writeln!(&mut out, "{} ({}):", nick, op.actions.len()));
+        for a in &op.actions {
+            let time = a.time.map(|t| format_time(ctx, t)).unwrap_or_default();
+            try!(write!(&mut out,
+                        "  {:19}  {:12}  {:7}  {}",
+                        time,
+                        a.channel,
+                        a.kind.name(),
+                        a.target));
+            if let Some(ref reason) = a.reason {
+                try!(write!(&mut out, " ({})", reason));
+            }
+            match (a.kind, a.lasted) {
+                (Kind::Ban, Some(t)) | (Kind::Quiet, Some(t)) => {
+                    try!(write!(&mut out, ", lifted after {}", duration(t)))
+                }
+                (Kind::Unban, Some(t)) | (Kind::Unquiet, Some(t)) => {
+                    try!(write!(&mut out, ", after {}", duration(t)))
+                }
+                _ => (),
+            }
+            try!(writeln!(&mut out, ""));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use clap::ArgMatches;
+
+use std::io::Write;
+
+use ilc_base;
+use ilc_ops::interactions::{Edge, Graph};
+use Environment;
+
+fn sorted_edges(graph: &Graph) -> Vec<(&(String, String), &Edge)> {
+    let mut edges: Vec<_> = graph.edges.iter().collect();
+    edges.sort_by(|a, b| a.0.cmp(b.0));
+    edges
+}
+
+fn dot_id(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Graphviz, with line widths growing with the weight of the edges.
+pub fn output_as_dot(args: &ArgMatches, graph: &Graph) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    let mut nodes: Vec<_> = graph.nodes.iter().collect();
+    nodes.sort_by(|a, b| a.0.cmp(b.0));
+
+    try!(writeln!(&mut out, "digraph interactions {{"));
+    for (key, node) in nodes {
+        try!(writeln!(&mut out,
+                      "    {} [label={}, lines={}];",
+                      dot_id(key),
+                      dot_id(&node.nick),
+                      node.lines));
+    }
+    for (&(ref from, ref to), edge) in sorted_edges(graph) {
+        try!(writeln!(&mut out,
+                      "    {} -> {} [weight={}, penwidth={:.2}];",
+                      dot_id(from),
+                      dot_id(to),
+                      edge.weight(),
+                      1.0 + (edge.weight() as f32).ln()));
+    }
+    try!(writeln!(&mut out, "}}"));
+    Ok(())
+}
+
+pub fn output_as_graphml(args: &ArgMatches, graph: &Graph) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    let mut nodes: Vec<_> = graph.nodes.iter().collect();
+    nodes.sort_by(|a, b| a.0.cmp(b.0));
+
+    try!(writeln!(&mut out,
+                  "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                   <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"));
+    let keys = [("nick", "node", "string"),
+                ("lines", "node", "int"),
+                ("weight", "edge", "int"),
+                ("addressed", "edge", "int"),
+                ("mentioned", "edge", "int"),
+                ("replied", "edge", "int")];
+    for &(id, kind, ty) in &keys {
+        try!(writeln!(&mut out,
+                      "  <key id=\"{0}\" for=\"{1}\" attr.name=\"{0}\" attr.type=\"{2}\"/>",
+                      id,
+                      kind,
+                      ty));
+    }
+    try!(writeln!(&mut out, "  <graph id=\"interactions\" edgedefault=\"directed\">"));
+    for (key, node) in nodes {
+        try!(writeln!(&mut out,
+                      "    <node id=\"{}\"><data key=\"nick\">{}</data>\
+                       <data key=\"lines\">{}</data></node>",
+                      xml(key),
+                      xml(&node.nick),
+                      node.lines));
+    }
+    for (&(ref from, ref to), edge) in sorted_edges(graph) {
+        try!(writeln!(&mut out,
+                      "    <edge source=\"{}\" target=\"{}\"><data key=\"weight\">{}</data>\
+                       <data key=\"addressed\">{}</data><data key=\"mentioned\">{}</data>\
+                       <data key=\"replied\">{}</data></edge>",
+                      xml(from),
+                      xml(to),
+                      edge.weight(),
+                      edge.addressed,
+                      edge.mentioned,
+                      edge.replied));
+    }
+    try!(writeln!(&mut out, "  </graph>\n</graphml>"));
+    Ok(())
+}
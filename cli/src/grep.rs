@@ -0,0 +1,138 @@
+use clap::ArgMatches;
+
+use atty;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use ilc_base::{self, Context};
+use ilc_ops::grep::{self, Line, Matcher};
+use Environment;
+use gather_input;
+
+const HIGHLIGHT: &'static str = "\x1b[01;31m";
+const RESET: &'static str = "\x1b[0m";
+
+/// Whether to colour matches, by `--color`.
+fn colored(args: &ArgMatches) -> bool {
+    match args.value_of("color") {
+        Some("always") => true,
+        Some("never") => false,
+        _ => !args.is_present("output_file") && atty::is(atty::Stream::Stdout),
+    }
+}
+
+/// Wrap every occurrence of `matches` in `line` in colour codes.
+fn highlight(line: &str, matches: &[String]) -> String {
+    let mut ranges: Vec<(usize, usize)> = matches.iter()
+                                                 .filter(|m| !m.is_empty())
+                                                 .flat_map(|m| {
+                                                     line.match_indices(&m[..])
+                                                         .map(|(i, _)| (i, i + m.len()))
+                                                         .collect::<Vec<_>>()
+                                                 })
+                                                 .collect();
+    ranges.sort();
+    let mut out = String::with_capacity(line.len());
+    let mut at = 0;
+    for (start, end) in ranges {
+        if end <= at {
+            continue;
+        }
+        let start = if start < at { at } else { start };
+        out.push_str(&line[at..start]);
+        out.push_str(HIGHLIGHT);
+        out.push_str(&line[start..end]);
+        out.push_str(RESET);
+        at = end;
+    }
+    out.push_str(&line[at..]);
+    out
+}
+
+/// How many lines the first `len` bytes of the file at `path` hold.
+fn lines_before(path: &Path, len: u64) -> io::Result<usize> {
+    let mut input = try!(File::open(path)).take(len);
+    let mut buf = [0u8; 65536];
+    let mut lines = 0;
+    loop {
+        let n = try!(input.read(&mut buf));
+        if n == 0 {
+            return Ok(lines);
+        }
+        lines += buf[..n].iter().filter(|&&b| b == b'\n').count();
+    }
+}
+
+/// Search every input file in turn, writing hits and their context with the output format, or
+/// prefixed with `file:line:` for hits and `file-line-` for context, like grep.
+pub fn output(args: &ArgMatches,
+              ctx: &Context,
+              matcher: &Matcher,
+              before: usize,
+              after: usize)
+              -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let encoder = e.encoder();
+    let mut decoder = e.decoder();
+    let mut out = e.output();
+    let provenance = args.is_present("provenance");
+    let colored = colored(args);
+    let mut written = false;
+
+    let files = gather_input(args);
+    let mut inputs: Vec<(String, usize, Box<BufRead>)> = Vec::new();
+    for path in &files {
+        let mut file = e.open(path);
+        // the time index may have skipped the start of the file
+        let skipped = if provenance {
+            let start = try!(file.get_mut().seek(SeekFrom::Current(0)));
+            try!(lines_before(path, start))
+        } else {
+            0
+        };
+        inputs.push((path.display().to_string(), skipped, Box::new(BufReader::new(file))));
+    }
+    if inputs.is_empty() {
+        inputs.push(("(standard input)".to_owned(),
+                     0,
+                     Box::new(BufReader::new(io::stdin()))));
+    }
+
+    for (name, skipped, mut input) in inputs {
+        let mut first = true;
+        try!(grep::grep(ctx,
+                        &mut input,
+                        &mut *decoder,
+                        matcher,
+                        before,
+                        after,
+                        |l: Line| {
+                            // separate groups of events that aren't next to each other
+                            if written && (l.gap || first) && (before > 0 || after > 0) {
+                                try!(writeln!(&mut out, "--"));
+                            }
+                            first = false;
+                            written = true;
+                            let mut buf = Vec::new();
+                            try!(encoder.encode(ctx, &mut buf, &l.event));
+                            let mut text = String::from_utf8_lossy(&buf).into_owned();
+                            if colored && l.hit {
+                                text = highlight(&text, &l.matches);
+                            }
+                            if provenance {
+                                let sep = if l.hit { ':' } else { '-' };
+                                try!(write!(&mut out,
+                                            "{}{}{}{}",
+                                            name,
+                                            sep,
+                                            skipped + l.line,
+                                            sep));
+                            }
+                            try!(out.write_all(text.as_bytes()));
+                            Ok(())
+                        }));
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use clap::ArgMatches;
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use ilc_base::{self, Context};
+use ilc_ops::index::{Index, Query, Store};
+use Environment;
+use die;
+use gather_input;
+use value_or;
+
+/// An index is a directory, with the list of files and segments in `index`, and the segments
+/// next to it.
+struct Dir<'a>(&'a Path);
+
+impl<'a> Dir<'a> {
+    fn index(&self) -> PathBuf {
+        self.0.join("index")
+    }
+
+    fn segment(&self, segment: u32) -> PathBuf {
+        self.0.join(format!("{}.seg", segment))
+    }
+
+    fn load(&self) -> ilc_base::Result<Index> {
+        let file = try!(File::open(self.index()));
+        Index::load(&mut BufReader::new(file))
+    }
+}
+
+impl<'a> Store for Dir<'a> {
+    type Segment = BufReader<File>;
+
+    fn open(&self, segment: u32) -> ilc_base::Result<BufReader<File>> {
+        Ok(BufReader::new(try!(File::open(self.segment(segment)))))
+    }
+
+    fn create(&self,
+              segment: u32,
+              write: &mut FnMut(&mut Write) -> ilc_base::Result<()>)
+              -> ilc_base::Result<()> {
+        let mut out = BufWriter::new(try!(File::create(self.segment(segment))));
+        try!(write(&mut out));
+        try!(out.flush());
+        Ok(())
+    }
+}
+
+/// Add what is new in the input files to the index in the directory `path`, or to a new one if
+/// there is none. Files are known by their canonical path, and read from where they were left
+/// off. The new events go into a new segment.
+pub fn build(args: &ArgMatches, ctx: &Context, path: &str) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let files = gather_input(args);
+    if files.is_empty() {
+        die("Events are found again by file, so the index can only be built from input files");
+    }
+    let dir = Dir(Path::new(path));
+    let mut index = if dir.index().exists() {
+        try!(dir.load())
+    } else {
+        try!(fs::create_dir_all(path));
+        Index::new(ctx.casemapping)
+    };
+    let mut decoder = e.whole_decoder();
+    for file in files {
+        let name = try!(fs::canonicalize(&file)).display().to_string();
+        let mut input = try!(File::open(&file));
+        let len = try!(input.metadata()).len();
+        let start = index.resume_at(&name, len);
+        if start == len {
+            continue;
+        }
+        try!(input.seek(SeekFrom::Start(start)));
+        try!(index.update(ctx, &name, start, &mut BufReader::new(input), &mut *decoder));
+    }
+
+    let obsolete = try!(index.commit(&dir));
+
+    // keep the old index intact until the new one is completely written
+    let tmp = dir.0.join("index.tmp");
+    {
+        let mut out = BufWriter::new(try!(File::create(&tmp)));
+        try!(index.save(&mut out));
+        try!(out.flush());
+    }
+    try!(fs::rename(&tmp, dir.index()));
+    for segment in obsolete {
+        try!(fs::remove_file(dir.segment(segment)));
+    }
+    info!("{} events of {} files indexed", index.len(), index.file_count());
+    Ok(())
+}
+
+/// Look up `query` in the index in the directory `path`, and write the events found, prefixed
+/// with their file and byte offset, `file:offset:`.
+pub fn search(args: &ArgMatches, ctx: &Context, path: &str, query: &str) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let dir = Dir(Path::new(path));
+    let index = try!(dir.load());
+    let query = Query::parse(index.casemapping(), query)
+                    .unwrap_or_else(|e| die(&format!("Can't make sense of the query: {}", e)));
+    let limit = value_or(args, "limit", usize::max_value());
+    let hits = try!(index.search(&dir, &query, e.time(ctx, "since"), e.time(ctx, "until")));
+
+    let decoder = e.whole_decoder();
+    let encoder = e.encoder();
+    let mut out = e.output();
+    for hit in hits.into_iter().take(limit) {
+        let mut input = try!(File::open(hit.path));
+        try!(input.seek(SeekFrom::Start(hit.offset)));
+        let mut input = BufReader::new(input);
+        let event = match decoder.decode(ctx, &mut input).next() {
+            Some(event) => try!(event),
+            None => continue,
+        };
+        try!(write!(&mut out, "{}:{}:", hit.path, hit.offset));
+        try!(encoder.encode(ctx, &mut out, &event));
+    }
+    Ok(())
+}
@@ -2,6 +2,9 @@ extern crate ilc_base;
 extern crate ilc_ops;
 extern crate ilc_format_weechat;
 extern crate ilc_format_energymech;
+extern crate ilc_format_sqlite;
+extern crate ilc_format_quassel;
+extern crate ilc_format_thelounge;
 extern crate chrono;
 #[macro_use]
 extern crate clap;
@@ -12,15 +15,27 @@ extern crate serde;
 extern crate serde_json;
 extern crate glob;
 extern crate regex;
+extern crate atty;
+extern crate includedir;
+extern crate phf;
 
-use ilc_base::{Context, Decode, Encode};
+use ilc_base::{CaseMapping, Context, Decode, Encode, Policy};
 use ilc_ops::convert::{Filter, Operator, Subject};
+use ilc_ops::grep::{Field, Matcher};
+use ilc_ops::identity::Identities;
+use ilc_ops::stats::Bucket;
+use ilc_ops::timeindex::Within;
+use ilc_ops::words::Stopwords;
 use ilc_format_weechat::Weechat;
 use ilc_format_energymech::Energymech;
+use ilc_format_sqlite::Sqlite;
+use ilc_format_quassel::Quassel;
+use ilc_format_thelounge::TheLounge;
 
 use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
 
-use chrono::{FixedOffset, NaiveDate};
+use chrono::{FixedOffset, NaiveDate, NaiveDateTime};
+use chrono::offset::TimeZone;
 
 use glob::glob;
 
@@ -30,13 +45,27 @@ use std::str::FromStr;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Take, Write};
 use std::process;
 use std::error::Error;
 
 
+mod audit;
 mod chain;
 mod stats;
+mod report;
+mod members;
+mod modes;
+mod sessions;
+mod topics;
+mod words;
+mod urls;
+mod graph;
+mod grep;
+mod index;
+mod timeindex;
+
+include!(concat!(env!("OUT_DIR"), "/data.rs"));
 
 pub struct Cli {
     pub version: String,
@@ -122,6 +151,91 @@ pub fn main(cli: Cli) {
                             .takes_value(true)
                             .long("output")
                             .short("o"))
+                   .arg(Arg::with_name("degrade")
+                            .help("What to do with events the output format can't represent: \
+                                   abort, skip them, or write them with placeholders \
+                                   (default: fallback)")
+                            .global(true)
+                            .takes_value(true)
+                            .possible_values(&["fail", "skip", "fallback"])
+                            .long("degrade"))
+                   .arg(Arg::with_name("casemapping")
+                            .help("How the network compares nicks and channels, see CASEMAPPING \
+                                   in RPL_ISUPPORT (default: rfc1459)")
+                            .global(true)
+                            .takes_value(true)
+                            .possible_values(&["ascii", "rfc1459", "strict-rfc1459"])
+                            .long("casemapping"))
+                   .arg(Arg::with_name("threads")
+                            .help("Work on this many input files at once, where the command \
+                                   supports it (default: 1)")
+                            .global(true)
+                            .takes_value(true)
+                            .long("threads"))
+                   .arg(Arg::with_name("since")
+                            .help("Leave out events before this time, a Unix timestamp, or \
+                                   YYYY-MM-DD[ HH:MM[:SS]] in the output timezone. Input files \
+                                   with a time index are read from close before it")
+                            .global(true)
+                            .takes_value(true)
+                            .long("since"))
+                   .arg(Arg::with_name("until")
+                            .help("Leave out events after this time, like --since. Input \
+                                   files with a time index are read until close after it")
+                            .global(true)
+                            .takes_value(true)
+                            .long("until"))
+                   .arg(Arg::with_name("aliases")
+                            .help("A file listing nicks of the same person on each line, \
+                                   canonical name first")
+                            .global(true)
+                            .takes_value(true)
+                            .long("aliases"))
+                   .arg(Arg::with_name("merge_masks")
+                            .help("Consider nicks that join with the same user@host the same \
+                                   person, which is wrong for shared bouncers and web clients")
+                            .global(true)
+                            .long("merge-masks"))
+                   .arg(Arg::with_name("energymech_timestamp")
+                            .help("Layout of Energymech/ZNC timestamps, without brackets, \
+                                   e.g. \"%Y-%m-%d %H:%M:%S\" (default: %H:%M:%S)")
+                            .global(true)
+                            .takes_value(true)
+                            .long("energymech-timestamp"))
+                   .arg(Arg::with_name("weechat_prefix_join")
+                            .help("Weechat prefix for joins (weechat.look.prefix_join)")
+                            .global(true)
+                            .takes_value(true)
+                            .long("weechat-prefix-join"))
+                   .arg(Arg::with_name("weechat_prefix_quit")
+                            .help("Weechat prefix for parts, quits and kicks \
+                                   (weechat.look.prefix_quit)")
+                            .global(true)
+                            .takes_value(true)
+                            .long("weechat-prefix-quit"))
+                   .arg(Arg::with_name("weechat_prefix_network")
+                            .help("Weechat prefix for server messages (weechat.look.prefix_network)")
+                            .global(true)
+                            .takes_value(true)
+                            .long("weechat-prefix-network"))
+                   .arg(Arg::with_name("weechat_prefix_action")
+                            .help("Weechat prefix for actions (weechat.look.prefix_action)")
+                            .global(true)
+                            .takes_value(true)
+                            .long("weechat-prefix-action"))
+                   .arg(Arg::with_name("sqlite_query")
+                            .help("Only read the events matching this SQL condition on the `log` \
+                                   view from an SQLite database, e.g. \"nick = 'foo'\"")
+                            .global(true)
+                            .takes_value(true)
+                            .long("query"))
+                   .arg(Arg::with_name("network")
+                            .help("The network events written to an SQLite database are from, \
+                                   or the only one read from a Quassel database, or by its id \
+                                   from one of The Lounge")
+                            .global(true)
+                            .takes_value(true)
+                            .long("network"))
                    .arg(Arg::with_name("notice")
                             .help("Print all the notices/licenses")
                             .takes_value(false)
@@ -159,6 +273,270 @@ pub fn main(cli: Cli) {
                                                                                  "op_matches"])))
                    .subcommand(SubCommand::with_name("stats")
                                    .about("Analyse the activity of users by certain metrics")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("resolve")
+                                            .help("Count nicks of the same person as one")
+                                            .long("resolve"))
+                                   .arg(Arg::with_name("html")
+                                            .help("Render an HTML report instead of JSON")
+                                            .long("html"))
+                                   .arg(Arg::with_name("count")
+                                            .help("How many nicks the HTML report lists \
+                                                   (default: 25)")
+                                            .takes_value(true)
+                                            .long("count"))
+                                   .arg(Arg::with_name("bucket")
+                                            .help("Count activity over time instead, per \
+                                                   channel and nick, in buckets of this size")
+                                            .takes_value(true)
+                                            .possible_values(&["day", "week", "month"])
+                                            .conflicts_with("html")
+                                            .long("bucket"))
+                                   .arg(Arg::with_name("csv")
+                                            .help("Write the series as CSV instead of JSON")
+                                            .requires("bucket")
+                                            .long("csv"))
+                                   .arg(Arg::with_name("state")
+                                            .help("Add the input to the statistics saved in \
+                                                   this file, which is created if needed, \
+                                                   and report on all of them")
+                                            .takes_value(true)
+                                            .long("state")))
+                   .subcommand(SubCommand::with_name("words")
+                                   .about("Reports the most used words and vocabulary of nicks")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("count")
+                                            .help("How many words to list (default: 10)")
+                                            .takes_value(true)
+                                            .long("count"))
+                                   .arg(Arg::with_name("stopwords")
+                                            .help("A file of words to leave out of the top \
+                                                   words, one per line, instead of the \
+                                                   built-in English ones")
+                                            .takes_value(true)
+                                            .long("stopwords"))
+                                   .arg(Arg::with_name("resolve")
+                                            .help("Count nicks of the same person as one")
+                                            .long("resolve")))
+                   .subcommand(SubCommand::with_name("urls")
+                                   .about("Extracts the URLs posted in a log")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("summary")
+                                            .help("Count URLs per domain, nick and URL instead \
+                                                   of listing them")
+                                            .long("summary"))
+                                   .arg(Arg::with_name("csv")
+                                            .help("Write CSV instead of JSON")
+                                            .long("csv"))
+                                   .arg(Arg::with_name("resolve")
+                                            .help("Use the canonical name of each person")
+                                            .long("resolve")))
+                   .subcommand(SubCommand::with_name("sessions")
+                                   .about("Estimates how long nicks stay in channels, from \
+                                           joins, parts, quits and kicks")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("csv")
+                                            .help("Write CSV instead of JSON")
+                                            .long("csv"))
+                                   .arg(Arg::with_name("resolve")
+                                            .help("Count nicks of the same person as one")
+                                            .long("resolve")))
+                   .subcommand(SubCommand::with_name("members")
+                                   .about("Lists who was in each channel at a given time")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("at")
+                                            .help("A Unix timestamp, or YYYY-MM-DD[ HH:MM[:SS]] \
+                                                   in the output timezone")
+                                            .takes_value(true)
+                                            .required_unless("population")
+                                            .long("at"))
+                                   .arg(Arg::with_name("population")
+                                            .help("Write the number of members over time as \
+                                                   CSV instead")
+                                            .conflicts_with("at")
+                                            .long("population")))
+                   .subcommand(SubCommand::with_name("modes")
+                                   .about("Lists the modes of each channel at a given time")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("at")
+                                            .help("A Unix timestamp, or YYYY-MM-DD[ HH:MM[:SS]] \
+                                                   in the output timezone")
+                                            .takes_value(true)
+                                            .required_unless("bans")
+                                            .long("at"))
+                                   .arg(Arg::with_name("bans")
+                                            .help("Write every ban, exception and other list \
+                                                   entry, when it was set and removed, as CSV \
+                                                   instead")
+                                            .conflicts_with("at")
+                                            .long("bans"))
+                                   .arg(Arg::with_name("chanmodes")
+                                            .help("The channel modes of the server, as in \
+                                                   CHANMODES (default: beI,k,l,imnpst)")
+                                            .takes_value(true)
+                                            .long("chanmodes"))
+                                   .arg(Arg::with_name("prefix")
+                                            .help("The member modes of the server, as in PREFIX \
+                                                   (default: (qaohv)~&@%+)")
+                                            .takes_value(true)
+                                            .long("prefix")))
+                   .subcommand(SubCommand::with_name("audit")
+                                   .about("Reports kicks, bans, quiets, op changes and topic \
+                                           changes by operator")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("json")
+                                            .help("Write JSON instead of a table")
+                                            .long("json"))
+                                   .arg(Arg::with_name("chanmodes")
+                                            .help("The channel modes of the server, as in \
+                                                   CHANMODES (default: beI,k,l,imnpst)")
+                                            .takes_value(true)
+                                            .long("chanmodes"))
+                                   .arg(Arg::with_name("prefix")
+                                            .help("The member modes of the server, as in PREFIX \
+                                                   (default: (qaohv)~&@%+)")
+                                            .takes_value(true)
+                                            .long("prefix")))
+                   .subcommand(SubCommand::with_name("topics")
+                                   .about("Lists the topics of each channel over time")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("json")
+                                            .help("Write JSON instead of text")
+                                            .long("json"))
+                                   .arg(Arg::with_name("html")
+                                            .help("Render an HTML page instead of text")
+                                            .conflicts_with("json")
+                                            .long("html")))
+                   .subcommand(SubCommand::with_name("graph")
+                                   .about("Exports who talks to whom as a weighted graph")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("export")
+                                            .help("The graph format (default: dot)")
+                                            .takes_value(true)
+                                            .possible_values(&["dot", "graphml", "json"])
+                                            .long("export"))
+                                   .arg(Arg::with_name("min_weight")
+                                            .help("Leave out edges with fewer interactions")
+                                            .takes_value(true)
+                                            .long("min-weight"))
+                                   .arg(Arg::with_name("resolve")
+                                            .help("Make nicks of the same person one node")
+                                            .long("resolve")))
+                   .subcommand(SubCommand::with_name("seen")
+                                   .about("Shows the last event of a nick")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("nick").required(true).index(1))
+                                   .arg(Arg::with_name("resolve")
+                                            .help("Include other nicks of the same person")
+                                            .long("resolve")))
+                   .subcommand(SubCommand::with_name("grep")
+                                   .about("Searches the input for events matching a pattern")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("pattern").required(true).index(1))
+                                   .arg(Arg::with_name("fixed")
+                                            .help("Match the pattern literally, rather than as \
+                                                   a regular expression")
+                                            .short("F")
+                                            .long("fixed-strings"))
+                                   .arg(Arg::with_name("ignore_case")
+                                            .help("Ignore case distinctions")
+                                            .long("ignore-case"))
+                                   .arg(Arg::with_name("invert")
+                                            .help("Select the events that don't match")
+                                            .short("v")
+                                            .long("invert-match"))
+                                   .arg(Arg::with_name("field")
+                                            .help("Only search this field, can be given \
+                                                   more than once (default: all)")
+                                            .takes_value(true)
+                                            .multiple(true)
+                                            .number_of_values(1)
+                                            .possible_values(&["nick", "text", "mask"])
+                                            .long("field"))
+                                   .arg(Arg::with_name("after")
+                                            .help("How many events to show after each match")
+                                            .takes_value(true)
+                                            .short("A")
+                                            .long("after-context"))
+                                   .arg(Arg::with_name("before")
+                                            .help("How many events to show before each match")
+                                            .takes_value(true)
+                                            .short("B")
+                                            .long("before-context"))
+                                   .arg(Arg::with_name("context")
+                                            .help("How many events to show before and after each \
+                                                   match")
+                                            .takes_value(true)
+                                            .short("C")
+                                            .long("context"))
+                                   .arg(Arg::with_name("provenance")
+                                            .help("Prefix events with the file and line they \
+                                                   come from")
+                                            .short("n")
+                                            .long("provenance"))
+                                   .arg(Arg::with_name("color")
+                                            .help("Whether to highlight matches (default: auto, \
+                                                   on terminals)")
+                                            .takes_value(true)
+                                            .possible_values(&["auto", "always", "never"])
+                                            .long("color")))
+                   .subcommand(SubCommand::with_name("index")
+                                   .about("Builds and searches a full-text index of log files")
+                                   .setting(AppSettings::SubcommandRequiredElseHelp)
+                                   .subcommand(SubCommand::with_name("build")
+                                                   .about("Adds new events of the input files \
+                                                           to the index")
+                                                   .setting(AppSettings::AllowLeadingHyphen)
+                                                   .arg(Arg::with_name("index")
+                                                            .help("The index directory, \
+                                                                   created if needed")
+                                                            .takes_value(true)
+                                                            .required(true)
+                                                            .long("index")))
+                                   .subcommand(SubCommand::with_name("search")
+                                                   .about("Finds events in the index. Words \
+                                                           and \"phrases\" all have to match, \
+                                                           unless prefixed with - or NOT, OR \
+                                                           separates alternatives, and nick:, \
+                                                           channel: and type: search fields")
+                                                   .setting(AppSettings::AllowLeadingHyphen)
+                                                   .arg(Arg::with_name("query")
+                                                            .required(true)
+                                                            .index(1))
+                                                   .arg(Arg::with_name("index")
+                                                            .help("The index directory")
+                                                            .takes_value(true)
+                                                            .required(true)
+                                                            .long("index"))
+                                                   .arg(Arg::with_name("limit")
+                                                            .help("List at most this many events")
+                                                            .takes_value(true)
+                                                            .long("limit"))))
+                   .subcommand(SubCommand::with_name("index-time")
+                                   .about("Writes a time index next to each input file, so that \
+                                           --since and --until can skip to the part they select. \
+                                           The files have to be sorted by time")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("every")
+                                            .help("Bytes between checkpoints (default: 262144)")
+                                            .takes_value(true)
+                                            .long("every")))
+                   .subcommand(SubCommand::with_name("mentions")
+                                   .about("Finds the messages that mention a nick, with the \
+                                           events around them")
+                                   .setting(AppSettings::AllowLeadingHyphen)
+                                   .arg(Arg::with_name("nick").required(true).index(1))
+                                   .arg(Arg::with_name("context")
+                                            .help("How many events of the same channel to show \
+                                                   before and after each mention (default: 2)")
+                                            .takes_value(true)
+                                            .short("C")
+                                            .long("context"))
+                                   .arg(Arg::with_name("resolve")
+                                            .help("Include other nicks of the same person")
+                                            .long("resolve")))
+                   .subcommand(SubCommand::with_name("aliases")
+                                   .about("Lists the nicks that belong to the same person")
                                    .setting(AppSettings::AllowLeadingHyphen))
                    .subcommand(SubCommand::with_name("sort")
                                    .about("Sorts a log by time")
@@ -180,7 +558,11 @@ pub fn main(cli: Cli) {
     let res = match args.subcommand() {
         ("parse", Some(args)) => {
             let e = Environment(&args);
-            ilc_ops::parse::parse(&e.context(), &mut e.input(), &mut *e.decoder())
+            if e.parallel() {
+                ilc_ops::parse::parse_files(&e.context(), e.inputs(), e.decoder(), e.threads())
+            } else {
+                ilc_ops::parse::parse(&e.context(), &mut e.input(), &mut *e.decoder())
+            }
         }
         ("convert", Some(args)) => {
             let e = Environment(&args);
@@ -228,36 +610,360 @@ pub fn main(cli: Cli) {
 
             let filter = subject.and_then(|s| op.map(|o| Filter(s, o)));
 
-            ilc_ops::convert::convert(&e.context(),
-                                      &mut e.input(),
-                                      &mut *e.decoder(),
-                                      &mut *e.output(),
-                                      &*e.encoder(),
-                                      filter,
-                                      args.is_present("op_not"))
+            let ctx = e.context();
+            // the database must not be truncated, and the encoder writes into it directly
+            let mut output = if is_database(&args) {
+                Box::new(io::sink())
+            } else {
+                e.output()
+            };
+            let res = if e.parallel() {
+                ilc_ops::convert::convert_files(&ctx,
+                                                e.inputs(),
+                                                e.decoder(),
+                                                &mut *output,
+                                                e.encoder(),
+                                                filter,
+                                                args.is_present("op_not"),
+                                                e.threads())
+            } else {
+                ilc_ops::convert::convert(&ctx,
+                                          &mut e.input(),
+                                          &mut *e.decoder(),
+                                          &mut *output,
+                                          &*e.encoder(),
+                                          filter,
+                                          args.is_present("op_not"))
+            };
+            report_losses(&ctx);
+            res
         }
         ("stats", Some(args)) => {
             let e = Environment(&args);
-            let stats = ilc_ops::stats::stats(&e.context(), &mut e.input(), &mut *e.decoder())
+            let ctx = e.context();
+            let state = args.value_of("state").map(|path| {
+                stats::update_state(&e, &ctx, path).unwrap_or_else(|e| error(Box::new(e)))
+            });
+            if let Some(bucket) = args.value_of("bucket").and_then(|b| Bucket::from_str(b).ok()) {
+                let series = match state {
+                    Some(state) => {
+                        let series = state.daily.rebucket(bucket);
+                        if e.resolving() {
+                            series.resolve(&ctx, &e.load_aliases(state.identities))
+                        } else {
+                            series
+                        }
+                    }
+                    None if e.parallel() => {
+                        let mut ids = e.identities(&ctx);
+                        ilc_ops::stats::series_files(&ctx,
+                                                     e.inputs(),
+                                                     e.decoder(),
+                                                     bucket,
+                                                     e.threads(),
+                                                     ids.as_mut())
+                            .unwrap_or_else(|e| error(Box::new(e)))
+                    }
+                    None => {
+                        let mut ids = e.identities(&ctx);
+                        ilc_ops::stats::series(&ctx,
+                                               &mut e.input(),
+                                               &mut *e.decoder(),
+                                               bucket,
+                                               ids.as_mut())
+                            .unwrap_or_else(|e| error(Box::new(e)))
+                    }
+                };
+                if args.is_present("csv") {
+                    stats::output_series_as_csv(&args, series)
+                } else {
+                    stats::output_as_json(&args, &cli, series)
+                }
+            } else {
+                let stats = match state {
+                    Some(state) => {
+                        if e.resolving() {
+                            state.stats.resolve(&e.load_aliases(state.identities))
+                        } else {
+                            state.stats
+                        }
+                    }
+                    None if e.parallel() => {
+                        let mut ids = e.identities(&ctx);
+                        ilc_ops::stats::stats_files(&ctx,
+                                                    e.inputs(),
+                                                    e.decoder(),
+                                                    e.threads(),
+                                                    ids.as_mut())
+                            .unwrap_or_else(|e| error(Box::new(e)))
+                    }
+                    None => {
+                        let mut ids = e.identities(&ctx);
+                        ilc_ops::stats::stats(&ctx, &mut e.input(), &mut *e.decoder(), ids.as_mut())
+                            .unwrap_or_else(|e| error(Box::new(e)))
+                    }
+                };
+                if args.is_present("html") {
+                    report::output_as_html(&args, &cli, &ctx, stats)
+                } else {
+                    stats::output_as_json(&args, &cli, stats)
+                }
+            }
+        }
+        ("words", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let mut ids = e.identities(&ctx);
+            let stopwords = match args.value_of("stopwords") {
+                Some(path) => {
+                    let file = File::open(path).unwrap_or_else(|e| error(Box::new(e)));
+                    Stopwords::read(&mut BufReader::new(file))
+                        .unwrap_or_else(|e| error(Box::new(e)))
+                }
+                None => Stopwords::default(),
+            };
+            ilc_ops::words::vocabulary(&ctx, &mut e.input(), &mut *e.decoder(), ids.as_mut())
+                .and_then(|vocabulary| words::output(&args, &vocabulary, &stopwords))
+        }
+        ("urls", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let mut ids = e.identities(&ctx);
+            let links = ilc_ops::urls::links(&ctx, &mut e.input(), &mut *e.decoder(), ids.as_mut())
                             .unwrap_or_else(|e| error(Box::new(e)));
-
-            stats::output_as_json(&args, &cli, stats)
+            match (args.is_present("summary"), args.is_present("csv")) {
+                (false, false) => stats::output_as_json(&args, &cli, links),
+                (false, true) => urls::output_links_as_csv(&args, &ctx, &links),
+                (true, false) => {
+                    stats::output_as_json(&args, &cli, ilc_ops::urls::summary(&ctx, &links))
+                }
+                (true, true) => {
+                    urls::output_summary_as_csv(&args, &ctx, &ilc_ops::urls::summary(&ctx, &links))
+                }
+            }
+        }
+        ("sessions", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let mut ids = e.identities(&ctx);
+            let nicks = ilc_ops::sessions::sessions(&ctx,
+                                                    &mut e.input(),
+                                                    &mut *e.decoder(),
+                                                    ids.as_mut())
+                            .unwrap_or_else(|e| error(Box::new(e)));
+            if args.is_present("csv") {
+                sessions::output_as_csv(&args, &nicks)
+            } else {
+                stats::output_as_json(&args, &cli, nicks)
+            }
+        }
+        ("members", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            match e.time(&ctx, "at") {
+                Some(time) => {
+                    ilc_ops::members::members_at(&ctx, &mut e.input(), &mut *e.decoder(), time)
+                        .and_then(|members| members::output(&args, &members))
+                }
+                None => {
+                    ilc_ops::members::population(&ctx, &mut e.input(), &mut *e.decoder())
+                        .and_then(|population| {
+                            members::output_population_as_csv(&args, &ctx, &population)
+                        })
+                }
+            }
+        }
+        ("modes", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let spec = modes::spec(&args);
+            match e.time(&ctx, "at") {
+                Some(time) => {
+                    ilc_ops::modes::modes_at(&ctx, &mut e.input(), &mut *e.decoder(), spec, time)
+                        .and_then(|m| modes::output(&args, &ctx, &m))
+                }
+                None => {
+                    ilc_ops::modes::list_history(&ctx, &mut e.input(), &mut *e.decoder(), spec)
+                        .and_then(|history| modes::output_history_as_csv(&args, &ctx, &history))
+                }
+            }
+        }
+        ("audit", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let spec = modes::spec(&args);
+            let (since, until) = (e.time(&ctx, "since"), e.time(&ctx, "until"));
+            // bans that were set before the range still need their duration
+            let operators = ilc_ops::audit::audit(&ctx,
+                                                  &mut e.whole_input(),
+                                                  &mut *e.whole_decoder(),
+                                                  &spec,
+                                                  since,
+                                                  until)
+                                .unwrap_or_else(|e| error(Box::new(e)));
+            if args.is_present("json") {
+                stats::output_as_json(&args, &cli, operators)
+            } else {
+                audit::output(&args, &ctx, &operators)
+            }
+        }
+        ("topics", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let channels = ilc_ops::topics::topics(&ctx, &mut e.input(), &mut *e.decoder())
+                               .unwrap_or_else(|e| error(Box::new(e)));
+            if args.is_present("json") {
+                stats::output_as_json(&args, &cli, channels)
+            } else if args.is_present("html") {
+                topics::output_as_html(&args, &cli, &ctx, &channels)
+            } else {
+                topics::output(&args, &ctx, &channels)
+            }
+        }
+        ("graph", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let mut ids = e.identities(&ctx);
+            let min_weight = value_or(args, "min_weight", 1u32);
+            let graph = ilc_ops::interactions::interactions(&ctx,
+                                                            &mut e.input(),
+                                                            &mut *e.decoder(),
+                                                            min_weight,
+                                                            ids.as_mut())
+                            .unwrap_or_else(|e| error(Box::new(e)));
+            match args.value_of("export") {
+                Some("graphml") => graph::output_as_graphml(&args, &graph),
+                Some("json") => stats::output_as_json(&args, &cli, graph),
+                _ => graph::output_as_dot(&args, &graph),
+            }
+        }
+        ("seen", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let nick = args.value_of("nick").unwrap();
+            let mut ids = e.identities(&ctx);
+            match ilc_ops::seen::seen(&ctx, &mut e.input(), &mut *e.decoder(), nick, ids.as_mut()) {
+                Ok(Some(event)) => {
+                    let res = e.encoder().encode(&ctx, &mut *e.output(), &event);
+                    report_losses(&ctx);
+                    res
+                }
+                Ok(None) => die(&format!("{} hasn't been seen", nick)),
+                Err(e) => Err(e),
+            }
+        }
+        ("grep", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let fields = args.values_of("field")
+                             .map(|fields| {
+                                 fields.map(|f| {
+                                           match f {
+                                               "nick" => Field::Nick,
+                                               "text" => Field::Text,
+                                               _ => Field::Mask,
+                                           }
+                                       })
+                                       .collect()
+                             })
+                             .unwrap_or_default();
+            let matcher = Matcher::new(args.value_of("pattern").unwrap(),
+                                       args.is_present("fixed"),
+                                       args.is_present("ignore_case"),
+                                       fields,
+                                       args.is_present("invert"))
+                              .unwrap_or_else(|e| error(Box::new(e)));
+            let context = value_or(args, "context", 0);
+            let before = value_or(args, "before", context);
+            let after = value_or(args, "after", context);
+            let res = grep::output(&args, &ctx, &matcher, before, after);
+            report_losses(&ctx);
+            res
+        }
+        ("index", Some(args)) => {
+            match args.subcommand() {
+                ("build", Some(args)) => {
+                    let ctx = Environment(&args).context();
+                    index::build(&args, &ctx, args.value_of("index").unwrap())
+                }
+                ("search", Some(args)) => {
+                    let ctx = Environment(&args).context();
+                    index::search(&args,
+                                  &ctx,
+                                  args.value_of("index").unwrap(),
+                                  args.value_of("query").unwrap())
+                }
+                _ => unreachable!(),
+            }
+        }
+        ("index-time", Some(args)) => {
+            let ctx = Environment(&args).context();
+            let every = value_or(args, "every", 262144u64);
+            timeindex::build(&args, &ctx, every)
+        }
+        ("mentions", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let nick = args.value_of("nick").unwrap();
+            let context = value_or(args, "context", 2usize);
+            let mut ids = e.identities(&ctx);
+            ilc_ops::mentions::mentions(&ctx,
+                                        &mut e.input(),
+                                        &mut *e.decoder(),
+                                        nick,
+                                        context,
+                                        ids.as_mut())
+                .and_then(|excerpts| {
+                    let encoder = e.encoder();
+                    let mut out = e.output();
+                    for (i, excerpt) in excerpts.iter().enumerate() {
+                        // like grep, separate excerpts only if they have context
+                        if i > 0 && context > 0 {
+                            try!(writeln!(&mut out, "--"));
+                        }
+                        for &(ref event, _) in &excerpt.events {
+                            try!(encoder.encode(&ctx, &mut out, event));
+                        }
+                    }
+                    report_losses(&ctx);
+                    Ok(())
+                })
+        }
+        ("aliases", Some(args)) => {
+            let e = Environment(&args);
+            let ctx = e.context();
+            let ids = Identities::new(ctx.casemapping);
+            let ids = e.load_aliases(ids);
+            ilc_ops::identity::identities(&ctx, &mut e.input(), &mut *e.decoder(), ids)
+                .and_then(|ids| {
+                    let mut out = e.output();
+                    for (person, nicks) in ids.groups() {
+                        try!(writeln!(&mut out, "{}: {}", person, nicks.join(" ")));
+                    }
+                    Ok(())
+                })
         }
         ("sort", Some(args)) => {
             let e = Environment(&args);
-            ilc_ops::sort::sort(&e.context(),
-                                &mut e.input(),
-                                &mut *e.decoder(),
-                                &mut *e.output(),
-                                &*e.encoder())
+            let ctx = e.context();
+            let res = ilc_ops::sort::sort(&ctx,
+                                          &mut e.input(),
+                                          &mut *e.decoder(),
+                                          &mut *e.output(),
+                                          &*e.encoder());
+            report_losses(&ctx);
+            res
         }
         ("dedup", Some(args)) => {
             let e = Environment(&args);
-            ilc_ops::dedup::dedup(&e.context(),
-                                  &mut e.input(),
-                                  &mut *e.decoder(),
-                                  &mut *e.output(),
-                                  &*e.encoder())
+            let ctx = e.context();
+            let res = ilc_ops::dedup::dedup(&ctx,
+                                            &mut e.input(),
+                                            &mut *e.decoder(),
+                                            &mut *e.output(),
+                                            &*e.encoder());
+            report_losses(&ctx);
+            res
         }
         ("merge", Some(args)) => {
             let e = Environment(&args);
@@ -266,11 +972,14 @@ pub fn main(cli: Cli) {
             let borrowed_inputs = inputs.iter_mut()
                                         .map(|a| a as &mut BufRead)
                                         .collect();
-            ilc_ops::merge::merge(&e.context(),
-                                  borrowed_inputs,
-                                  &mut *e.decoder(),
-                                  &mut *e.output(),
-                                  &*e.encoder())
+            let ctx = e.context();
+            let res = ilc_ops::merge::merge(&ctx,
+                                            borrowed_inputs,
+                                            &mut *e.decoder(),
+                                            &mut *e.output(),
+                                            &*e.encoder());
+            report_losses(&ctx);
+            res
         }
         (sc, _) if !sc.is_empty() => panic!("Unimplemented subcommand `{}`, this is a bug", sc),
         _ => die("No command specified"),
@@ -292,6 +1001,44 @@ pub fn error(e: Box<Error>) -> ! {
     process::exit(1)
 }
 
+/// Tell the user about events that didn't make it into the output unscathed.
+pub fn report_losses(ctx: &Context) {
+    for line in format!("{}", ctx.losses).lines() {
+        let _ = writeln!(&mut io::stderr(), "Warning: {}", line);
+    }
+}
+
+/// A point in time given on the command line, either as a Unix timestamp, or as
+/// `YYYY-MM-DD[ HH:MM[:SS]]` in the output timezone.
+pub fn parse_time(ctx: &Context, s: &str) -> Option<i64> {
+    if let Ok(t) = s.parse::<i64>() {
+        return Some(t);
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|d| d.and_hms(0, 0, 0));
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .filter_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .next()
+        .or(date.ok())
+        .and_then(|t| ctx.timezone_out.from_local_datetime(&t).single())
+        .map(|t| t.timestamp())
+}
+
+/// A time for people to read, as `YYYY-MM-DD HH:MM:SS` in the output timezone.
+pub fn format_time(ctx: &Context, time: i64) -> String {
+    ctx.timezone_out.timestamp(time, 0).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// The value of an optional argument, or `default` without one. An invalid value ends the
+/// program with clap's usual message.
+pub fn value_or<T: FromStr>(args: &ArgMatches, name: &str, default: T) -> T {
+    match value_t!(args, name, T) {
+        Ok(value) => value,
+        Err(ref e) if e.kind == clap::ErrorKind::ArgumentNotFound => default,
+        Err(e) => e.exit(),
+    }
+}
+
 pub fn die(s: &str) -> ! {
     let _ = writeln!(&mut io::stderr(), "Aborting: {}", s);
     process::exit(1)
@@ -312,10 +1059,13 @@ macro_rules! error {
     }};
 }
 
-pub fn decoder(format: &str) -> Option<Box<Decode>> {
+pub fn decoder(format: &str, args: &ArgMatches) -> Option<Box<Decode + Send + Sync>> {
     match format {
-        "energymech" | "em" => Some(Box::new(Energymech)),
-        "weechat" | "w" => Some(Box::new(Weechat)),
+        "energymech" | "em" => Some(Box::new(energymech(args))),
+        "weechat" | "w" => Some(Box::new(weechat(args))),
+        "sqlite" => Some(Box::new(sqlite_in(args))),
+        "quassel" => Some(Box::new(quassel(args))),
+        "thelounge" => Some(Box::new(thelounge(args))),
         // "irssi" => Some(Box::new(irssi::Irssi)),
         // "binary" => Some(Box::new(Binary)),
         // "msgpack" => Some(Box::new(Msgpack)),
@@ -323,10 +1073,11 @@ pub fn decoder(format: &str) -> Option<Box<Decode>> {
     }
 }
 
-pub fn encoder(format: &str) -> Option<Box<Encode>> {
+pub fn encoder(format: &str, args: &ArgMatches) -> Option<Box<Encode + Send + Sync>> {
     match format {
-        "energymech" | "em" => Some(Box::new(Energymech)),
-        "weechat" | "w" => Some(Box::new(Weechat)),
+        "energymech" | "em" => Some(Box::new(energymech(args))),
+        "weechat" | "w" => Some(Box::new(weechat(args))),
+        "sqlite" => Some(Box::new(sqlite_out(args))),
         // "irssi" => Some(Box::new(irssi::Irssi)),
         // "binary" => Some(Box::new(Binary)),
         // "msgpack" => Some(Box::new(Msgpack)),
@@ -334,23 +1085,101 @@ pub fn encoder(format: &str) -> Option<Box<Encode>> {
     }
 }
 
-pub fn force_decoder(s: Option<&str>) -> Box<Decode> {
+/// Whether the encoder writes into the output file by itself, instead of to a stream.
+fn is_database(args: &ArgMatches) -> bool {
+    args.value_of("format").or(args.value_of("output_format")) == Some("sqlite")
+}
+
+/// The database of the single input file.
+pub fn sqlite_in(args: &ArgMatches) -> Sqlite {
+    let files = gather_input(args);
+    if files.len() != 1 {
+        die("An SQLite database is read from exactly one input file");
+    }
+    Sqlite::open(&files[0], args.value_of("sqlite_query").map(str::to_owned))
+        .unwrap_or_else(|e| error(Box::new(e)))
+}
+
+/// The Quassel core database of the single input file. Reading several networks at once is
+/// warned about, as their channels are told apart by name only.
+pub fn quassel(args: &ArgMatches) -> Quassel {
+    let files = gather_input(args);
+    if files.len() != 1 {
+        die("A Quassel database is read from exactly one input file");
+    }
+    let network = args.value_of("network").map(str::to_owned);
+    let quassel = Quassel::open(&files[0], network.clone()).unwrap_or_else(|e| error(Box::new(e)));
+    if network.is_none() {
+        let networks = quassel.networks().unwrap_or_else(|e| error(Box::new(e)));
+        if networks.len() > 1 {
+            let _ = writeln!(&mut io::stderr(),
+                             "Warning: Channels of the same name on the networks {} are read as \
+                              one, choose one with --network",
+                             networks.join(", "));
+        }
+    }
+    quassel
+}
+
+/// The history database of The Lounge of the single input file.
+pub fn thelounge(args: &ArgMatches) -> TheLounge {
+    let files = gather_input(args);
+    if files.len() != 1 {
+        die("A database of The Lounge is read from exactly one input file");
+    }
+    TheLounge::open(&files[0], args.value_of("network").map(str::to_owned))
+        .unwrap_or_else(|e| error(Box::new(e)))
+}
+
+/// The database of the output file, which is created if needed.
+pub fn sqlite_out(args: &ArgMatches) -> Sqlite {
+    let path = args.value_of("output_file")
+                   .unwrap_or_else(|| die("An SQLite database can only be written to a file"));
+    Sqlite::create(path, args.value_of("network").map(str::to_owned))
+        .unwrap_or_else(|e| error(Box::new(e)))
+}
+
+pub fn energymech(args: &ArgMatches) -> Energymech {
+    match args.value_of("energymech_timestamp") {
+        Some(layout) => Energymech::with_timestamp(layout),
+        None => Energymech::default(),
+    }
+}
+
+pub fn weechat(args: &ArgMatches) -> Weechat {
+    let mut weechat = Weechat::default();
+    if let Some(p) = args.value_of("weechat_prefix_join") {
+        weechat.prefixes.join = p.to_owned();
+    }
+    if let Some(p) = args.value_of("weechat_prefix_quit") {
+        weechat.prefixes.quit = p.to_owned();
+    }
+    if let Some(p) = args.value_of("weechat_prefix_network") {
+        weechat.prefixes.network = p.to_owned();
+    }
+    if let Some(p) = args.value_of("weechat_prefix_action") {
+        weechat.prefixes.action = p.to_owned();
+    }
+    weechat
+}
+
+pub fn force_decoder(s: Option<&str>, args: &ArgMatches) -> Box<Decode + Send + Sync> {
     let inf = match s {
         Some(s) => s,
         None => die("You didn't specify the input format"),
     };
-    match decoder(&inf) {
+    match decoder(&inf, args) {
         Some(d) => d,
         None => error!(2, "The format `{}` is unknown to me", inf),
     }
 }
 
-pub fn force_encoder<'a>(s: Option<&str>) -> Box<Encode> {
+pub fn force_encoder<'a>(s: Option<&str>, args: &ArgMatches) -> Box<Encode + Send + Sync> {
     let outf = match s {
         Some(s) => s,
         None => die("You didn't specify the output format"),
     };
-    match encoder(&outf) {
+    match encoder(&outf, args) {
         Some(e) => e,
         None => error!(2, "The format `{}` is unknown to me", outf),
     }
@@ -363,32 +1192,109 @@ impl<'a> Environment<'a> {
         build_context(self.0)
     }
 
+    /// The input files one after the other, or stdin. Of files with a time index, only the part
+    /// between `--since` and `--until` is read.
     pub fn input(&self) -> Box<BufRead> {
+        let files = gather_input(self.0);
+        if files.is_empty() {
+            return open_files(files);
+        }
+        Box::new(BufReader::new(chain::Chain::new(files.iter()
+                                                       .map(|p| self.open(p))
+                                                       .collect())))
+    }
+
+    /// The input files from the start to the end, regardless of `--since` and `--until`.
+    pub fn whole_input(&self) -> Box<BufRead> {
         open_files(gather_input(self.0))
     }
 
-    pub fn inputs(&self) -> Vec<Box<BufRead>> {
+    pub fn inputs(&self) -> Vec<Box<BufRead + Send>> {
         gather_input(self.0)
             .iter()
-            .map(|path| {
-                Box::new(BufReader::new(File::open(path)
-                                            .unwrap_or_else(|e| {
-                                                error(Box::new(e))
-                                            }))) as Box<BufRead>
-            })
+            .map(|path| Box::new(BufReader::new(self.open(path))) as Box<BufRead + Send>)
             .collect()
     }
 
+    /// Open an input file, see `timeindex::open`.
+    pub fn open(&self, path: &Path) -> Take<File> {
+        let ctx = self.context();
+        let (since, until) = (self.time(&ctx, "since"), self.time(&ctx, "until"));
+        timeindex::open(&ctx, path, since, until).unwrap_or_else(|e| error(Box::new(e)))
+    }
+
+    /// The time given for the argument `name`, see `parse_time`.
+    pub fn time(&self, ctx: &Context, name: &str) -> Option<i64> {
+        self.0.value_of(name).map(|s| {
+            parse_time(ctx, s)
+                .unwrap_or_else(|| die(&format!("Can't make sense of the time `{}`", s)))
+        })
+    }
+
+    /// How many input files to work on at once.
+    pub fn threads(&self) -> usize {
+        value_or(self.0, "threads", 1)
+    }
+
+    /// Whether to read the input files in worker threads, instead of one after the other.
+    pub fn parallel(&self) -> bool {
+        self.threads() > 1 && gather_input(self.0).len() > 1
+    }
+
     pub fn output(&self) -> Box<Write> {
         open_output(self.0)
     }
 
-    pub fn decoder(&self) -> Box<Decode> {
-        force_decoder(self.0.value_of("format").or(self.0.value_of("input_format")))
+    /// Whether nicks should be resolved to people.
+    pub fn resolving(&self) -> bool {
+        self.0.is_present("resolve") || self.0.is_present("aliases")
+    }
+
+    /// Identity resolution, if asked for with `--resolve` or `--aliases`.
+    pub fn identities(&self, ctx: &Context) -> Option<Identities> {
+        if self.resolving() {
+            Some(self.load_aliases(Identities::new(ctx.casemapping)))
+        } else {
+            None
+        }
+    }
+
+    /// Add what `--aliases` and `--merge-masks` say about who is who to `ids`.
+    pub fn load_aliases(&self, mut ids: Identities) -> Identities {
+        if let Some(path) = self.0.value_of("aliases") {
+            let file = File::open(path).unwrap_or_else(|e| error(Box::new(e)));
+            ids.load_aliases(&mut BufReader::new(file)).unwrap_or_else(|e| error(Box::new(e)));
+        }
+        if self.0.is_present("merge_masks") {
+            ids.merge_masks();
+        }
+        ids
+    }
+
+    /// The input format, leaving out events outside `--since` and `--until`.
+    pub fn decoder(&self) -> Box<Decode + Send + Sync> {
+        let decoder = self.whole_decoder();
+        if self.0.is_present("since") || self.0.is_present("until") {
+            let ctx = self.context();
+            Box::new(Within {
+                inner: decoder,
+                since: self.time(&ctx, "since"),
+                until: self.time(&ctx, "until"),
+            })
+        } else {
+            decoder
+        }
+    }
+
+    /// The input format, regardless of `--since` and `--until`.
+    pub fn whole_decoder(&self) -> Box<Decode + Send + Sync> {
+        force_decoder(self.0.value_of("format").or(self.0.value_of("input_format")),
+                      self.0)
     }
 
-    pub fn encoder(&self) -> Box<Encode> {
-        force_encoder(self.0.value_of("format").or(self.0.value_of("output_format")))
+    pub fn encoder(&self) -> Box<Encode + Send + Sync> {
+        force_encoder(self.0.value_of("format").or(self.0.value_of("output_format")),
+                      self.0)
     }
 }
 
@@ -403,6 +1309,13 @@ pub fn build_context(args: &ArgMatches) -> Context {
                                             .unwrap_or(0)),
         override_date: args.value_of("date").and_then(|d| NaiveDate::from_str(&d).ok()),
         channel: args.value_of("channel").map(str::to_owned).clone(),
+        casemapping: args.value_of("casemapping")
+                         .and_then(|c| CaseMapping::from_str(c).ok())
+                         .unwrap_or_default(),
+        policy: args.value_of("degrade")
+                    .and_then(|p| Policy::from_str(p).ok())
+                    .unwrap_or_default(),
+        losses: Default::default(),
     };
     if args.is_present("infer_date") {
         let input_files = gather_input(args);
@@ -0,0 +1,46 @@
+use clap::ArgMatches;
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use ilc_base::{self, Context};
+use ilc_ops::members::Members;
+use stats::csv_field;
+use Environment;
+use format_time;
+
+/// One line per channel, with its number of members and their nicks.
+pub fn output(args: &ArgMatches, members: &Members) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    for channel in members.channels() {
+        try!(writeln!(&mut out,
+                      "{} ({}): {}",
+                      channel,
+                      members.count(channel),
+                      members.members(channel).join(" ")));
+    }
+    Ok(())
+}
+
+/// One row per change, ordered by channel and time.
+pub fn output_population_as_csv(args: &ArgMatches,
+                                ctx: &Context,
+                                population: &HashMap<String, Vec<(i64, usize)>>)
+                                -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    try!(writeln!(&mut out, "channel,time,members"));
+    let mut channels: Vec<_> = population.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+    for (channel, changes) in channels {
+        for &(time, n) in changes {
+            try!(writeln!(&mut out,
+                          "{},{},{}",
+                          csv_field(channel),
+                          format_time(ctx, time),
+                          n));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use clap::ArgMatches;
+
+use std::io::Write;
+
+use ilc_base::{self, Context};
+use ilc_ops::modes::{ListRecord, ModeSpec, Modes};
+use stats::csv_field;
+use Environment;
+use die;
+use format_time;
+
+/// The modes the server supports, from `--chanmodes` and `--prefix`, or the common defaults.
+pub fn spec(args: &ArgMatches) -> ModeSpec {
+    let default = ModeSpec::default();
+    let chanmodes = args.value_of("chanmodes").map(str::to_owned).unwrap_or_else(|| {
+        format!("{},{},{},{}",
+                default.lists,
+                default.always,
+                default.when_set,
+                default.flags)
+    });
+    let prefix = args.value_of("prefix").map(str::to_owned).unwrap_or_else(|| {
+        let (modes, symbols): (String, String) = default.prefixes.iter().cloned().unzip();
+        format!("({}){}", modes, symbols)
+    });
+    ModeSpec::parse(&chanmodes, &prefix).unwrap_or_else(|| {
+        die(&format!("Can't make sense of the modes `{}` and `{}`", chanmodes, prefix))
+    })
+}
+
+/// One line per channel with its flags, then one line per prefix mode with its holders, and one
+/// line per list entry.
+pub fn output(args: &ArgMatches, ctx: &Context, modes: &Modes) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    for name in modes.channels() {
+        let channel = modes.channel(name).unwrap();
+        try!(writeln!(&mut out, "{}: {}", name, channel.flag_string()));
+        for &(mode, _) in &modes.spec().prefixes {
+            let holders = match channel.prefixes.get(&mode) {
+                Some(h) if !h.is_empty() => h,
+                _ => continue,
+            };
+            let nicks: Vec<&str> = holders.values().map(|n| n.as_str()).collect();
+            try!(writeln!(&mut out, "  +{} {}", mode, nicks.join(" ")));
+        }
+        for (mode, list) in &channel.lists {
+            for entry in list.values() {
+                try!(write!(&mut out, "  +{} {}", mode, entry.mask));
+                if let Some(ref by) = entry.set_by {
+                    try!(write!(&mut out, " by {}", by));
+                }
+                if let Some(time) = entry.time {
+                    try!(write!(&mut out, " at {}", format_time(ctx, time)));
+                }
+                try!(writeln!(&mut out, ""));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One row per list entry, in the order they were set.
+pub fn output_history_as_csv(args: &ArgMatches,
+                             ctx: &Context,
+                             history: &[ListRecord])
+                             -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    try!(writeln!(&mut out, "channel,mode,mask,set_by,set,removed_by,removed"));
+    for r in history {
+        try!(writeln!(&mut out,
+                      "{},{},{},{},{},{},{}",
+                      csv_field(&r.channel),
+                      r.mode,
+                      csv_field(&r.mask),
+                      csv_field(r.set_by.as_ref().map_or("", |s| s)),
+                      r.set.map(|t| format_time(ctx, t)).unwrap_or_default(),
+                      csv_field(r.removed_by.as_ref().map_or("", |s| s)),
+                      r.removed.map(|t| format_time(ctx, t)).unwrap_or_default()));
+    }
+    Ok(())
+}
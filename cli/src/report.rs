@@ -0,0 +1,297 @@
+//! pisg-style HTML channel statistics
+use clap::ArgMatches;
+
+use chrono::Local;
+
+use std::cmp;
+use std::io::Write;
+
+use ilc_base::{self, Context};
+use ilc_ops::stats::{NickStat, Stats};
+use Environment;
+use Cli;
+use FILES;
+use format_time;
+use value_or;
+
+/// Words shorter than this are too common to be interesting.
+const MIN_WORD_LEN: usize = 5;
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn per_line(n: u32, lines: u32) -> f32 {
+    if lines == 0 { 0.0 } else { n as f32 / lines as f32 }
+}
+
+/// Sort by the counts in descending order, and by key for equal counts.
+fn top<'a, T, F>(items: T, count: usize, key: F) -> Vec<(&'a str, u32)>
+    where T: Iterator<Item = (&'a String, u32)>,
+          F: Fn(&str) -> bool
+{
+    let mut items: Vec<(&str, u32)> = items.filter(|&(k, _)| key(k))
+                                           .map(|(k, n)| (k.as_str(), n))
+                                           .collect();
+    items.sort_by(|&(ka, a), &(kb, b)| (b, ka).cmp(&(a, kb)));
+    items.truncate(count);
+    items
+}
+
+fn hours(stats: &Stats) -> String {
+    let mut hours = [0u32; 24];
+    for nick in stats.freqs.values() {
+        for (h, n) in nick.hours.iter().enumerate() {
+            hours[h] += *n;
+        }
+    }
+    let total = cmp::max(1, hours.iter().fold(0, |a, b| a + b));
+    let max = cmp::max(1, *hours.iter().max().unwrap());
+
+    let mut out = String::from("<h2>Most active times</h2>\n<div class=\"hours\">\n");
+    for (h, n) in hours.iter().enumerate() {
+        out.push_str(&format!("<div>{:.1}%<div class=\"bar\" style=\"height: {}px\"></div>\
+                               {}</div>\n",
+                              *n as f32 * 100.0 / total as f32,
+                              *n * 120 / max,
+                              h));
+    }
+    out.push_str("</div>\n");
+    out
+}
+
+/// Lines per quarter of the day, as a bar of coloured segments.
+fn quarters(nick: &NickStat) -> String {
+    let total = cmp::max(1, nick.lines);
+    let mut out = String::from("<span class=\"quarters\">");
+    for (q, hours) in nick.hours.chunks(6).enumerate() {
+        let n = hours.iter().fold(0, |a, b| a + b);
+        out.push_str(&format!("<span class=\"q{}\" style=\"width: {}px\" title=\"{}\"></span>",
+                              q,
+                              n * 100 / total,
+                              n));
+    }
+    out.push_str("</span>");
+    out
+}
+
+fn talkers(nicks: &[&NickStat]) -> String {
+    let mut out = String::from("<h2>Most active nicks</h2>\n<table>\n<tr><th></th><th>Nick</th>\
+                                <th>Lines</th><th>Words</th><th>Words per line</th>\
+                                <th>When</th><th>Random quote</th></tr>\n");
+    for (i, nick) in nicks.iter().enumerate() {
+        out.push_str(&format!("<tr><td class=\"num\">{}</td><td>{}</td><td class=\"num\">{}</td>\
+                               <td class=\"num\">{}</td><td class=\"num\">{:.1}</td><td>{}</td>\
+                               <td class=\"quote\">{}</td></tr>\n",
+                              i + 1,
+                              escape(&nick.nick),
+                              nick.lines,
+                              nick.words,
+                              per_line(nick.words, nick.lines),
+                              quarters(nick),
+                              nick.quote.as_ref().map(|q| escape(q)).unwrap_or_default()));
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+fn facts(nicks: &[&NickStat]) -> String {
+    fn most<F>(nicks: &[&NickStat], f: F) -> Option<(String, u32)>
+        where F: Fn(&NickStat) -> u32
+    {
+        nicks.iter()
+             .map(|n| (escape(&n.nick), f(n)))
+             .filter(|&(_, n)| n > 0)
+             .max_by_key(|&(_, n)| n)
+    }
+
+    let mut facts = Vec::new();
+    let talking: Vec<&NickStat> = nicks.iter().cloned().filter(|n| n.lines > 0).collect();
+    let (chars, lines) = talking.iter().fold((0, 0), |(c, l), n| (c + n.chars, l + n.lines));
+    let longest = talking.iter().max_by(|a, b| {
+        per_line(a.chars, a.lines).partial_cmp(&per_line(b.chars, b.lines)).unwrap()
+    });
+    let shortest = talking.iter().min_by(|a, b| {
+        per_line(a.chars, a.lines).partial_cmp(&per_line(b.chars, b.lines)).unwrap()
+    });
+    if let Some(n) = longest {
+        facts.push(format!("<b>{}</b> wrote the longest lines, averaging {:.1} letters per line. \
+                            The channel average was {:.1} letters.",
+                           escape(&n.nick),
+                           per_line(n.chars, n.lines),
+                           per_line(chars, lines)));
+    }
+    if let Some(n) = shortest {
+        facts.push(format!("<b>{}</b> wrote the shortest lines, averaging {:.1} letters per line.",
+                           escape(&n.nick),
+                           per_line(n.chars, n.lines)));
+    }
+    if let Some((nick, n)) = most(nicks, |n| n.kicks_given) {
+        facts.push(format!("<b>{}</b> is either insane or just a fair op, kicking {} people.",
+                           nick,
+                           n));
+    }
+    if let Some((nick, n)) = most(nicks, |n| n.kicks_received) {
+        facts.push(format!("<b>{}</b> got kicked the most, {} times.", nick, n));
+    }
+    if let Some((nick, n)) = most(nicks, |n| n.topics) {
+        facts.push(format!("<b>{}</b> changed the topic {} times.", nick, n));
+    }
+    if let Some((nick, n)) = most(nicks, |n| *n.events.get("action").unwrap_or(&0)) {
+        facts.push(format!("<b>{}</b> always lets us know what they're doing, with {} actions.",
+                           nick,
+                           n));
+    }
+    if let Some((nick, n)) = most(nicks, |n| *n.events.get("join").unwrap_or(&0)) {
+        facts.push(format!("<b>{}</b> couldn't decide whether to stay or go, joining {} times.",
+                           nick,
+                           n));
+    }
+    if let Some((nick, n)) = most(nicks, |n| n.modes) {
+        facts.push(format!("<b>{}</b> handed out or took away {} modes.", nick, n));
+    }
+    if let Some((nick, n)) = most(nicks, |n| n.smileys) {
+        facts.push(format!("<b>{}</b> brings happiness to the world, using {} smileys.", nick, n));
+    }
+
+    if facts.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("<h2>Big numbers</h2>\n<ul class=\"facts\">\n");
+    for fact in facts {
+        out.push_str(&format!("<li>{}</li>\n", fact));
+    }
+    out.push_str("</ul>\n");
+    out
+}
+
+fn words(stats: &Stats) -> String {
+    let words = top(stats.words.iter().map(|(w, n)| (w, *n)),
+                    10,
+                    |w| w.chars().count() >= MIN_WORD_LEN);
+    if words.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("<h2>Most used words</h2>\n<table>\n<tr><th></th><th>Word</th>\
+                                <th>Uses</th></tr>\n");
+    for (i, &(word, n)) in words.iter().enumerate() {
+        out.push_str(&format!("<tr><td class=\"num\">{}</td><td>{}</td>\
+                               <td class=\"num\">{}</td></tr>\n",
+                              i + 1,
+                              escape(word),
+                              n));
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+fn urls(stats: &Stats) -> String {
+    let urls = top(stats.urls.iter().map(|(u, s)| (u, s.count)), 5, |_| true);
+    if urls.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("<h2>Most referenced URLs</h2>\n<table>\n<tr><th></th><th>URL</th>\
+                                <th>Uses</th><th>Last used by</th></tr>\n");
+    for (i, &(url, n)) in urls.iter().enumerate() {
+        let href = if url.starts_with("www.") { format!("http://{}", url) } else { url.to_owned() };
+        out.push_str(&format!("<tr><td class=\"num\">{}</td><td><a href=\"{}\">{}</a></td>\
+                               <td class=\"num\">{}</td><td>{}</td></tr>\n",
+                              i + 1,
+                              escape(&href),
+                              escape(url),
+                              n,
+                              escape(&stats.urls[url].last_by)));
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+fn smileys(stats: &Stats) -> String {
+    let smileys = top(stats.smileys.iter().map(|(s, n)| (s, *n)), 10, |_| true);
+    if smileys.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("<h2>Smileys</h2>\n<table>\n\
+                                <tr><th>Smiley</th><th>Uses</th></tr>\n");
+    for &(smiley, n) in &smileys {
+        out.push_str(&format!("<tr><td>{}</td><td class=\"num\">{}</td></tr>\n",
+                              escape(smiley),
+                              n));
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+fn topics(ctx: &Context, stats: &Stats) -> String {
+    if stats.topics.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("<h2>Latest topics</h2>\n<table>\n");
+    for topic in stats.topics.iter().rev().take(5) {
+        let time = topic.time.map(|t| format_time(ctx, t)).unwrap_or_default();
+        out.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                              escape(&topic.topic),
+                              topic.nick.as_ref().map(|n| escape(n)).unwrap_or_default(),
+                              time));
+    }
+    out.push_str("</table>\n");
+    out
+}
+
+pub fn output_as_html(args: &ArgMatches,
+                      cli: &Cli,
+                      ctx: &Context,
+                      stats: Stats)
+                      -> ilc_base::Result<()> {
+    let count = value_or(args, "count", 25);
+
+    let mut nicks: Vec<&NickStat> = stats.freqs.values().collect();
+    nicks.sort_by(|a, b| (b.lines, &a.nick).cmp(&(a.lines, &b.nick)));
+    // nicks that only joined, left or were kicked come last, and don't count as talkers
+    let talking = nicks.iter().take_while(|n| n.lines > 0).count();
+
+    let mut content = hours(&stats);
+    content.push_str(&talkers(&nicks[..cmp::min(count, talking)]));
+    content.push_str(&facts(&nicks));
+    content.push_str(&words(&stats));
+    content.push_str(&urls(&stats));
+    content.push_str(&smileys(&stats));
+    content.push_str(&topics(ctx, &stats));
+
+    let title = match ctx.channel {
+        Some(ref c) => format!("Statistics for {}", c),
+        None => "IRC statistics".to_owned(),
+    };
+    render_page(args, cli, &title, &content)
+}
+
+/// Write a page of the HTML report, with `content` under `title`, which is escaped here.
+pub fn render_page(args: &ArgMatches,
+                   cli: &Cli,
+                   title: &str,
+                   content: &str)
+                   -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let generated = format!("Generated on {} by ilc {}",
+                            Local::now().to_rfc2822(),
+                            cli.version);
+
+    let template = try!(FILES.get("../templates/report.html"));
+    let page = String::from_utf8_lossy(&template)
+                   .replace("{{title}}", &escape(title))
+                   .replace("{{generated}}", &escape(&generated))
+                   .replace("{{content}}", content);
+    try!(e.output().write_all(page.as_bytes()));
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use clap::ArgMatches;
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use ilc_base;
+use ilc_ops::sessions::Presence;
+use stats::csv_field;
+use Environment;
+
+/// One row per nick, most present first. Times are in seconds.
+pub fn output_as_csv(args: &ArgMatches, nicks: &HashMap<String, Presence>) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    try!(writeln!(&mut out, "nick,sessions,interrupted,total,median,typical_hours"));
+
+    let mut nicks: Vec<&Presence> = nicks.values().collect();
+    nicks.sort_by(|a, b| (b.total(), &a.nick).cmp(&(a.total(), &b.nick)));
+    for p in nicks {
+        let hours: Vec<String> = p.typical_hours(3).iter().map(|h| h.to_string()).collect();
+        try!(writeln!(&mut out,
+                      "{},{},{},{},{},{}",
+                      csv_field(&p.nick),
+                      p.sessions.len(),
+                      p.interrupted(),
+                      p.total(),
+                      p.median().map(|m| m.to_string()).unwrap_or_default(),
+                      hours.join(" ")));
+    }
+    Ok(())
+}
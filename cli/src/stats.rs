@@ -5,25 +5,30 @@ use chrono::Local;
 use serde_json;
 use serde::ser::{MapVisitor, Serialize, Serializer};
 
-use ilc_base;
-use ilc_ops::stats::Stats;
+use ilc_base::{self, Context};
+use ilc_ops::state::State;
+use ilc_ops::stats::{Points, Series};
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
 use Environment;
 use Cli;
 use error;
 
-struct StatFormat {
+struct StatFormat<T> {
     version: String,
     master_hash: Option<String>,
     time: String,
-    stats: Stats,
+    stats: T,
 }
 
-impl Serialize for StatFormat {
+impl<T: Serialize> Serialize for StatFormat<T> {
     fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
         where S: Serializer
     {
-        struct Visitor<'a>(&'a StatFormat);
-        impl<'a> MapVisitor for Visitor<'a> {
+        struct Visitor<'a, T: 'a>(&'a StatFormat<T>);
+        impl<'a, T: Serialize> MapVisitor for Visitor<'a, T> {
             fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
                 where S: Serializer
             {
@@ -45,7 +50,9 @@ impl Serialize for StatFormat {
 }
 
 
-pub fn output_as_json(args: &ArgMatches, cli: &Cli, stats: Stats) -> ilc_base::Result<()> {
+pub fn output_as_json<T>(args: &ArgMatches, cli: &Cli, stats: T) -> ilc_base::Result<()>
+    where T: Serialize
+{
     let e = Environment(args);
     // let count = value_t!(args, "count", usize).unwrap_or(usize::MAX);
     // let mut stats: Vec<(String, Person)> = stats.into_iter().collect();
@@ -73,3 +80,67 @@ pub fn output_as_json(args: &ArgMatches, cli: &Cli, stats: Stats) -> ilc_base::R
     // }
     Ok(())
 }
+
+/// One row per channel or nick and bucket, ordered by name and time.
+pub fn output_series_as_csv(args: &ArgMatches, series: Series) -> ilc_base::Result<()> {
+    fn rows(out: &mut Write, kind: &str, name: &str, points: &Points) -> ilc_base::Result<()> {
+        for (date, p) in points {
+            let nicks = if kind == "channel" { p.nicks().to_string() } else { String::new() };
+            try!(writeln!(out,
+                          "{},{},{},{},{},{}",
+                          kind,
+                          csv_field(name),
+                          date,
+                          p.lines,
+                          p.words,
+                          nicks));
+        }
+        Ok(())
+    }
+
+    let e = Environment(args);
+    let mut out = e.output();
+    try!(writeln!(&mut out, "kind,name,start,lines,words,nicks"));
+
+    let mut channels: Vec<_> = series.channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+    for (channel, points) in channels {
+        try!(rows(&mut out, "channel", channel, points));
+    }
+    let mut nicks: Vec<_> = series.nicks.values().collect();
+    nicks.sort_by(|a, b| a.nick.cmp(&b.nick));
+    for n in nicks {
+        try!(rows(&mut out, "nick", &n.nick, &n.points));
+    }
+    Ok(())
+}
+
+/// Add the input to the state saved at `path`, or to a new one if there is no such file, and
+/// save the result there.
+pub fn update_state(e: &Environment, ctx: &Context, path: &str) -> ilc_base::Result<State> {
+    let mut state = if Path::new(path).exists() {
+        let file = try!(File::open(path));
+        try!(State::load(&mut BufReader::new(file)))
+    } else {
+        State::new(ctx.casemapping)
+    };
+    try!(state.update(ctx, &mut e.input(), &mut *e.decoder()));
+
+    // keep the old state intact until the new one is completely written
+    let tmp = format!("{}.tmp", path);
+    {
+        let mut out = BufWriter::new(try!(File::create(&tmp)));
+        try!(state.save(&mut out));
+        try!(out.flush());
+    }
+    try!(fs::rename(&tmp, path));
+    Ok(state)
+}
+
+pub fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
@@ -0,0 +1,69 @@
+use clap::ArgMatches;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Take, Write};
+use std::path::{Path, PathBuf};
+
+use ilc_base::{self, Context};
+use ilc_ops::timeindex::TimeIndex;
+use Environment;
+use die;
+use gather_input;
+
+/// The time index of a log is kept next to it, with `.ilctime` appended to its name.
+pub fn path(log: &Path) -> PathBuf {
+    let mut name = log.as_os_str().to_owned();
+    name.push(".ilctime");
+    PathBuf::from(name)
+}
+
+/// Open a log, limited to the part that can hold events between `since` and `until` if it has a
+/// time index that still fits it.
+pub fn open(ctx: &Context,
+            log: &Path,
+            since: Option<i64>,
+            until: Option<i64>)
+            -> io::Result<Take<File>> {
+    let mut file = try!(File::open(log));
+    let len = try!(file.metadata()).len();
+    let index = if since.is_some() || until.is_some() {
+        File::open(path(log))
+            .ok()
+            .and_then(|f| TimeIndex::load(&mut BufReader::new(f)).ok())
+            .and_then(|index| if index.fits(ctx, len) { Some(index) } else { None })
+    } else {
+        None
+    };
+    let (start, end) = match index {
+        Some(index) => index.range(since, until),
+        None => (0, u64::max_value()),
+    };
+    try!(file.seek(SeekFrom::Start(start)));
+    Ok(file.take(end - start))
+}
+
+/// Write a time index next to every input file, with a checkpoint every `every` bytes or so.
+pub fn build(args: &ArgMatches, ctx: &Context, every: u64) -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let files = gather_input(args);
+    if files.is_empty() {
+        die("A time index belongs to a file, so it can only be built for input files");
+    }
+    let mut decoder = e.whole_decoder();
+    for file in files {
+        let mut input = BufReader::new(try!(File::open(&file)));
+        let index = try!(TimeIndex::build(ctx, &mut input, &mut *decoder, every));
+
+        // keep the old index intact until the new one is completely written
+        let path = path(&file);
+        let tmp = format!("{}.tmp", path.display());
+        {
+            let mut out = BufWriter::new(try!(File::create(&tmp)));
+            try!(index.save(&mut out));
+            try!(out.flush());
+        }
+        try!(fs::rename(&tmp, &path));
+        info!("{} checkpoints for {}", index.checkpoints.len(), file.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use clap::ArgMatches;
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use ilc_base::{self, Context};
+use ilc_ops::topics::{Change, TopicEntry};
+use audit::duration;
+use report::{escape, render_page};
+use Environment;
+use Cli;
+use format_time;
+
+fn start(ctx: &Context, topic: &TopicEntry) -> String {
+    topic.start.map_or_else(|| "unknown time".to_owned(), |t| format_time(ctx, t))
+}
+
+/// Who set the topic and how long it stayed, e.g. `by alice, for 2h 5m`.
+fn about(topic: &TopicEntry) -> String {
+    let mut about = match topic.nick {
+        Some(ref nick) => format!("by {}", nick),
+        None => "announced".to_owned(),
+    };
+    match topic.lasted() {
+        Some(t) => about.push_str(&format!(", for {}", duration(t))),
+        None if topic.current => about.push_str(", current"),
+        None => (),
+    }
+    about
+}
+
+/// A heading per channel, then per topic the time, setter and text, and the changed words, as
+/// `[-removed-]` and `{+added+}`.
+pub fn output(args: &ArgMatches,
+              ctx: &Context,
+              channels: &BTreeMap<String, Vec<TopicEntry>>)
+              -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    for (channel, timeline) in channels {
+        try!(writeln!(&mut out, "{}:", channel));
+        for topic in timeline {
+            try!(writeln!(&mut out, "  {} {}", start(ctx, topic), about(topic)));
+            try!(writeln!(&mut out, "    {}", topic.topic));
+            if let Some(ref diff) = topic.diff {
+                let words: Vec<String> = diff.iter()
+                                             .map(|c| {
+                                                 match *c {
+                                                     Change::Same(ref w) => w.clone(),
+                                                     Change::Added(ref w) => format!("{{+{}+}}", w),
+                                                     Change::Removed(ref w) => format!("[-{}-]", w),
+                                                 }
+                                             })
+                                             .collect();
+                try!(writeln!(&mut out, "    {}", words.join(" ")));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A table per channel, in the style of the HTML report, with added and removed words marked.
+pub fn output_as_html(args: &ArgMatches,
+                      cli: &Cli,
+                      ctx: &Context,
+                      channels: &BTreeMap<String, Vec<TopicEntry>>)
+                      -> ilc_base::Result<()> {
+    let mut content = String::new();
+    for (channel, timeline) in channels {
+        content.push_str(&format!("<h2>{}</h2>\n<table>\n", escape(channel)));
+        for topic in timeline {
+            let text = match topic.diff {
+                Some(ref diff) => {
+                    let words: Vec<String> = diff.iter()
+                                                 .map(|c| {
+                                                     match *c {
+                                                         Change::Same(ref w) => escape(w),
+                                                         Change::Added(ref w) => {
+                                                             format!("<ins>{}</ins>", escape(w))
+                                                         }
+                                                         Change::Removed(ref w) => {
+                                                             format!("<del>{}</del>", escape(w))
+                                                         }
+                                                     }
+                                                 })
+                                                 .collect();
+                    words.join(" ")
+                }
+                None => escape(&topic.topic),
+            };
+            content.push_str(&format!("<tr><td class=\"num\">{}</td><td>{}</td><td>{}</td></tr>\n",
+                                      start(ctx, topic),
+                                      text,
+                                      escape(&about(topic))));
+        }
+        content.push_str("</table>\n");
+    }
+
+    let title = match ctx.channel {
+        Some(ref c) => format!("Topics of {}", c),
+        None => "IRC topics".to_owned(),
+    };
+    render_page(args, cli, &title, &content)
+}
@@ -0,0 +1,61 @@
+use clap::ArgMatches;
+
+use std::io::Write;
+
+use ilc_base::{self, Context, Time};
+use ilc_ops::urls::{Link, Summary};
+use stats::csv_field;
+use Environment;
+
+fn time(ctx: &Context, time: &Time) -> String {
+    time.try_format(&ctx.timezone_out, "%Y-%m-%d %H:%M:%S")
+        .or_else(|| time.try_format(&ctx.timezone_out, "%H:%M:%S"))
+        .unwrap_or_default()
+}
+
+pub fn output_links_as_csv(args: &ArgMatches,
+                           ctx: &Context,
+                           links: &[Link])
+                           -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    try!(writeln!(&mut out, "time,nick,channel,url"));
+    for link in links {
+        try!(writeln!(&mut out,
+                      "{},{},{},{}",
+                      time(ctx, &link.time),
+                      csv_field(link.nick.as_ref().map_or("", |n| n as &str)),
+                      csv_field(link.channel.as_ref().map_or("", |c| c as &str)),
+                      csv_field(&link.url)));
+    }
+    Ok(())
+}
+
+/// Domains and nicks by count, then URLs by count, with the time they were first and last posted.
+pub fn output_summary_as_csv(args: &ArgMatches,
+                             ctx: &Context,
+                             summary: &Summary)
+                             -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let mut out = e.output();
+    try!(writeln!(&mut out, "kind,key,count,first,last"));
+
+    for &(kind, counts) in &[("domain", &summary.domains), ("nick", &summary.nicks)] {
+        let mut counts: Vec<_> = counts.iter().collect();
+        counts.sort_by(|&(ka, a), &(kb, b)| (b, ka).cmp(&(a, kb)));
+        for (key, n) in counts {
+            try!(writeln!(&mut out, "{},{},{},,", kind, csv_field(key), n));
+        }
+    }
+    let mut urls: Vec<_> = summary.urls.iter().collect();
+    urls.sort_by(|&(ka, a), &(kb, b)| (b.count, ka).cmp(&(a.count, kb)));
+    for (url, u) in urls {
+        try!(writeln!(&mut out,
+                      "url,{},{},{},{}",
+                      csv_field(url),
+                      u.count,
+                      time(ctx, &u.first),
+                      time(ctx, &u.last)));
+    }
+    Ok(())
+}
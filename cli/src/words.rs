@@ -0,0 +1,59 @@
+use clap::ArgMatches;
+
+use std::io::Write;
+
+use ilc_base;
+use ilc_ops::words::{Stopwords, Vocabulary, WordCount};
+use Environment;
+use value_or;
+
+fn top(out: &mut Write,
+       words: &WordCount,
+       count: usize,
+       stopwords: &Stopwords)
+       -> ilc_base::Result<()> {
+    for (i, (word, n)) in words.top(count, stopwords).into_iter().enumerate() {
+        try!(writeln!(out, "  {:>3}. {} ({})", i + 1, word, n));
+    }
+    Ok(())
+}
+
+/// Plain text, the whole channel first, then every nick, most talkative first.
+pub fn output(args: &ArgMatches,
+              vocabulary: &Vocabulary,
+              stopwords: &Stopwords)
+              -> ilc_base::Result<()> {
+    let e = Environment(args);
+    let count = value_or(args, "count", 10);
+    let mut out = e.output();
+
+    try!(writeln!(&mut out,
+                  "{} words, {} distinct, lexical diversity {:.3}",
+                  vocabulary.words.total,
+                  vocabulary.words.vocabulary(),
+                  vocabulary.words.diversity()));
+    try!(top(&mut out, &vocabulary.words, count, stopwords));
+
+    let mut nicks: Vec<_> = vocabulary.nicks.values().collect();
+    nicks.sort_by(|a, b| (b.words.total, &a.nick).cmp(&(a.words.total, &b.nick)));
+    for n in nicks {
+        let introduced: Vec<&str> = n.introduced
+                                     .iter()
+                                     .map(|w| w as &str)
+                                     .filter(|w| !stopwords.contains(w))
+                                     .take(count)
+                                     .collect();
+        try!(writeln!(&mut out,
+                      "\n{}: {} words, {} distinct, lexical diversity {:.3}, introduced {} words",
+                      n.nick,
+                      n.words.total,
+                      n.words.vocabulary(),
+                      n.words.diversity(),
+                      n.introduced.len()));
+        try!(top(&mut out, &n.words, count, stopwords));
+        if !introduced.is_empty() {
+            try!(writeln!(&mut out, "  first to say: {}", introduced.join(", ")));
+        }
+    }
+    Ok(())
+}
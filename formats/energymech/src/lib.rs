@@ -1,11 +1,13 @@
 #[macro_use]
 extern crate log;
+#[macro_use]
 extern crate ilc_base;
 extern crate chrono;
 
 use std::io::{BufRead, Write};
 use std::borrow::{Cow, ToOwned};
 use std::iter::Iterator;
+use std::cmp;
 
 use ilc_base::event::{Event, Time, Type};
 use ilc_base::format::{rejoin, strip_one};
@@ -15,13 +17,38 @@ use log::LogLevel::Info;
 
 use chrono::*;
 
-#[derive(Copy, Clone)]
-pub struct Energymech;
+/// Energymech, and ZNC's `log` module, which writes the same format.
+#[derive(Clone, Debug)]
+pub struct Energymech {
+    /// Layout of the timestamp between the brackets, in `strftime` syntax.
+    /// energymech and ZNC default to `%H:%M:%S`, but can be configured to e.g.
+    /// `%Y-%m-%d %H:%M:%S` or `%H:%M`.
+    pub timestamp: String,
+}
+
+impl Default for Energymech {
+    fn default() -> Energymech {
+        Energymech { timestamp: "%H:%M:%S".to_owned() }
+    }
+}
+
+impl Energymech {
+    pub fn with_timestamp<S: Into<String>>(timestamp: S) -> Energymech {
+        Energymech { timestamp: timestamp.into() }
+    }
 
-static TIME_FORMAT: &'static str = "%H:%M:%S";
+    /// Whether the timestamp layout includes a date, and yields full timestamps
+    fn dated(&self) -> bool {
+        ["%Y", "%y", "%C", "%m", "%b", "%h", "%B", "%d", "%e", "%j", "%D", "%x", "%F", "%s"]
+            .iter()
+            .any(|spec| self.timestamp.contains(spec))
+    }
+}
 
 pub struct Iter<'a> {
     context: &'a Context,
+    layout: &'a str,
+    dated: bool,
     input: &'a mut BufRead,
     buffer: Vec<u8>,
 }
@@ -29,34 +56,49 @@ pub struct Iter<'a> {
 impl<'a> Iterator for Iter<'a> {
     type Item = ilc_base::Result<Event<'a>>;
     fn next(&mut self) -> Option<ilc_base::Result<Event<'a>>> {
-        fn parse_time(context: &Context, time: &str) -> Time {
-            let h = time[1..3].parse::<u32>().unwrap();
-            let m = time[4..6].parse::<u32>().unwrap();
-            let s = time[7..9].parse::<u32>().unwrap();
+        fn parse_time(context: &Context, layout: &str, dated: bool, stamp: &str) -> Time {
+            let stamp = strip_one(stamp);
+            if dated {
+                return Time::from_format(&context.timezone_in, &stamp, layout);
+            }
+            let time = match NaiveTime::parse_from_str(&stamp, layout) {
+                Ok(t) => t,
+                Err(_) => return Time::Unknown,
+            };
             if let Some(date) = context.override_date {
                 Time::Timestamp(context.timezone_in
                                        .from_local_date(&date)
-                                       .and_time(NaiveTime::from_hms(h, m, s))
+                                       .and_time(time)
                                        .single()
                                        .expect("Transformed log times can't be represented, due \
                                                 to timezone transitions")
                                        .timestamp())
             } else {
-                Time::Hms(h as u8, m as u8, s as u8)
+                Time::Hms(time.hour() as u8, time.minute() as u8, time.second() as u8)
             }
         }
 
+        // dated layouts contain whitespace, and span several tokens
+        let stamp_len = cmp::max(1, self.layout.split_whitespace().count());
+
         loop {
             self.buffer.clear();
             match self.input.read_until(b'\n', &mut self.buffer) {
                 Ok(0) | Err(_) => return None,
                 Ok(_) => (),
             }
+            // `rejoin` relies on a trailing separator
+            if !self.buffer.ends_with(b"\n") {
+                self.buffer.push(b'\n');
+            }
 
             let buffer = String::from_utf8_lossy(&self.buffer);
+            if !buffer.starts_with('[') {
+                continue;
+            }
 
             let mut split_tokens: Vec<char> = Vec::new();
-            let tokens = buffer.split(|c: char| {
+            let mut tokens = buffer.split(|c: char| {
                                    if c.is_whitespace() {
                                        split_tokens.push(c);
                                        true
@@ -71,6 +113,17 @@ impl<'a> Iterator for Iter<'a> {
                 info!("Parsing:   {:?}", tokens);
             }
 
+            if tokens.len() <= stamp_len {
+                continue;
+            }
+            let time = {
+                let stamp = rejoin(&tokens[..stamp_len], &split_tokens[..stamp_len]);
+                parse_time(&self.context, self.layout, self.dated, &stamp)
+            };
+            // from here on, tokens[0] is the (last part of the) timestamp
+            tokens.drain(..stamp_len - 1);
+            split_tokens.drain(..stamp_len - 1);
+
             // slice pattern matching is not stable as of Feb. 2016 and was replaced with
             // nested if-else chains in this module.
 
@@ -83,7 +136,7 @@ impl<'a> Iterator for Iter<'a> {
                         from: tokens[2].to_owned().into(),
                         content: rejoin(&tokens[3..], &split_tokens[3..]),
                     },
-                    time: parse_time(&self.context, tokens[0]),
+                    time: time,
                     channel: self.context.channel.clone().map(Into::into),
                 }));
             }
@@ -97,22 +150,25 @@ impl<'a> Iterator for Iter<'a> {
                             old_nick: tokens[2].to_owned().into(),
                             new_nick: tokens[7].to_owned().into(),
                         },
-                        time: parse_time(&self.context, tokens[0]),
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
 
                 // [23:21:17] *** Paster was kicked by fripp.mozilla.org (Channel flood triggered (limit is 5 lines in 3 secs))
-                if len >= 8 && tokens[3] == "was" && tokens[4] == "kicked" && tokens[5] == "by" {
+                // [23:21:17] *** Paster was kicked by Foo
+                if len >= 7 && tokens[3] == "was" && tokens[4] == "kicked" && tokens[5] == "by" {
                     return Some(Ok(Event {
                         ty: Type::Kick {
                             kicked_nick: tokens[2].to_owned().into(),
                             kicking_nick: Some(tokens[6].to_owned().into()),
-                            kick_message: Some(strip_one(&rejoin(&tokens[7..],
-                                                                 &split_tokens[7..]))
-                                                   .into()),
+                            kick_message: if len > 8 {
+                                Some(strip_one(&rejoin(&tokens[7..], &split_tokens[7..])).into())
+                            } else {
+                                None
+                            },
                         },
-                        time: parse_time(&self.context, tokens[0]),
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
@@ -125,7 +181,7 @@ impl<'a> Iterator for Iter<'a> {
                             mode: tokens[5].to_owned().into(),
                             masks: rejoin(&tokens[6..], &split_tokens[6..]).to_owned().into(),
                         },
-                        time: parse_time(&self.context, tokens[0]),
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
@@ -137,7 +193,7 @@ impl<'a> Iterator for Iter<'a> {
                             nick: tokens[3].to_owned().into(),
                             mask: Some(strip_one(tokens[4]).into()),
                         },
-                        time: parse_time(&self.context, tokens[0]),
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
@@ -151,7 +207,7 @@ impl<'a> Iterator for Iter<'a> {
                             reason: Some(strip_one(&rejoin(&tokens[5..], &split_tokens[5..]))
                                              .into()),
                         },
-                        time: parse_time(&self.context, tokens[0]),
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
@@ -165,7 +221,37 @@ impl<'a> Iterator for Iter<'a> {
                             reason: Some(strip_one(&rejoin(&tokens[5..], &split_tokens[5..]))
                                              .into()),
                         },
-                        time: parse_time(&self.context, tokens[0]),
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+
+                // [09:44:50] *** Topic is 'Hi there'
+                if len >= 5 && tokens[2] == "Topic" && tokens[3] == "is" {
+                    return Some(Ok(Event {
+                        ty: Type::Topic {
+                            topic: strip_one(&rejoin(&tokens[4..], &split_tokens[4..])).into(),
+                        },
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+
+                // [09:40:02] *** Connected to IRC (irc.example.net)
+                if len >= 5 && tokens[2] == "Connected" && tokens[3] == "to" && tokens[4] == "IRC" {
+                    return Some(Ok(Event {
+                        ty: Type::Connect,
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+
+                // [09:40:01] *** Disconnected from IRC (irc.example.net)
+                if len >= 5 && tokens[2] == "Disconnected" && tokens[3] == "from" &&
+                   tokens[4] == "IRC" {
+                    return Some(Ok(Event {
+                        ty: Type::Disconnect,
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
@@ -177,7 +263,7 @@ impl<'a> Iterator for Iter<'a> {
                             nick: Some(tokens[2].to_owned().into()),
                             new_topic: strip_one(&rejoin(&tokens[6..], &split_tokens[6..])).into(),
                         },
-                        time: parse_time(&self.context, tokens[0]),
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
@@ -190,20 +276,27 @@ impl<'a> Iterator for Iter<'a> {
                         from: strip_one(tokens[1]).into(),
                         content: rejoin(&tokens[2..], &split_tokens[2..]),
                     },
-                    time: parse_time(&self.context, tokens[0]),
+                    time: time,
                     channel: self.context.channel.clone().map(Into::into),
                 }));
             }
 
             // [10:25:22] -playbot- true
-            if len >= 3 && tokens[1].starts_with('-') && tokens[1].ends_with('-') {
+            // [10:25:22] -playbot:#channel- true
+            if len >= 3 && tokens[1].len() >= 2 && tokens[1].starts_with('-') &&
+               tokens[1].ends_with('-') {
+                let from = strip_one(tokens[1]);
+                let mut from = from.splitn(2, ':');
+                let nick = from.next().unwrap_or("");
+                let channel = from.next();
                 return Some(Ok(Event {
                     ty: Type::Notice {
-                        from: strip_one(tokens[1]).into(),
+                        from: nick.to_owned().into(),
                         content: rejoin(&tokens[2..], &split_tokens[2..]),
                     },
-                    time: parse_time(&self.context, tokens[0]),
-                    channel: self.context.channel.clone().map(Into::into),
+                    time: time,
+                    channel: channel.map(|c| c.to_owned().into())
+                                    .or(self.context.channel.clone().map(Into::into)),
                 }));
             }
             if option_env!("FUSE").is_some() {
@@ -220,6 +313,8 @@ impl Decode for Energymech {
                   -> Box<Iterator<Item = ilc_base::Result<Event<'a>>> + 'a> {
         Box::new(Iter {
             context: context,
+            layout: &self.timestamp,
+            dated: self.dated(),
             input: input,
             buffer: Vec::new(),
         })
@@ -232,80 +327,251 @@ impl Encode for Energymech {
                   mut output: &'a mut Write,
                   event: &'a Event)
                   -> ilc_base::Result<()> {
+        let ty = event.ty.type_desc();
+        let time = degrade!(context.time(ty, &event.time, &self.timestamp));
         match event {
-            &Event { ty: Type::Msg { ref from, ref content }, ref time, .. } => {
-                try!(writeln!(&mut output,
-                              "[{}] <{}> {}",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
-                              from,
-                              content))
+            &Event { ty: Type::Msg { ref from, ref content }, .. } => {
+                try!(writeln!(&mut output, "[{}] <{}> {}", time, from, content))
             }
-            &Event { ty: Type::Notice { ref from, ref content }, ref time, .. } => {
-                try!(writeln!(&mut output,
-                              "[{}] -{}- {}",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
-                              from,
-                              content))
+            &Event { ty: Type::Notice { ref from, ref content }, ref channel, .. } => {
+                // a notice to a channel other than the one of the log names it
+                match *channel {
+                    Some(ref c) if context.channel.as_ref().map_or(true, |own| own != c) => {
+                        try!(writeln!(&mut output, "[{}] -{}:{}- {}", time, from, c, content))
+                    }
+                    _ => try!(writeln!(&mut output, "[{}] -{}- {}", time, from, content)),
+                }
             }
-            &Event { ty: Type::Action { ref from, ref content }, ref time, .. } => {
-                try!(writeln!(&mut output,
-                              "[{}] * {} {}",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
-                              from,
-                              content))
+            &Event { ty: Type::Action { ref from, ref content }, .. } => {
+                try!(writeln!(&mut output, "[{}] * {} {}", time, from, content))
             }
-            &Event { ty: Type::Nick { ref old_nick, ref new_nick }, ref time, .. } => {
+            &Event { ty: Type::Nick { ref old_nick, ref new_nick }, .. } => {
                 try!(writeln!(&mut output,
                               "[{}] *** {} is now known as {}",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
+                              time,
                               old_nick,
                               new_nick))
             }
-            &Event { ty: Type::Mode { ref nick, ref mode, ref masks }, ref time, .. } => {
-                try!(writeln!(&mut output,
-                              "[{}] *** {} sets mode: {} {}",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
-                              nick.as_ref().expect("Nickname not present, but required."),
-                              mode,
-                              masks))
+            &Event { ty: Type::Mode { ref nick, ref mode, ref masks }, .. } => {
+                let nick = nick.as_ref().map(|n| n as &str);
+                let nick = degrade!(context.require(ty, "nick", nick, "*"));
+                try!(writeln!(&mut output, "[{}] *** {} sets mode: {} {}", time, nick, mode, masks))
             }
-            &Event { ty: Type::Join { ref nick, ref mask }, ref time, .. } => {
-                try!(writeln!(&mut output,
-                              "[{}] *** Joins: {} ({})",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
-                              nick,
-                              mask.as_ref().expect("Mask not present, but required.")))
+            &Event { ty: Type::Join { ref nick, ref mask }, .. } => {
+                let mask = mask.as_ref().map(|m| m as &str);
+                let mask = degrade!(context.require(ty, "mask", mask, "*@*"));
+                try!(writeln!(&mut output, "[{}] *** Joins: {} ({})", time, nick, mask))
             }
-            &Event { ty: Type::Part { ref nick, ref mask, ref reason }, ref time, .. } => {
+            &Event { ty: Type::Part { ref nick, ref mask, ref reason }, .. } => {
+                let mask = mask.as_ref().map(|m| m as &str);
+                let mask = degrade!(context.require(ty, "mask", mask, "*@*"));
                 try!(writeln!(&mut output,
                               "[{}] *** Parts: {} ({}) ({})",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
+                              time,
                               nick,
-                              mask.as_ref().expect("Mask not present, but required."),
+                              mask,
                               reason.as_ref().unwrap_or(&Cow::Borrowed(""))))
             }
-            &Event { ty: Type::Quit { ref nick, ref mask, ref reason }, ref time, .. } => {
+            &Event { ty: Type::Quit { ref nick, ref mask, ref reason }, .. } => {
+                let mask = mask.as_ref().map(|m| m as &str);
+                let mask = degrade!(context.require(ty, "mask", mask, "*@*"));
                 try!(writeln!(&mut output,
                               "[{}] *** Quits: {} ({}) ({})",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
+                              time,
                               nick,
-                              mask.as_ref().expect("Mask not present, but required."),
-                              reason.as_ref().expect("Reason not present, but required.")))
+                              mask,
+                              reason.as_ref().unwrap_or(&Cow::Borrowed(""))))
             }
-            &Event { ty: Type::TopicChange { ref nick, ref new_topic }, ref time, .. } => {
+            &Event { ty: Type::TopicChange { ref nick, ref new_topic }, .. } => {
+                let nick = nick.as_ref().map(|n| n as &str);
+                let nick = degrade!(context.require(ty, "nick", nick, "*"));
                 try!(writeln!(&mut output,
                               "[{}] *** {} changes topic to '{}'",
-                              time.with_format(&context.timezone_out, TIME_FORMAT),
-                              nick.as_ref().expect("Nick not present, but required."),
+                              time,
+                              nick,
                               new_topic))
             }
+            &Event { ty: Type::Kick { ref kicked_nick, ref kicking_nick, ref kick_message },
+                     .. } => {
+                let kicker = kicking_nick.as_ref().map(|n| n as &str);
+                let kicker = degrade!(context.require(ty, "kicking nick", kicker, "*"));
+                try!(write!(&mut output,
+                            "[{}] *** {} was kicked by {}",
+                            time,
+                            kicked_nick,
+                            kicker));
+                if let &Some(ref message) = kick_message {
+                    try!(write!(&mut output, " ({})", message));
+                }
+                try!(write!(&mut output, "\n"))
+            }
+            &Event { ty: Type::Topic { ref topic }, .. } => {
+                try!(writeln!(&mut output, "[{}] *** Topic is '{}'", time, topic))
+            }
+            &Event { ty: Type::Connect, .. } => {
+                try!(writeln!(&mut output, "[{}] *** Connected to IRC", time))
+            }
+            &Event { ty: Type::Disconnect, .. } => {
+                try!(writeln!(&mut output, "[{}] *** Disconnected from IRC", time))
+            }
             _ => {
-                if option_env!("FUSE").is_some() {
-                    panic!("Shouldn't reach here, this is a bug!")
+                degrade!(context.unsupported(ty));
+                try!(write!(&mut output, "[{}] *** [{}]", time, ty));
+                if let Some(actor) = event.ty.actor() {
+                    try!(write!(&mut output, " {}", actor));
                 }
-                ()
+                if let Some(text) = event.ty.text() {
+                    try!(write!(&mut output, ": {}", text));
+                }
+                try!(write!(&mut output, "\n"))
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use chrono::NaiveDate;
+
+    use ilc_base::event::{Event, Time, Type};
+    use ilc_base::{Context, Decode, Encode};
+
+    use super::Energymech;
+
+    fn decode(mech: &Energymech, ctx: &Context, line: &str) -> Vec<Event<'static>> {
+        let mut input = line.as_bytes();
+        mech.decode(ctx, &mut input)
+            .map(|e| {
+                let e = e.unwrap();
+                Event {
+                    ty: e.ty.map_nicks(str::to_owned),
+                    time: e.time,
+                    channel: None,
+                }
+            })
+            .collect()
+    }
+
+    fn roundtrip(line: &str) {
+        let mech = Energymech::default();
+        let ctx = Context::default();
+        let mut input = line.as_bytes();
+        let mut out = Vec::new();
+        for e in mech.decode(&ctx, &mut input) {
+            mech.encode(&ctx, &mut out, &e.unwrap()).unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), line);
+    }
+
+    fn encode(mech: &Energymech, ctx: &Context, events: &[Event]) -> String {
+        let mut out = Vec::new();
+        for e in events {
+            mech.encode(ctx, &mut out, e).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn dated() {
+        assert!(!Energymech::default().dated());
+        assert!(!Energymech::with_timestamp("%H:%M").dated());
+        assert!(Energymech::with_timestamp("%Y-%m-%d %H:%M:%S").dated());
+        assert!(Energymech::with_timestamp("%d.%m. %H:%M").dated());
+    }
+
+    #[test]
+    fn dated_layout() {
+        let mech = Energymech::with_timestamp("%Y-%m-%d %H:%M:%S");
+        let ctx = Context::default();
+        let line = "[2016-02-25 01:15:05] <Foo> Hello there\n";
+        let events = decode(&mech, &ctx, line);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].time, Time::Timestamp(1456362905));
+        assert_eq!(events[0].ty,
+                   Type::Msg {
+                       from: Cow::Borrowed("Foo"),
+                       content: Cow::Borrowed("Hello there"),
+                   });
+        assert_eq!(encode(&mech, &ctx, &events), line);
+    }
+
+    #[test]
+    fn undated_layout() {
+        let mech = Energymech::default();
+        let mut ctx = Context::default();
+        let line = "[01:15:05] * Foo waves\n";
+        assert_eq!(decode(&mech, &ctx, line)[0].time, Time::Hms(1, 15, 5));
+
+        ctx.override_date = Some(NaiveDate::from_ymd(2016, 2, 25));
+        let events = decode(&mech, &ctx, line);
+        assert_eq!(events[0].time, Time::Timestamp(1456362905));
+        assert_eq!(encode(&mech, &ctx, &events), line);
+        assert_eq!(encode(&Energymech::with_timestamp("%Y-%m-%d %H:%M:%S"), &ctx, &events),
+                   "[2016-02-25 01:15:05] * Foo waves\n");
+    }
+
+    #[test]
+    fn kick() {
+        let ctx = Context::default();
+        let line = "[23:21:17] *** Paster was kicked by Foo (Channel flood (limit is 5 lines))\n";
+        assert_eq!(decode(&Energymech::default(), &ctx, line)[0].ty,
+                   Type::Kick {
+                       kicked_nick: Cow::Borrowed("Paster"),
+                       kicking_nick: Some(Cow::Borrowed("Foo")),
+                       kick_message: Some(Cow::Borrowed("Channel flood (limit is 5 lines)")),
+                   });
+        roundtrip(line);
+
+        let line = "[23:21:17] *** Paster was kicked by Foo\n";
+        assert_eq!(decode(&Energymech::default(), &ctx, line)[0].ty,
+                   Type::Kick {
+                       kicked_nick: Cow::Borrowed("Paster"),
+                       kicking_nick: Some(Cow::Borrowed("Foo")),
+                       kick_message: None,
+                   });
+        roundtrip(line);
+    }
+
+    #[test]
+    fn topic() {
+        let line = "[09:44:50] *** Topic is 'Hi there'\n";
+        assert_eq!(decode(&Energymech::default(), &Context::default(), line)[0].ty,
+                   Type::Topic { topic: Cow::Borrowed("Hi there") });
+        roundtrip(line);
+    }
+
+    #[test]
+    fn connection() {
+        let ctx = Context::default();
+        let events = decode(&Energymech::default(),
+                            &ctx,
+                            "[09:40:01] *** Disconnected from IRC (irc.example.net)\n[09:40:02] \
+                             *** Connected to IRC (irc.example.net)\n");
+        assert_eq!(events.iter().map(|e| e.ty.clone()).collect::<Vec<_>>(),
+                   vec![Type::Disconnect, Type::Connect]);
+        roundtrip("[09:40:01] *** Disconnected from IRC\n");
+        roundtrip("[09:40:02] *** Connected to IRC\n");
+    }
+
+    #[test]
+    fn notice() {
+        let mech = Energymech::default();
+        let mut ctx = Context::default();
+        ctx.channel = Some("#example".to_owned());
+        let mut input = "[10:25:22] -playbot:#other- true\n[10:25:23] -playbot- false\n".as_bytes();
+        let channels = mech.decode(&ctx, &mut input)
+                           .map(|e| e.unwrap().channel.map(|c| c.into_owned()))
+                           .collect::<Vec<_>>();
+        assert_eq!(channels, vec![Some("#other".to_owned()), Some("#example".to_owned())]);
+        assert_eq!(decode(&mech, &ctx, "[10:25:22] -playbot:#other- true\n")[0].ty,
+                   Type::Notice {
+                       from: Cow::Borrowed("playbot"),
+                       content: Cow::Borrowed("true"),
+                   });
+        roundtrip("[10:25:22] -playbot:#other- true\n");
+        roundtrip("[10:25:23] -playbot- false\n");
+    }
+}
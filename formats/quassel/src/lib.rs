@@ -0,0 +1,422 @@
+//! The backlog a Quassel core keeps in its SQLite database.
+//!
+//! Like the SQLite format, this reads the database file it was opened with, not the input it
+//! is given. Every message is stored in a buffer, which is a channel, a query or the status
+//! buffer of a network, and its type is one of the flags of Quassel's `Message::Type`.
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate rusqlite;
+extern crate ilc_base;
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OpenFlags};
+
+use ilc_base::event::{Event, Time, Type};
+use ilc_base::{Context, Decode, Error, Result};
+
+const PLAIN: i64 = 0x00001;
+const NOTICE: i64 = 0x00002;
+const ACTION: i64 = 0x00004;
+const NICK: i64 = 0x00008;
+const MODE: i64 = 0x00010;
+const JOIN: i64 = 0x00020;
+const PART: i64 = 0x00040;
+const QUIT: i64 = 0x00080;
+const KICK: i64 = 0x00100;
+const TOPIC: i64 = 0x04000;
+const NETSPLIT_JOIN: i64 = 0x08000;
+const NETSPLIT_QUIT: i64 = 0x10000;
+
+/// `buffer.buffertype` of the status buffer of a network, which has no channel
+const STATUS_BUFFER: i64 = 0x01;
+
+/// Separates the nicks of a netsplit message, the servers come last.
+const NETSPLIT_SEPARATOR: &'static str = "#:#";
+
+/// How many messages are read from the database at once.
+const PAGE: i64 = 1024;
+
+/// The messages after the one with id ?1, of the network named ?2 if given.
+static PAGE_QUERY: &'static str = "
+    SELECT backlog.messageid, backlog.time, backlog.type, backlog.message,
+           sender.sender, buffer.buffername, buffer.buffertype
+    FROM backlog
+    JOIN buffer ON buffer.bufferid = backlog.bufferid
+    JOIN sender ON sender.senderid = backlog.senderid
+    JOIN network ON network.networkid = buffer.networkid
+    WHERE backlog.messageid > ?1 AND (?2 IS NULL OR network.networkname = ?2)
+    ORDER BY backlog.messageid
+    LIMIT ?3
+";
+
+fn sql(e: rusqlite::Error) -> Error {
+    Error::Custom(Box::new(e))
+}
+
+/// Quassel stores seconds, but milliseconds since schema version 31. Both are told apart by
+/// size, as seconds that large are thousands of years away.
+fn timestamp(time: i64) -> i64 {
+    if time > 100000000000 { time / 1000 } else { time }
+}
+
+/// A sender is stored as `nick!user@host`, or just a nick or server name.
+fn split_sender(sender: &str) -> (String, Option<String>) {
+    match sender.find('!') {
+        Some(i) => (sender[..i].to_owned(), Some(sender[i + 1..].to_owned())),
+        None => (sender.to_owned(), None),
+    }
+}
+
+/// A row of the backlog.
+struct Message {
+    id: i64,
+    time: i64,
+    ty: i64,
+    text: String,
+    sender: String,
+    buffer: String,
+    buffer_type: i64,
+}
+
+/// The events a message stands for. Those of types that can't be represented, like server
+/// replies and day changes, stand for none, and netsplits for a join or quit of each nick.
+fn events(m: Message) -> Vec<Event<'static>> {
+    let (nick, mask) = split_sender(&m.sender);
+    let own = |s: &str| Cow::Owned(s.to_owned());
+    let types = match m.ty {
+        PLAIN => {
+            vec![Type::Msg {
+                     from: own(&nick),
+                     content: own(&m.text),
+                 }]
+        }
+        NOTICE => {
+            vec![Type::Notice {
+                     from: own(&nick),
+                     content: own(&m.text),
+                 }]
+        }
+        ACTION => {
+            vec![Type::Action {
+                     from: own(&nick),
+                     content: own(&m.text),
+                 }]
+        }
+        NICK => {
+            vec![Type::Nick {
+                     old_nick: own(&nick),
+                     new_nick: own(&m.text),
+                 }]
+        }
+        MODE => {
+            let mut parts = m.text.splitn(2, ' ');
+            vec![Type::Mode {
+                     nick: Some(own(&nick)),
+                     mode: own(parts.next().unwrap_or("")),
+                     masks: own(parts.next().unwrap_or("")),
+                 }]
+        }
+        JOIN => {
+            vec![Type::Join {
+                     nick: own(&nick),
+                     mask: mask.map(Cow::Owned),
+                 }]
+        }
+        PART => {
+            vec![Type::Part {
+                     nick: own(&nick),
+                     mask: mask.map(Cow::Owned),
+                     reason: if m.text.is_empty() { None } else { Some(own(&m.text)) },
+                 }]
+        }
+        QUIT => {
+            vec![Type::Quit {
+                     nick: own(&nick),
+                     mask: mask.map(Cow::Owned),
+                     reason: if m.text.is_empty() { None } else { Some(own(&m.text)) },
+                 }]
+        }
+        KICK => {
+            // the kicked nick, then the reason
+            let mut parts = m.text.splitn(2, ' ');
+            vec![Type::Kick {
+                     kicked_nick: own(parts.next().unwrap_or("")),
+                     kicking_nick: Some(own(&nick)),
+                     kick_message: parts.next().map(own),
+                 }]
+        }
+        TOPIC => topic(&nick, &m.text).into_iter().collect(),
+        NETSPLIT_JOIN | NETSPLIT_QUIT => {
+            let mut parts: Vec<&str> = m.text.split(NETSPLIT_SEPARATOR).collect();
+            let servers = parts.pop().unwrap_or("");
+            parts.into_iter()
+                 .map(|sender| {
+                     let (nick, mask) = split_sender(sender);
+                     if m.ty == NETSPLIT_JOIN {
+                         Type::Join {
+                             nick: Cow::Owned(nick),
+                             mask: mask.map(Cow::Owned),
+                         }
+                     } else {
+                         Type::Quit {
+                             nick: Cow::Owned(nick),
+                             mask: mask.map(Cow::Owned),
+                             reason: Some(own(servers)),
+                         }
+                     }
+                 })
+                 .collect()
+        }
+        _ => {
+            debug!("Skipping Quassel message {} of type {:#x}", m.id, m.ty);
+            Vec::new()
+        }
+    };
+    let time = Time::Timestamp(timestamp(m.time));
+    let channel = if m.buffer_type == STATUS_BUFFER { None } else { Some(m.buffer) };
+    types.into_iter()
+         .map(|ty| {
+             Event {
+                 ty: ty,
+                 time: time.clone(),
+                 channel: channel.clone().map(Cow::Owned),
+             }
+         })
+         .collect()
+}
+
+/// Topic messages are stored the way the Quassel core words them, in English.
+fn topic(nick: &str, text: &str) -> Option<Type<'static>> {
+    let quoted = |s: &str| {
+        if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+            Some(s[1..s.len() - 1].to_owned())
+        } else {
+            None
+        }
+    };
+    if let Some(i) = text.find(" to: ") {
+        if text.contains(" has changed topic for ") {
+            return quoted(&text[i + 5..]).map(|topic| {
+                Type::TopicChange {
+                    nick: Some(Cow::Owned(nick.to_owned())),
+                    new_topic: Cow::Owned(topic),
+                }
+            });
+        }
+    }
+    if text.contains(" has cleared topic for ") {
+        return Some(Type::TopicChange {
+            nick: Some(Cow::Owned(nick.to_owned())),
+            new_topic: Cow::Borrowed(""),
+        });
+    }
+    if text.starts_with("Topic for ") {
+        if let Some(i) = text.find(" is ") {
+            return quoted(&text[i + 4..]).map(|topic| Type::Topic { topic: Cow::Owned(topic) });
+        }
+    }
+    // who set the topic and when, or that there is none
+    None
+}
+
+pub struct Quassel {
+    conn: Mutex<Connection>,
+    /// Only read the buffers of this network
+    network: Option<String>,
+}
+
+impl Quassel {
+    /// Open a Quassel database to read the backlog of `network`, or of all networks.
+    pub fn open<P: AsRef<Path>>(path: P, network: Option<String>) -> Result<Quassel> {
+        let conn = try!(Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                            .map_err(sql));
+        Ok(Quassel {
+            conn: Mutex::new(conn),
+            network: network,
+        })
+    }
+
+    /// The names of the networks with buffers in the database. Channels are known by their
+    /// name, so those of the same name on different networks are read as one, unless only one
+    /// network is read.
+    pub fn networks(&self) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut select = try!(conn.prepare("SELECT DISTINCT network.networkname FROM network \
+                                            JOIN buffer ON buffer.networkid = \
+                                            network.networkid ORDER BY network.networkname")
+                                  .map_err(sql));
+        let names = try!(select.query_map(params![], |row| row.get(0)).map_err(sql));
+        names.collect::<rusqlite::Result<_>>().map_err(sql)
+    }
+
+    /// The next messages after the one with id `after`.
+    fn page(&self, after: i64) -> rusqlite::Result<Vec<Message>> {
+        let conn = self.conn.lock().unwrap();
+        let mut select = try!(conn.prepare_cached(PAGE_QUERY));
+        let rows = try!(select.query_map(params![after, self.network, PAGE], |row| {
+            Ok(Message {
+                id: try!(row.get(0)),
+                time: try!(row.get(1)),
+                ty: try!(row.get(2)),
+                text: try!(row.get::<_, Option<String>>(3)).unwrap_or_default(),
+                sender: try!(row.get(4)),
+                buffer: try!(row.get(5)),
+                buffer_type: try!(row.get(6)),
+            })
+        }));
+        rows.collect()
+    }
+}
+
+pub struct Iter<'a> {
+    quassel: &'a Quassel,
+    /// The id of the last message read
+    last: i64,
+    buffer: VecDeque<Event<'static>>,
+    done: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<Event<'a>>;
+    fn next(&mut self) -> Option<Result<Event<'a>>> {
+        while self.buffer.is_empty() && !self.done {
+            match self.quassel.page(self.last) {
+                Ok(page) => {
+                    self.done = (page.len() as i64) < PAGE;
+                    for m in page {
+                        self.last = m.id;
+                        self.buffer.extend(events(m));
+                    }
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(sql(e)));
+                }
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+impl Decode for Quassel {
+    fn decode<'a>(&'a self,
+                  _context: &'a Context,
+                  _input: &'a mut BufRead)
+                  -> Box<Iterator<Item = Result<Event<'a>>> + 'a> {
+        Box::new(Iter {
+            quassel: self,
+            last: 0,
+            buffer: VecDeque::new(),
+            done: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use ilc_base::event::{Event, Time, Type};
+
+    use super::{Message, events, topic};
+    use super::{JOIN, KICK, MODE, NETSPLIT_QUIT, PLAIN, STATUS_BUFFER, TOPIC};
+
+    fn message(ty: i64, sender: &str, text: &str) -> Message {
+        Message {
+            id: 1,
+            time: 1456362905000,
+            ty: ty,
+            text: text.to_owned(),
+            sender: sender.to_owned(),
+            buffer: "#ilc".to_owned(),
+            buffer_type: 0x02,
+        }
+    }
+
+    fn types(m: Message) -> Vec<Type<'static>> {
+        events(m).into_iter().map(|e| e.ty).collect()
+    }
+
+    fn s(s: &'static str) -> Cow<'static, str> {
+        Cow::Borrowed(s)
+    }
+
+    #[test]
+    fn message_events() {
+        assert_eq!(events(message(PLAIN, "Foo!foo@example.com", "hello")),
+                   vec![Event {
+                            ty: Type::Msg {
+                                from: s("Foo"),
+                                content: s("hello"),
+                            },
+                            time: Time::Timestamp(1456362905),
+                            channel: Some(s("#ilc")),
+                        }]);
+        assert_eq!(types(message(JOIN, "Foo!foo@example.com", "#ilc")),
+                   vec![Type::Join {
+                            nick: s("Foo"),
+                            mask: Some(s("foo@example.com")),
+                        }]);
+        assert_eq!(types(message(MODE, "Foo", "+o Bar")),
+                   vec![Type::Mode {
+                            nick: Some(s("Foo")),
+                            mode: s("+o"),
+                            masks: s("Bar"),
+                        }]);
+        assert_eq!(types(message(KICK, "Foo!f@h", "Bar go away")),
+                   vec![Type::Kick {
+                            kicked_nick: s("Bar"),
+                            kicking_nick: Some(s("Foo")),
+                            kick_message: Some(s("go away")),
+                        }]);
+    }
+
+    #[test]
+    fn netsplits_and_skipped() {
+        assert_eq!(types(message(NETSPLIT_QUIT, "", "Foo!f@h#:#Bar#:#a.net b.net")),
+                   vec![Type::Quit {
+                            nick: s("Foo"),
+                            mask: Some(s("f@h")),
+                            reason: Some(s("a.net b.net")),
+                        },
+                        Type::Quit {
+                            nick: s("Bar"),
+                            mask: None,
+                            reason: Some(s("a.net b.net")),
+                        }]);
+        // a day change
+        assert!(events(message(0x02000, "", "")).is_empty());
+        let mut status = message(PLAIN, "server", "hi");
+        status.buffer_type = STATUS_BUFFER;
+        status.time = 1456362905;
+        let e = events(status);
+        assert_eq!(e[0].channel, None);
+        assert_eq!(e[0].time, Time::Timestamp(1456362905));
+    }
+
+    #[test]
+    fn topics() {
+        assert_eq!(topic("Foo", "Foo has changed topic for #ilc to: \"Welcome\""),
+                   Some(Type::TopicChange {
+                       nick: Some(s("Foo")),
+                       new_topic: s("Welcome"),
+                   }));
+        assert_eq!(topic("Foo", "Foo has cleared topic for #ilc"),
+                   Some(Type::TopicChange {
+                       nick: Some(s("Foo")),
+                       new_topic: s(""),
+                   }));
+        assert_eq!(topic("", "Topic for #ilc is \"Welcome: to it\""),
+                   Some(Type::Topic { topic: s("Welcome: to it") }));
+        assert_eq!(topic("", "Topic set by Foo on Thu Feb 25 2016"), None);
+        assert_eq!(topic("", "No topic is set for #ilc."), None);
+        assert_eq!(types(message(TOPIC, "Foo", "Topic for #ilc is \"Hi\"")),
+                   vec![Type::Topic { topic: s("Hi") }]);
+    }
+}
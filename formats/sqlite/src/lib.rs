@@ -0,0 +1,607 @@
+//! Events in an SQLite database, to run SQL over them.
+//!
+//! Unlike the other formats, this one doesn't use the input and output streams it is given,
+//! but the database file it was opened with. Nicks, channels and networks are kept in tables
+//! of their own, events refer to them, and the `log` view joins them back together.
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate rusqlite;
+extern crate ilc_base;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Row};
+
+use ilc_base::event::{Event, Time, Type};
+use ilc_base::{Context, Decode, Encode, Error, Result};
+
+static SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS networks (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS channels (
+        id INTEGER PRIMARY KEY,
+        network INTEGER REFERENCES networks (id),
+        name TEXT NOT NULL,
+        UNIQUE (network, name)
+    );
+    CREATE TABLE IF NOT EXISTS nicks (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+    -- which columns are used depends on the type, see `Sqlite::insert`
+    CREATE TABLE IF NOT EXISTS events (
+        id INTEGER PRIMARY KEY,
+        type TEXT NOT NULL,
+        -- Unix timestamp, or just the time of day as HH:MM:SS if the date is unknown
+        time INTEGER,
+        hms TEXT,
+        network INTEGER REFERENCES networks (id),
+        channel INTEGER REFERENCES channels (id),
+        nick INTEGER REFERENCES nicks (id),
+        other_nick INTEGER REFERENCES nicks (id),
+        mask TEXT,
+        text TEXT,
+        mode TEXT,
+        mode_args TEXT
+    );
+    CREATE INDEX IF NOT EXISTS events_time ON events (time);
+    CREATE INDEX IF NOT EXISTS events_nick ON events (nick);
+    CREATE INDEX IF NOT EXISTS events_other_nick ON events (other_nick);
+    CREATE INDEX IF NOT EXISTS events_channel ON events (channel);
+    CREATE VIEW IF NOT EXISTS log AS
+        SELECT events.id, events.type, events.time, events.hms,
+               networks.name AS network, channels.name AS channel,
+               nicks.name AS nick, others.name AS other_nick,
+               events.mask, events.text, events.mode, events.mode_args
+        FROM events
+        LEFT JOIN networks ON networks.id = events.network
+        LEFT JOIN channels ON channels.id = events.channel
+        LEFT JOIN nicks ON nicks.id = events.nick
+        LEFT JOIN nicks AS others ON others.id = events.other_nick;
+";
+
+/// How many events are read from the database at once.
+const PAGE: i64 = 1024;
+
+fn sql(e: rusqlite::Error) -> Error {
+    Error::Custom(Box::new(e))
+}
+
+struct Db {
+    conn: Connection,
+    /// The ids of things already in the database
+    networks: HashMap<String, i64>,
+    channels: HashMap<(Option<i64>, String), i64>,
+    nicks: HashMap<String, i64>,
+    /// Whether events were written since the last commit
+    dirty: bool,
+}
+
+impl Db {
+    fn network(&mut self, name: &str) -> rusqlite::Result<i64> {
+        if let Some(&id) = self.networks.get(name) {
+            return Ok(id);
+        }
+        try!(self.conn.execute("INSERT OR IGNORE INTO networks (name) VALUES (?1)",
+                               params![name]));
+        let id = try!(self.conn.query_row("SELECT id FROM networks WHERE name = ?1",
+                                          params![name],
+                                          |row| row.get(0)));
+        self.networks.insert(name.to_owned(), id);
+        Ok(id)
+    }
+
+    fn channel(&mut self, network: Option<i64>, name: &str) -> rusqlite::Result<i64> {
+        let key = (network, name.to_owned());
+        if let Some(&id) = self.channels.get(&key) {
+            return Ok(id);
+        }
+        // UNIQUE doesn't catch channels without a network, as NULLs are all distinct
+        let found = try!(self.conn
+                             .query_row("SELECT id FROM channels WHERE network IS ?1 AND name = ?2",
+                                        params![network, name],
+                                        |row| row.get(0))
+                             .optional());
+        let id = match found {
+            Some(id) => id,
+            None => {
+                try!(self.conn.execute("INSERT INTO channels (network, name) VALUES (?1, ?2)",
+                                       params![network, name]));
+                self.conn.last_insert_rowid()
+            }
+        };
+        self.channels.insert(key, id);
+        Ok(id)
+    }
+
+    fn nick(&mut self, name: &str) -> rusqlite::Result<i64> {
+        if let Some(&id) = self.nicks.get(name) {
+            return Ok(id);
+        }
+        try!(self.conn.execute("INSERT OR IGNORE INTO nicks (name) VALUES (?1)", params![name]));
+        let id = try!(self.conn.query_row("SELECT id FROM nicks WHERE name = ?1",
+                                          params![name],
+                                          |row| row.get(0)));
+        self.nicks.insert(name.to_owned(), id);
+        Ok(id)
+    }
+
+    fn commit(&mut self) -> rusqlite::Result<()> {
+        if self.dirty {
+            try!(self.conn.execute_batch("COMMIT"));
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+/// The columns of an event, other than its time and where it happened.
+#[derive(Default)]
+struct Columns<'a> {
+    nick: Option<&'a str>,
+    other_nick: Option<&'a str>,
+    mask: Option<&'a str>,
+    text: Option<&'a str>,
+    mode: Option<&'a str>,
+    mode_args: Option<&'a str>,
+}
+
+fn columns<'a>(ty: &'a Type) -> Columns<'a> {
+    use ilc_base::event::Type::*;
+    fn opt<'a>(s: &'a Option<Cow<str>>) -> Option<&'a str> {
+        s.as_ref().map(|s| s as &str)
+    }
+    match ty {
+        &Connect | &Disconnect => Columns::default(),
+        &Msg { ref from, ref content } |
+        &Action { ref from, ref content } |
+        &Notice { ref from, ref content } |
+        &Ctcp { ref from, ref content } => {
+            Columns {
+                nick: Some(from),
+                text: Some(content),
+                ..Columns::default()
+            }
+        }
+        &Join { ref nick, ref mask } => {
+            Columns {
+                nick: Some(nick),
+                mask: opt(mask),
+                ..Columns::default()
+            }
+        }
+        &Part { ref nick, ref mask, ref reason } |
+        &Quit { ref nick, ref mask, ref reason } => {
+            Columns {
+                nick: Some(nick),
+                mask: opt(mask),
+                text: opt(reason),
+                ..Columns::default()
+            }
+        }
+        &Nick { ref old_nick, ref new_nick } => {
+            Columns {
+                nick: Some(old_nick),
+                other_nick: Some(new_nick),
+                ..Columns::default()
+            }
+        }
+        &Kick { ref kicked_nick, ref kicking_nick, ref kick_message } => {
+            Columns {
+                nick: opt(kicking_nick),
+                other_nick: Some(kicked_nick),
+                text: opt(kick_message),
+                ..Columns::default()
+            }
+        }
+        &Topic { ref topic } => {
+            Columns {
+                text: Some(topic),
+                ..Columns::default()
+            }
+        }
+        &TopicChange { ref nick, ref new_topic } => {
+            Columns {
+                nick: opt(nick),
+                text: Some(new_topic),
+                ..Columns::default()
+            }
+        }
+        &Mode { ref nick, ref mode, ref masks } => {
+            Columns {
+                nick: opt(nick),
+                mode: Some(mode),
+                mode_args: Some(masks),
+                ..Columns::default()
+            }
+        }
+        &Away { ref nick, ref reason } => {
+            Columns {
+                nick: Some(nick),
+                text: opt(reason),
+                ..Columns::default()
+            }
+        }
+    }
+}
+
+/// Turn a row of the `log` view back into an event.
+fn event(row: &Row) -> rusqlite::Result<Result<Event<'static>>> {
+    let ty: String = try!(row.get("type"));
+    let time: Option<i64> = try!(row.get("time"));
+    let hms: Option<String> = try!(row.get("hms"));
+    let channel: Option<String> = try!(row.get("channel"));
+    let nick: Option<String> = try!(row.get("nick"));
+    let other_nick: Option<String> = try!(row.get("other_nick"));
+    let mask: Option<String> = try!(row.get("mask"));
+    let text: Option<String> = try!(row.get("text"));
+    let mode: Option<String> = try!(row.get("mode"));
+    let mode_args: Option<String> = try!(row.get("mode_args"));
+
+    let time = match (time, hms) {
+        (Some(t), _) => Time::Timestamp(t),
+        (None, Some(hms)) => {
+            let parts: Vec<u8> = hms.split(':').filter_map(|p| p.parse().ok()).collect();
+            if parts.len() == 3 {
+                Time::Hms(parts[0], parts[1], parts[2])
+            } else {
+                Time::Unknown
+            }
+        }
+        (None, None) => Time::Unknown,
+    };
+    let missing = |field: &str| Error::Parse(format!("{} event without {}", ty, field));
+    let need = |value: Option<String>, field: &str| value.map(Cow::Owned).ok_or(missing(field));
+    let opt = |value: Option<String>| value.map(Cow::Owned);
+
+    let ty = match &ty[..] {
+        "connect" => Ok(Type::Connect),
+        "disconnect" => Ok(Type::Disconnect),
+        "message" | "action" | "notice" | "ctcp" => {
+            need(nick, "nick").and_then(|from| {
+                need(text, "text").map(|content| {
+                    match &ty[..] {
+                        "message" => Type::Msg { from: from, content: content },
+                        "action" => Type::Action { from: from, content: content },
+                        "notice" => Type::Notice { from: from, content: content },
+                        _ => Type::Ctcp { from: from, content: content },
+                    }
+                })
+            })
+        }
+        "join" => {
+            need(nick, "nick").map(|nick| {
+                Type::Join {
+                    nick: nick,
+                    mask: opt(mask),
+                }
+            })
+        }
+        "part" => {
+            need(nick, "nick").map(|nick| {
+                Type::Part {
+                    nick: nick,
+                    mask: opt(mask),
+                    reason: opt(text),
+                }
+            })
+        }
+        "quit" => {
+            need(nick, "nick").map(|nick| {
+                Type::Quit {
+                    nick: nick,
+                    mask: opt(mask),
+                    reason: opt(text),
+                }
+            })
+        }
+        "nick" => {
+            need(nick, "nick").and_then(|old| {
+                need(other_nick, "other_nick").map(|new| {
+                    Type::Nick {
+                        old_nick: old,
+                        new_nick: new,
+                    }
+                })
+            })
+        }
+        "kick" => {
+            need(other_nick, "other_nick").map(|kicked| {
+                Type::Kick {
+                    kicked_nick: kicked,
+                    kicking_nick: opt(nick),
+                    kick_message: opt(text),
+                }
+            })
+        }
+        "topic" => need(text, "text").map(|topic| Type::Topic { topic: topic }),
+        "topic_change" => {
+            need(text, "text").map(|topic| {
+                Type::TopicChange {
+                    nick: opt(nick),
+                    new_topic: topic,
+                }
+            })
+        }
+        "mode" => {
+            need(mode, "mode").map(|mode| {
+                Type::Mode {
+                    nick: opt(nick),
+                    mode: mode,
+                    masks: opt(mode_args).unwrap_or(Cow::Borrowed("")),
+                }
+            })
+        }
+        "away" => {
+            need(nick, "nick").map(|nick| {
+                Type::Away {
+                    nick: nick,
+                    reason: opt(text),
+                }
+            })
+        }
+        _ => Err(Error::Parse(format!("Unknown event type `{}`", ty))),
+    };
+    Ok(ty.map(|ty| {
+        Event {
+            ty: ty,
+            time: time,
+            channel: channel.map(Cow::Owned),
+        }
+    }))
+}
+
+pub struct Sqlite {
+    db: Mutex<Db>,
+    /// The network of the events written
+    network: Option<String>,
+    /// An SQL condition on the `log` view, for the events read
+    query: Option<String>,
+}
+
+impl Sqlite {
+    /// Open a database to write events of `network` into, creating it if needed. They are
+    /// committed when the `Sqlite` is dropped.
+    pub fn create<P: AsRef<Path>>(path: P, network: Option<String>) -> Result<Sqlite> {
+        let conn = try!(Connection::open(path).map_err(sql));
+        try!(conn.execute_batch(SCHEMA).map_err(sql));
+        Ok(Sqlite::new(conn, network, None))
+    }
+
+    /// Open an existing database to read the events matching `query` from.
+    pub fn open<P: AsRef<Path>>(path: P, query: Option<String>) -> Result<Sqlite> {
+        let conn = try!(Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                            .map_err(sql));
+        Ok(Sqlite::new(conn, None, query))
+    }
+
+    fn new(conn: Connection, network: Option<String>, query: Option<String>) -> Sqlite {
+        Sqlite {
+            db: Mutex::new(Db {
+                conn: conn,
+                networks: HashMap::new(),
+                channels: HashMap::new(),
+                nicks: HashMap::new(),
+                dirty: false,
+            }),
+            network: network,
+            query: query,
+        }
+    }
+
+    fn insert(&self, event: &Event) -> rusqlite::Result<()> {
+        let mut db = self.db.lock().unwrap();
+        if !db.dirty {
+            try!(db.conn.execute_batch("BEGIN"));
+            db.dirty = true;
+        }
+        let (time, hms) = match event.time {
+            Time::Timestamp(t) => (Some(t), None),
+            Time::Hms(h, m, s) => (None, Some(format!("{:02}:{:02}:{:02}", h, m, s))),
+            Time::Unknown => (None, None),
+        };
+        let network = match self.network {
+            Some(ref name) => Some(try!(db.network(name))),
+            None => None,
+        };
+        let channel = match event.channel {
+            Some(ref name) => Some(try!(db.channel(network, name))),
+            None => None,
+        };
+        let c = columns(&event.ty);
+        let nick = match c.nick {
+            Some(name) => Some(try!(db.nick(name))),
+            None => None,
+        };
+        let other_nick = match c.other_nick {
+            Some(name) => Some(try!(db.nick(name))),
+            None => None,
+        };
+        let mut insert = try!(db.conn.prepare_cached("INSERT INTO events (type, time, hms, \
+                                                      network, channel, nick, other_nick, mask, \
+                                                      text, mode, mode_args) VALUES (?1, ?2, \
+                                                      ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"));
+        try!(insert.execute(params![event.ty.type_desc(),
+                                    time,
+                                    hms,
+                                    network,
+                                    channel,
+                                    nick,
+                                    other_nick,
+                                    c.mask,
+                                    c.text,
+                                    c.mode,
+                                    c.mode_args]));
+        Ok(())
+    }
+
+    /// The next events matching the query after the event with id `after`, with their ids.
+    fn page(&self, after: i64) -> rusqlite::Result<Vec<(i64, Result<Event<'static>>)>> {
+        let db = self.db.lock().unwrap();
+        let query = format!("SELECT * FROM log WHERE id > ?1 AND ({}) ORDER BY id LIMIT ?2",
+                            self.query.as_ref().map_or("1", |q| q as &str));
+        let mut select = try!(db.conn.prepare_cached(&query));
+        let rows = try!(select.query_map(params![after, PAGE], |row| {
+            let id = try!(row.get("id"));
+            event(row).map(|e| (id, e))
+        }));
+        rows.collect()
+    }
+}
+
+impl Drop for Sqlite {
+    fn drop(&mut self) {
+        if let Ok(mut db) = self.db.lock() {
+            if let Err(e) = db.commit() {
+                error!("Couldn't commit the events to the database: {}", e);
+            }
+        }
+    }
+}
+
+pub struct Iter<'a> {
+    sqlite: &'a Sqlite,
+    /// The id of the last event read
+    last: i64,
+    buffer: VecDeque<Result<Event<'static>>>,
+    done: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<Event<'a>>;
+    fn next(&mut self) -> Option<Result<Event<'a>>> {
+        if self.buffer.is_empty() && !self.done {
+            match self.sqlite.page(self.last) {
+                Ok(page) => {
+                    self.done = (page.len() as i64) < PAGE;
+                    if let Some(&(id, _)) = page.last() {
+                        self.last = id;
+                    }
+                    self.buffer.extend(page.into_iter().map(|(_, e)| e));
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(sql(e)));
+                }
+            }
+        }
+        self.buffer.pop_front()
+    }
+}
+
+impl Decode for Sqlite {
+    fn decode<'a>(&'a self,
+                  _context: &'a Context,
+                  _input: &'a mut BufRead)
+                  -> Box<Iterator<Item = Result<Event<'a>>> + 'a> {
+        Box::new(Iter {
+            sqlite: self,
+            last: 0,
+            buffer: VecDeque::new(),
+            done: false,
+        })
+    }
+}
+
+impl Encode for Sqlite {
+    fn encode<'a>(&'a self,
+                  _context: &'a Context,
+                  _output: &'a mut Write,
+                  event: &'a Event)
+                  -> Result<()> {
+        self.insert(event).map_err(sql)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use rusqlite::Connection;
+
+    use ilc_base::event::{Event, Time, Type};
+    use ilc_base::{Context, Decode, Encode};
+
+    use super::{SCHEMA, Sqlite};
+
+    fn s(s: &'static str) -> Cow<'static, str> {
+        Cow::Borrowed(s)
+    }
+
+    fn events() -> Vec<Event<'static>> {
+        let types = vec![Type::Connect,
+                         Type::Disconnect,
+                         Type::Msg { from: s("Foo"), content: s("hello there") },
+                         Type::Action { from: s("Foo"), content: s("waves") },
+                         Type::Notice { from: s("Bar"), content: s("hi") },
+                         Type::Ctcp { from: s("Bar"), content: s("VERSION") },
+                         Type::Join { nick: s("Baz"), mask: Some(s("baz@example.com")) },
+                         Type::Join { nick: s("Qux"), mask: None },
+                         Type::Part { nick: s("Baz"), mask: None, reason: Some(s("bye")) },
+                         Type::Quit { nick: s("Qux"), mask: Some(s("q@h")), reason: None },
+                         Type::Nick { old_nick: s("Foo"), new_nick: s("Foo_") },
+                         Type::Kick {
+                             kicked_nick: s("Bar"),
+                             kicking_nick: Some(s("Foo_")),
+                             kick_message: None,
+                         },
+                         Type::Topic { topic: s("Welcome") },
+                         Type::TopicChange { nick: None, new_topic: s("Welcome back") },
+                         Type::Mode { nick: Some(s("Foo_")), mode: s("+o"), masks: s("Baz") },
+                         Type::Mode { nick: None, mode: s("+n"), masks: s("") },
+                         Type::Away { nick: s("Foo_"), reason: Some(s("lunch")) }];
+        let times = vec![Time::Timestamp(1456362905), Time::Hms(10, 5, 0), Time::Unknown];
+        types.into_iter()
+             .enumerate()
+             .map(|(i, ty)| {
+                 Event {
+                     ty: ty,
+                     time: times[i % times.len()].clone(),
+                     channel: if i % 4 == 3 { None } else { Some(s("#ilc")) },
+                 }
+             })
+             .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let sqlite = Sqlite::new(conn, Some("example".to_owned()), None);
+        let ctx = Context::default();
+        let mut out: Vec<u8> = Vec::new();
+        for e in &events() {
+            sqlite.encode(&ctx, &mut out, e).unwrap();
+        }
+        let mut input: &[u8] = &[];
+        let decoded: Vec<Event> = sqlite.decode(&ctx, &mut input).map(Result::unwrap).collect();
+        assert_eq!(decoded, events());
+        // nothing is written to the stream
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn query() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let ctx = Context::default();
+        let mut out: Vec<u8> = Vec::new();
+        let mut sqlite = Sqlite::new(conn, None, None);
+        for e in &events() {
+            sqlite.encode(&ctx, &mut out, e).unwrap();
+        }
+        sqlite.query = Some("nick = 'Foo_'".to_owned());
+        let mut input: &[u8] = &[];
+        let decoded: Vec<String> = sqlite.decode(&ctx, &mut input)
+                                         .map(|e| e.unwrap().ty.type_desc().to_owned())
+                                         .collect();
+        assert_eq!(decoded, vec!["kick", "mode", "away"]);
+    }
+}
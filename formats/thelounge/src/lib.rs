@@ -0,0 +1,329 @@
+//! The history The Lounge keeps of each user in an SQLite database.
+//!
+//! Like the SQLite format, this reads the database file it was opened with, not the input it
+//! is given. Every row of the `messages` table has the network and channel of a message, its
+//! time in milliseconds, its type, and the rest of it as a JSON object.
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate rusqlite;
+extern crate rustc_serialize;
+extern crate ilc_base;
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::path::Path;
+use std::result;
+use std::sync::Mutex;
+
+use rusqlite::{Connection, OpenFlags};
+use rustc_serialize::json::Json;
+
+use ilc_base::event::{Event, Time, Type};
+use ilc_base::{Context, Decode, Error, Result};
+
+/// How many messages are read from the database at once.
+const PAGE: i64 = 1024;
+
+/// The messages after the one with rowid ?1, of the network with the id ?2 if given.
+static PAGE_QUERY: &'static str = "
+    SELECT rowid, channel, time, type, msg
+    FROM messages
+    WHERE rowid > ?1 AND (?2 IS NULL OR network = ?2)
+    ORDER BY rowid
+    LIMIT ?3
+";
+
+fn sql(e: rusqlite::Error) -> Error {
+    Error::Custom(Box::new(e))
+}
+
+/// A row of the `messages` table.
+struct Message {
+    id: i64,
+    channel: String,
+    time: i64,
+    ty: String,
+    msg: String,
+}
+
+/// The type of event a message stands for, if it can be represented, or the field it lacks.
+fn ty(m: &Message, msg: &Json) -> result::Result<Option<Type<'static>>, String> {
+    let get = |path: &[&str]| msg.find_path(path).and_then(Json::as_string).map(str::to_owned);
+    let need = |path: &[&str]| get(path).map(Cow::Owned).ok_or_else(|| path.join("."));
+    let opt = |path: &[&str]| {
+        get(path).and_then(|s| if s.is_empty() { None } else { Some(Cow::Owned(s)) })
+    };
+
+    let ty = match &m.ty[..] {
+        "message" => {
+            Type::Msg {
+                from: try!(need(&["from", "nick"])),
+                content: try!(need(&["text"])),
+            }
+        }
+        "action" => {
+            Type::Action {
+                from: try!(need(&["from", "nick"])),
+                content: try!(need(&["text"])),
+            }
+        }
+        "notice" => {
+            Type::Notice {
+                from: try!(need(&["from", "nick"])),
+                content: try!(need(&["text"])),
+            }
+        }
+        "join" => {
+            Type::Join {
+                nick: try!(need(&["from", "nick"])),
+                mask: opt(&["hostmask"]),
+            }
+        }
+        "part" => {
+            Type::Part {
+                nick: try!(need(&["from", "nick"])),
+                mask: opt(&["hostmask"]),
+                reason: opt(&["text"]),
+            }
+        }
+        "quit" => {
+            Type::Quit {
+                nick: try!(need(&["from", "nick"])),
+                mask: opt(&["hostmask"]),
+                reason: opt(&["text"]),
+            }
+        }
+        "nick" => {
+            Type::Nick {
+                old_nick: try!(need(&["from", "nick"])),
+                new_nick: try!(need(&["new_nick"])),
+            }
+        }
+        "mode" => {
+            // the mode, then its parameters
+            let text = try!(need(&["text"]));
+            let mut parts = text.splitn(2, ' ');
+            Type::Mode {
+                nick: opt(&["from", "nick"]),
+                mode: Cow::Owned(parts.next().unwrap_or("").to_owned()),
+                masks: Cow::Owned(parts.next().unwrap_or("").to_owned()),
+            }
+        }
+        "topic" => {
+            let topic = try!(need(&["text"]));
+            // the topic shown when joining has nobody who set it
+            match opt(&["from", "nick"]) {
+                Some(nick) => {
+                    Type::TopicChange {
+                        nick: Some(nick),
+                        new_topic: topic,
+                    }
+                }
+                None => Type::Topic { topic: topic },
+            }
+        }
+        "kick" => {
+            Type::Kick {
+                kicked_nick: try!(need(&["target", "nick"])),
+                kicking_nick: opt(&["from", "nick"]),
+                kick_message: opt(&["text"]),
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(ty))
+}
+
+/// The event a message stands for. Messages of types that can't be represented, or without
+/// the fields they need, are skipped.
+fn event(m: Message) -> Result<Option<Event<'static>>> {
+    let msg = try!(Json::from_str(&m.msg).map_err(|e| Error::Custom(Box::new(e))));
+    let ty = match ty(&m, &msg) {
+        Ok(Some(ty)) => ty,
+        Ok(None) => {
+            debug!("Skipping The Lounge message {} of type {}", m.id, m.ty);
+            return Ok(None);
+        }
+        Err(field) => {
+            debug!("Skipping The Lounge message {} of type {} without {}", m.id, m.ty, field);
+            return Ok(None);
+        }
+    };
+    Ok(Some(Event {
+        ty: ty,
+        time: Time::Timestamp(m.time / 1000),
+        channel: Some(Cow::Owned(m.channel)),
+    }))
+}
+
+pub struct TheLounge {
+    conn: Mutex<Connection>,
+    /// Only read the messages of the network with this id
+    network: Option<String>,
+}
+
+impl TheLounge {
+    pub fn open<P: AsRef<Path>>(path: P, network: Option<String>) -> Result<TheLounge> {
+        let conn = try!(Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                            .map_err(sql));
+        Ok(TheLounge {
+            conn: Mutex::new(conn),
+            network: network,
+        })
+    }
+
+    /// The next messages after the one with rowid `after`.
+    fn page(&self, after: i64) -> rusqlite::Result<Vec<Message>> {
+        let conn = self.conn.lock().unwrap();
+        let mut select = try!(conn.prepare_cached(PAGE_QUERY));
+        let rows = try!(select.query_map(params![after, self.network, PAGE], |row| {
+            Ok(Message {
+                id: try!(row.get(0)),
+                channel: try!(row.get(1)),
+                time: try!(row.get(2)),
+                ty: try!(row.get(3)),
+                msg: try!(row.get(4)),
+            })
+        }));
+        rows.collect()
+    }
+}
+
+pub struct Iter<'a> {
+    lounge: &'a TheLounge,
+    /// The rowid of the last message read
+    last: i64,
+    buffer: VecDeque<Message>,
+    done: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<Event<'a>>;
+    fn next(&mut self) -> Option<Result<Event<'a>>> {
+        loop {
+            if self.buffer.is_empty() && !self.done {
+                match self.lounge.page(self.last) {
+                    Ok(page) => {
+                        self.done = (page.len() as i64) < PAGE;
+                        if let Some(m) = page.last() {
+                            self.last = m.id;
+                        }
+                        self.buffer.extend(page);
+                    }
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(sql(e)));
+                    }
+                }
+            }
+            match self.buffer.pop_front().map(event) {
+                Some(Ok(None)) => continue,
+                Some(Ok(Some(e))) => return Some(Ok(e)),
+                Some(Err(e)) => return Some(Err(e)),
+                None => return None,
+            }
+        }
+    }
+}
+
+impl Decode for TheLounge {
+    fn decode<'a>(&'a self,
+                  _context: &'a Context,
+                  _input: &'a mut BufRead)
+                  -> Box<Iterator<Item = Result<Event<'a>>> + 'a> {
+        Box::new(Iter {
+            lounge: self,
+            last: 0,
+            buffer: VecDeque::new(),
+            done: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use ilc_base::event::{Event, Time, Type};
+
+    use super::{Message, event};
+
+    fn message(ty: &str, msg: &str) -> Message {
+        Message {
+            id: 1,
+            channel: "#ilc".to_owned(),
+            time: 1456362905123,
+            ty: ty.to_owned(),
+            msg: msg.to_owned(),
+        }
+    }
+
+    fn ty(ty: &str, msg: &str) -> Option<Type<'static>> {
+        event(message(ty, msg)).unwrap().map(|e| e.ty)
+    }
+
+    fn s(s: &'static str) -> Cow<'static, str> {
+        Cow::Borrowed(s)
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(event(message("message", r#"{"from": {"nick": "Foo"}, "text": "hi"}"#))
+                       .unwrap(),
+                   Some(Event {
+                       ty: Type::Msg {
+                           from: s("Foo"),
+                           content: s("hi"),
+                       },
+                       time: Time::Timestamp(1456362905),
+                       channel: Some(s("#ilc")),
+                   }));
+        assert_eq!(ty("join", r#"{"from": {"nick": "Foo"}, "hostmask": "f@h"}"#),
+                   Some(Type::Join {
+                       nick: s("Foo"),
+                       mask: Some(s("f@h")),
+                   }));
+        assert_eq!(ty("part", r#"{"from": {"nick": "Foo"}, "hostmask": "", "text": ""}"#),
+                   Some(Type::Part {
+                       nick: s("Foo"),
+                       mask: None,
+                       reason: None,
+                   }));
+        assert_eq!(ty("kick", r#"{"from": {"nick": "Foo"}, "target": {"nick": "Bar"}}"#),
+                   Some(Type::Kick {
+                       kicked_nick: s("Bar"),
+                       kicking_nick: Some(s("Foo")),
+                       kick_message: None,
+                   }));
+        assert_eq!(ty("mode", r#"{"from": {"nick": "Foo"}, "text": "+o Bar"}"#),
+                   Some(Type::Mode {
+                       nick: Some(s("Foo")),
+                       mode: s("+o"),
+                       masks: s("Bar"),
+                   }));
+    }
+
+    #[test]
+    fn topics() {
+        assert_eq!(ty("topic", r#"{"from": {"nick": "Foo"}, "text": "Welcome"}"#),
+                   Some(Type::TopicChange {
+                       nick: Some(s("Foo")),
+                       new_topic: s("Welcome"),
+                   }));
+        assert_eq!(ty("topic", r#"{"from": {}, "text": "Welcome"}"#),
+                   Some(Type::Topic { topic: s("Welcome") }));
+    }
+
+    #[test]
+    fn skipped() {
+        assert_eq!(ty("motd", r#"{"text": "Hello"}"#), None);
+        // without the fields they need
+        assert_eq!(ty("message", r#"{"text": "hi"}"#), None);
+        assert_eq!(ty("nick", r#"{"from": {"nick": "Foo"}}"#), None);
+        assert_eq!(ty("kick", r#"{"from": {"nick": "Foo"}, "target": {}}"#), None);
+        // but not broken JSON
+        assert!(event(message("message", "{")).is_err());
+    }
+}
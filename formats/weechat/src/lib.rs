@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate log;
+#[macro_use]
 extern crate ilc_base;
 
 use std::io::{BufRead, Write};
@@ -12,24 +13,78 @@ use ilc_base::format::{rejoin, strip_one};
 
 use log::LogLevel::Info;
 
-#[derive(Copy, Clone)]
-pub struct Weechat;
+/// The prefixes Weechat puts between timestamp and message for non-message lines.
+/// These are configurable in Weechat (`weechat.look.prefix_*`), so they are here too.
+#[derive(Clone, Debug)]
+pub struct Prefixes {
+    pub join: String,
+    pub quit: String,
+    pub network: String,
+    pub action: String,
+}
+
+impl Default for Prefixes {
+    fn default() -> Prefixes {
+        Prefixes {
+            join: "-->".to_owned(),
+            quit: "<--".to_owned(),
+            network: "--".to_owned(),
+            action: " *".to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Weechat {
+    pub prefixes: Prefixes,
+}
 
 static TIME_DATE_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S";
 
 pub struct Iter<'a> {
     context: &'a Context,
+    prefixes: &'a Prefixes,
     input: &'a mut BufRead,
     buffer: Vec<u8>,
 }
 
+/// `Mode #chan [+o foo] by bar`, `Mode #chan [+nt]` and `User mode [+i] by foo`,
+/// starting at the token containing the opening bracket.
+/// Returns mode, arguments and the setting nick, if any.
+fn parse_mode(tokens: &[&str], split_tokens: &[char]) -> Option<(String, String, Option<String>)> {
+    if tokens.is_empty() || !tokens[0].starts_with('[') {
+        return None;
+    }
+    let close = match tokens.iter().position(|t| t.ends_with(']')) {
+        Some(c) => c,
+        None => return None,
+    };
+    let inner = strip_one(&rejoin(&tokens[..close + 1], &split_tokens[..close + 1]));
+    let mut inner = inner.splitn(2, ' ');
+    let mode = inner.next().unwrap_or("").to_owned();
+    let args = inner.next().unwrap_or("").to_owned();
+    let nick = if tokens.len() > close + 2 && tokens[close + 1] == "by" {
+        Some(tokens[close + 2].to_owned())
+    } else {
+        None
+    };
+    Some((mode, args, nick))
+}
+
+/// Strip the quotes Weechat puts around topics
+fn unquote(s: &str) -> String {
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        strip_one(s)
+    } else {
+        s.to_owned()
+    }
+}
+
 impl<'a> Iterator for Iter<'a> {
     type Item = ilc_base::Result<Event<'a>>;
     fn next(&mut self) -> Option<ilc_base::Result<Event<'a>>> {
-        fn parse_time(c: &Context, date: &str, time: &str) -> Time {
-            Time::from_format(&c.timezone_in,
-                              &format!("{} {}", date, time),
-                              TIME_DATE_FORMAT)
+        fn parse_time(c: &Context, stamp: &str) -> Time {
+            Time::from_format(&c.timezone_in, stamp, TIME_DATE_FORMAT)
         }
 
         loop {
@@ -38,121 +93,297 @@ impl<'a> Iterator for Iter<'a> {
                 Ok(0) | Err(_) => return None,
                 Ok(_) => (),
             }
+            // `rejoin` relies on a trailing separator
+            if !self.buffer.ends_with(b"\n") {
+                self.buffer.push(b'\n');
+            }
 
             let buffer = String::from_utf8_lossy(&self.buffer);
 
+            // 2016-02-25 01:15:05 \t prefix \t message
+            let mut columns = buffer.splitn(3, '\t');
+            let (stamp, prefix, message) = match (columns.next(), columns.next(), columns.next()) {
+                (Some(s), Some(p), Some(m)) => (s, p, m),
+                _ => continue,
+            };
+            let time = parse_time(&self.context, stamp);
+
             let mut split_tokens: Vec<char> = Vec::new();
-            let tokens = buffer.split(|c: char| {
-                                   if c.is_whitespace() {
-                                       split_tokens.push(c);
-                                       true
-                                   } else {
-                                       false
-                                   }
-                               })
-                               .collect::<Vec<_>>();
+            let tokens = message.split(|c: char| {
+                                    if c.is_whitespace() {
+                                        split_tokens.push(c);
+                                        true
+                                    } else {
+                                        false
+                                    }
+                                })
+                                .collect::<Vec<_>>();
 
             if log_enabled!(Info) {
                 info!("Original:  `{}`", buffer);
-                info!("Parsing:   {:?}", tokens);
+                info!("Parsing:   {:?} {:?}", prefix, tokens);
             }
 
             // slice pattern matching is not stable as of Feb. 2016 and was replaced with
             // nested if-else chains in this module.
 
-            // Don't match on the --> arrows, those are apparently often configured, and
-            // that would break parsing for many users.
+            // Lines are told apart by their prefix, which is configurable in Weechat, and
+            // may be configured here as well.
 
             let len = tokens.len();
 
-            // 2016-02-25 01:15:05 --> Foo (host@mask.foo) has joined #example
-            if len >= 8 && tokens[5] == "has" && tokens[6] == "joined" {
-                return Some(Ok(Event {
-                    ty: Type::Join {
-                        nick: tokens[3].to_owned().into(),
-                        mask: Some(strip_one(tokens[4]).into()),
-                    },
-                    channel: Some(tokens[7].to_owned().into()),
-                    time: parse_time(&self.context, tokens[0], tokens[1]),
-                }));
-            }
-            // 2016-02-25 01:36:13 <-- Foo (host@mask.foo) has left #channel (Some reason)
-            else if len >= 9 && tokens[5] == "has" && tokens[6] == "left" {
-                return Some(Ok(Event {
-                    ty: Type::Part {
-                        nick: tokens[3].to_owned().into(),
-                        mask: Some(strip_one(&tokens[4]).into()),
-                        reason: Some(strip_one(&rejoin(&tokens[8..], &split_tokens[8..])).into()),
-                    },
-                    channel: Some(tokens[7].to_owned().into()),
-                    time: parse_time(&self.context, tokens[0], tokens[1]),
-                }));
-            }
-            // 2016-02-25 01:38:55 <-- Foo (host@mask.foo) has quit (Some reason)
-            else if len >= 8 && tokens[5] == "has" && tokens[6] == "quit" {
-                return Some(Ok(Event {
-                    ty: Type::Quit {
-                        nick: tokens[3].to_owned().into(),
-                        mask: Some(strip_one(tokens[4]).into()),
-                        reason: Some(strip_one(&rejoin(&tokens[7..], &split_tokens[7..])).into()),
-                    },
-                    time: parse_time(&self.context, tokens[0], tokens[1]),
-                    channel: self.context.channel.clone().map(Into::into),
-                }));
-            } else if len >= 3 && tokens[2] == "--" {
+            if prefix == self.prefixes.join {
+                // 2016-02-25 01:15:05	-->	Foo (host@mask.foo) has joined #example
+                if len >= 5 && tokens[2] == "has" && tokens[3] == "joined" {
+                    return Some(Ok(Event {
+                        ty: Type::Join {
+                            nick: tokens[0].to_owned().into(),
+                            mask: Some(strip_one(tokens[1]).into()),
+                        },
+                        channel: Some(tokens[4].to_owned().into()),
+                        time: time,
+                    }));
+                }
+            } else if prefix == self.prefixes.quit {
+                // 2016-02-25 01:36:13	<--	Foo (host@mask.foo) has left #channel (Some reason)
+                if len >= 5 && tokens[2] == "has" && tokens[3] == "left" {
+                    return Some(Ok(Event {
+                        ty: Type::Part {
+                            nick: tokens[0].to_owned().into(),
+                            mask: Some(strip_one(&tokens[1]).into()),
+                            reason: if len > 6 {
+                                Some(strip_one(&rejoin(&tokens[5..], &split_tokens[5..])).into())
+                            } else {
+                                None
+                            },
+                        },
+                        channel: Some(tokens[4].to_owned().into()),
+                        time: time,
+                    }));
+                }
+                // 2016-02-25 01:38:55	<--	Foo (host@mask.foo) has quit (Some reason)
+                else if len >= 4 && tokens[2] == "has" && tokens[3] == "quit" {
+                    return Some(Ok(Event {
+                        ty: Type::Quit {
+                            nick: tokens[0].to_owned().into(),
+                            mask: Some(strip_one(tokens[1]).into()),
+                            reason: if len > 5 {
+                                Some(strip_one(&rejoin(&tokens[4..], &split_tokens[4..])).into())
+                            } else {
+                                None
+                            },
+                        },
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+                // 2016-02-25 01:40:21	<--	Bar has kicked Foo (Some reason)
+                else if len >= 4 && tokens[1] == "has" && tokens[2] == "kicked" {
+                    return Some(Ok(Event {
+                        ty: Type::Kick {
+                            kicked_nick: tokens[3].to_owned().into(),
+                            kicking_nick: Some(tokens[0].to_owned().into()),
+                            kick_message: if len > 5 {
+                                Some(strip_one(&rejoin(&tokens[4..], &split_tokens[4..])).into())
+                            } else {
+                                None
+                            },
+                        },
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+            } else if prefix == self.prefixes.network {
                 // 2016-02-25 04:32:15	--	Notice(playbot-veno): ""
-                if len >= 5 && tokens[3].starts_with("Notice(") {
+                // 2016-02-25 04:32:15	--	Notice(playbot-veno) -> #channel: ""
+                if len >= 2 && tokens[0].starts_with("Notice(") {
+                    let (from, channel, rest) = if tokens[0].ends_with("):") {
+                        (&tokens[0]["Notice(".len()..tokens[0].len() - 2], None, 1)
+                    } else if len >= 4 && tokens[0].ends_with(")") && tokens[1] == "->" {
+                        (&tokens[0]["Notice(".len()..tokens[0].len() - 1],
+                         Some(tokens[2].trim_right_matches(':')),
+                         3)
+                    } else {
+                        continue;
+                    };
                     return Some(Ok(Event {
                         ty: Type::Notice {
-                            from: tokens[3]["Notice(".len()..tokens[3].len() - 2].to_owned().into(),
-                            content: rejoin(&tokens[4..], &split_tokens[4..]),
+                            from: from.to_owned().into(),
+                            content: rejoin(&tokens[rest..], &split_tokens[rest..]),
                         },
-                        time: parse_time(&self.context, tokens[0], tokens[1]),
-                        channel: self.context.channel.clone().map(Into::into),
+                        time: time,
+                        channel: channel.map(|c| c.to_owned().into())
+                                        .or(self.context.channel.clone().map(Into::into)),
                     }));
                 }
                 // 2014-07-11 15:00:03	--	irc: disconnected from server
-                else if len >= 7 && tokens[3] == "irc:" && tokens[4] == "disconnected" &&
-                   tokens[5] == "from" && tokens[6] == "server" {
+                else if len >= 4 && tokens[0] == "irc:" && tokens[1] == "disconnected" &&
+                   tokens[2] == "from" && tokens[3] == "server" {
                     return Some(Ok(Event {
                         ty: Type::Disconnect,
-                        time: parse_time(&self.context, tokens[0], tokens[1]),
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+                // 2014-07-11 14:58:51	--	irc: connected to chat.freenode.net/6697 (185.30.166.38)
+                else if len >= 3 && tokens[0] == "irc:" && tokens[1] == "connected" &&
+                   tokens[2] == "to" {
+                    return Some(Ok(Event {
+                        ty: Type::Connect,
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
                 // 2014-07-11 15:00:03	--	Foo|afk is now known as Foo
                 // 2015-05-09 13:56:05	--	You are now known as foo
-                else if len >= 9 && tokens[5] == "now" && tokens[6] == "known" && tokens[7] == "as" &&
-                   (tokens[4] == "is" || tokens[4] == "are") {
+                else if len >= 6 && tokens[2] == "now" && tokens[3] == "known" &&
+                   tokens[4] == "as" && (tokens[1] == "is" || tokens[1] == "are") {
                     return Some(Ok(Event {
                         ty: Type::Nick {
-                            old_nick: tokens[3].to_owned().into(),
-                            new_nick: tokens[8].to_owned().into(),
+                            old_nick: tokens[0].to_owned().into(),
+                            new_nick: tokens[5].to_owned().into(),
+                        },
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+                // 2016-02-25 01:40:21	--	Bar has kicked Foo (Some reason)
+                else if len >= 4 && tokens[1] == "has" && tokens[2] == "kicked" {
+                    return Some(Ok(Event {
+                        ty: Type::Kick {
+                            kicked_nick: tokens[3].to_owned().into(),
+                            kicking_nick: Some(tokens[0].to_owned().into()),
+                            kick_message: if len > 5 {
+                                Some(strip_one(&rejoin(&tokens[4..], &split_tokens[4..])).into())
+                            } else {
+                                None
+                            },
+                        },
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+                // 2016-02-25 01:41:10	--	Mode #example [+o Foo] by ChanServ
+                // 2016-02-25 01:41:10	--	Mode #example [+nt]
+                else if len >= 3 && tokens[0] == "Mode" {
+                    if let Some((mode, masks, nick)) = parse_mode(&tokens[2..],
+                                                                  &split_tokens[2..]) {
+                        return Some(Ok(Event {
+                            ty: Type::Mode {
+                                nick: nick.map(Into::into),
+                                mode: mode.into(),
+                                masks: masks.into(),
+                            },
+                            time: time,
+                            channel: Some(tokens[1].to_owned().into()),
+                        }));
+                    }
+                }
+                // 2016-02-25 01:41:10	--	User mode [+i] by Foo
+                else if len >= 3 && tokens[0] == "User" && tokens[1] == "mode" {
+                    if let Some((mode, masks, nick)) = parse_mode(&tokens[2..],
+                                                                  &split_tokens[2..]) {
+                        return Some(Ok(Event {
+                            ty: Type::Mode {
+                                nick: nick.map(Into::into),
+                                mode: mode.into(),
+                                masks: masks.into(),
+                            },
+                            time: time,
+                            channel: None,
+                        }));
+                    }
+                }
+                // 2016-02-25 01:42:00	--	Foo has changed topic for #example to "Hi"
+                // 2016-02-25 01:42:00	--	Foo has changed topic for #example from "Hi" to "Ho"
+                else if len >= 8 && tokens[1] == "has" && tokens[2] == "changed" &&
+                   tokens[3] == "topic" && tokens[4] == "for" {
+                    let rest = rejoin(&tokens[7..], &split_tokens[7..]);
+                    let new_topic = if tokens[6] == "to" {
+                        unquote(&rest)
+                    } else {
+                        match rest.find("\" to \"") {
+                            Some(idx) => unquote(&rest[idx + 5..]),
+                            None => continue,
+                        }
+                    };
+                    return Some(Ok(Event {
+                        ty: Type::TopicChange {
+                            nick: Some(tokens[0].to_owned().into()),
+                            new_topic: new_topic.into(),
+                        },
+                        time: time,
+                        channel: Some(tokens[5].to_owned().into()),
+                    }));
+                }
+                // 2016-02-25 01:42:00	--	Foo has unset topic for #example
+                else if len >= 6 && tokens[1] == "has" && tokens[2] == "unset" &&
+                   tokens[3] == "topic" && tokens[4] == "for" {
+                    return Some(Ok(Event {
+                        ty: Type::TopicChange {
+                            nick: Some(tokens[0].to_owned().into()),
+                            new_topic: "".into(),
+                        },
+                        time: time,
+                        channel: Some(tokens[5].to_owned().into()),
+                    }));
+                }
+                // 2016-02-25 01:15:05	--	Topic for #example is "Hi"
+                else if len >= 5 && tokens[0] == "Topic" && tokens[1] == "for" &&
+                   tokens[3] == "is" {
+                    return Some(Ok(Event {
+                        ty: Type::Topic {
+                            topic: unquote(&rejoin(&tokens[4..], &split_tokens[4..])).into(),
+                        },
+                        time: time,
+                        channel: Some(tokens[2].to_owned().into()),
+                    }));
+                }
+                // 2016-02-25 01:43:12	--	CTCP requested by Foo: VERSION
+                else if len >= 5 && tokens[0] == "CTCP" && tokens[1] == "requested" &&
+                   tokens[2] == "by" {
+                    return Some(Ok(Event {
+                        ty: Type::Ctcp {
+                            from: tokens[3].trim_right_matches(':').to_owned().into(),
+                            content: rejoin(&tokens[4..], &split_tokens[4..]),
+                        },
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
+                // 2016-02-25 01:44:30	--	Foo is away: gone fishing
+                else if len >= 3 && tokens[1] == "is" && tokens[2] == "away:" {
+                    return Some(Ok(Event {
+                        ty: Type::Away {
+                            nick: tokens[0].to_owned().into(),
+                            reason: Some(rejoin(&tokens[3..], &split_tokens[3..])),
                         },
-                        time: parse_time(&self.context, tokens[0], tokens[1]),
+                        time: time,
                         channel: self.context.channel.clone().map(Into::into),
                     }));
                 }
             }
             // 2016-01-24 20:32:57	 *	nick emotes
-            else if tokens[3] == "*" && tokens[2].is_empty() {
-                return Some(Ok(Event {
-                    ty: Type::Action {
-                        from: tokens[4].to_owned().into(),
-                        content: rejoin(&tokens[5..], &split_tokens[5..]),
-                    },
-                    time: parse_time(&self.context, tokens[0], tokens[1]),
-                    channel: self.context.channel.clone().map(Into::into),
-                }));
+            else if prefix == self.prefixes.action {
+                if len >= 2 {
+                    return Some(Ok(Event {
+                        ty: Type::Action {
+                            from: tokens[0].to_owned().into(),
+                            content: rejoin(&tokens[1..], &split_tokens[1..]),
+                        },
+                        time: time,
+                        channel: self.context.channel.clone().map(Into::into),
+                    }));
+                }
             }
             // 2016-01-24 20:32:25	nick	just some message
             else {
                 return Some(Ok(Event {
                     ty: Type::Msg {
-                        from: tokens[2].to_owned().into(),
-                        content: rejoin(&tokens[3..], &split_tokens[3..]),
+                        from: prefix.to_owned().into(),
+                        content: rejoin(&tokens, &split_tokens),
                     },
-                    time: parse_time(&self.context, tokens[0], tokens[1]),
+                    time: time,
                     channel: self.context.channel.clone().map(Into::into),
                 }));
             }
@@ -167,6 +398,7 @@ impl Decode for Weechat {
                   -> Box<Iterator<Item = ilc_base::Result<Event<'a>>> + 'a> {
         Box::new(Iter {
             context: context,
+            prefixes: &self.prefixes,
             input: input,
             buffer: Vec::new(),
         })
@@ -179,78 +411,380 @@ impl Encode for Weechat {
                   mut output: &'a mut Write,
                   event: &'a Event)
                   -> ilc_base::Result<()> {
+        let ty = event.ty.type_desc();
+        let time = degrade!(context.time(ty, &event.time, TIME_DATE_FORMAT));
+        let channel = event.channel.as_ref().map(|c| c as &str);
+        let p = &self.prefixes;
         match event {
-            &Event { ty: Type::Msg { ref from, ref content, .. }, ref time, .. } => {
-                try!(writeln!(&mut output,
-                              "{}\t{}\t{}",
-                              time.with_format(&context.timezone_out, TIME_DATE_FORMAT),
-                              from,
-                              content))
+            &Event { ty: Type::Msg { ref from, ref content, .. }, .. } => {
+                try!(writeln!(&mut output, "{}\t{}\t{}", time, from, content))
             }
-            &Event { ty: Type::Action { ref from, ref content, .. }, ref time, .. } => {
-                try!(writeln!(&mut output,
-                              "{}\t *\t{} {}",
-                              time.with_format(&context.timezone_out, TIME_DATE_FORMAT),
-                              from,
-                              content))
+            &Event { ty: Type::Action { ref from, ref content, .. }, .. } => {
+                try!(writeln!(&mut output, "{}\t{}\t{} {}", time, p.action, from, content))
             }
-            &Event { ty: Type::Join { ref nick, ref mask, .. }, ref channel, ref time } => {
+            &Event { ty: Type::Join { ref nick, ref mask, .. }, .. } => {
+                let mask = mask.as_ref().map(|m| m as &str);
+                let mask = degrade!(context.require(ty, "mask", mask, "*@*"));
+                let channel = degrade!(context.require(ty, "channel", channel, "*"));
                 try!(writeln!(&mut output,
-                              "{}\t-->\t{} ({}) has joined {}",
-                              time.with_format(&context.timezone_out, TIME_DATE_FORMAT),
+                              "{}\t{}\t{} ({}) has joined {}",
+                              time,
+                              p.join,
                               nick,
-                              mask.as_ref().expect("Hostmask not present, but required."),
-                              channel.as_ref().expect("Channel not present, but required.")))
+                              mask,
+                              channel))
             }
-            &Event { ty: Type::Nick { ref old_nick, ref new_nick, .. }, ref time, .. } => {
+            &Event { ty: Type::Nick { ref old_nick, ref new_nick, .. }, .. } => {
                 try!(writeln!(&mut output,
-                              "{}\t--\t{} is now known as {}",
-                              time.with_format(&context.timezone_out, TIME_DATE_FORMAT),
+                              "{}\t{}\t{} is now known as {}",
+                              time,
+                              p.network,
                               old_nick,
                               new_nick))
             }
-            &Event { ty: Type::Part { ref nick, ref mask, ref reason }, ref channel, ref time } => {
+            &Event { ty: Type::Part { ref nick, ref mask, ref reason }, .. } => {
+                let mask = mask.as_ref().map(|m| m as &str);
+                let mask = degrade!(context.require(ty, "mask", mask, "*@*"));
+                let channel = degrade!(context.require(ty, "channel", channel, "*"));
                 try!(write!(&mut output,
-                            "{}\t<--\t{} ({}) has left {}",
-                            time.with_format(&context.timezone_out, TIME_DATE_FORMAT),
+                            "{}\t{}\t{} ({}) has left {}",
+                            time,
+                            p.quit,
                             nick,
-                            mask.as_ref().expect("Hostmask not present, but required."),
-                            channel.as_ref().expect("Channel not present, but required.")));
+                            mask,
+                            channel));
                 if reason.is_some() && reason.as_ref().unwrap().len() > 0 {
                     try!(write!(&mut output, " ({})", reason.as_ref().unwrap()));
                 }
                 try!(write!(&mut output, "\n"))
             }
-            &Event { ty: Type::Quit { ref nick, ref mask, ref reason }, ref time, .. } => {
-                try!(write!(&mut output,
-                            "{}\t<--\t{} ({}) has quit",
-                            time.with_format(&context.timezone_out, TIME_DATE_FORMAT),
-                            nick,
-                            mask.as_ref().expect("Hostmask not present, but required.")));
+            &Event { ty: Type::Quit { ref nick, ref mask, ref reason }, .. } => {
+                let mask = mask.as_ref().map(|m| m as &str);
+                let mask = degrade!(context.require(ty, "mask", mask, "*@*"));
+                try!(write!(&mut output, "{}\t{}\t{} ({}) has quit", time, p.quit, nick, mask));
                 if reason.is_some() && reason.as_ref().unwrap().len() > 0 {
                     try!(write!(&mut output, " ({})", reason.as_ref().unwrap()));
                 }
                 try!(write!(&mut output, "\n"))
             }
-            &Event { ty: Type::Disconnect, ref time, .. } => {
+            &Event { ty: Type::Kick { ref kicked_nick, ref kicking_nick, ref kick_message },
+                     .. } => {
+                let kicker = kicking_nick.as_ref().map(|n| n as &str);
+                let kicker = degrade!(context.require(ty, "kicking nick", kicker, "*"));
+                try!(write!(&mut output,
+                            "{}\t{}\t{} has kicked {}",
+                            time,
+                            p.quit,
+                            kicker,
+                            kicked_nick));
+                if kick_message.is_some() && kick_message.as_ref().unwrap().len() > 0 {
+                    try!(write!(&mut output, " ({})", kick_message.as_ref().unwrap()));
+                }
+                try!(write!(&mut output, "\n"))
+            }
+            &Event { ty: Type::Mode { ref nick, ref mode, ref masks }, .. } => {
+                // user modes take no parameters, so those with some must be channel modes
+                if let (None, &Some(ref nick), true) = (channel, nick, masks.is_empty()) {
+                    try!(writeln!(&mut output,
+                                  "{}\t{}\tUser mode [{}] by {}",
+                                  time,
+                                  p.network,
+                                  mode,
+                                  nick))
+                } else {
+                    let channel = degrade!(context.require(ty, "channel", channel, "*"));
+                    try!(write!(&mut output,
+                                "{}\t{}\tMode {} [{}",
+                                time,
+                                p.network,
+                                channel,
+                                mode));
+                    if masks.len() > 0 {
+                        try!(write!(&mut output, " {}", masks));
+                    }
+                    try!(write!(&mut output, "]"));
+                    if let &Some(ref nick) = nick {
+                        try!(write!(&mut output, " by {}", nick));
+                    }
+                    try!(write!(&mut output, "\n"))
+                }
+            }
+            &Event { ty: Type::Topic { ref topic }, .. } => {
+                let channel = degrade!(context.require(ty, "channel", channel, "*"));
                 try!(writeln!(&mut output,
-                              "{}\t--\tirc: disconnected from server",
-                              time.with_format(&context.timezone_out, TIME_DATE_FORMAT)))
+                              "{}\t{}\tTopic for {} is \"{}\"",
+                              time,
+                              p.network,
+                              channel,
+                              topic))
             }
-            &Event { ty: Type::Notice { ref from, ref content }, ref time, .. } => {
+            &Event { ty: Type::TopicChange { ref nick, ref new_topic }, .. } => {
+                let nick = nick.as_ref().map(|n| n as &str);
+                let nick = degrade!(context.require(ty, "nick", nick, "*"));
+                let channel = degrade!(context.require(ty, "channel", channel, "*"));
+                if new_topic.is_empty() {
+                    try!(writeln!(&mut output,
+                                  "{}\t{}\t{} has unset topic for {}",
+                                  time,
+                                  p.network,
+                                  nick,
+                                  channel))
+                } else {
+                    try!(writeln!(&mut output,
+                                  "{}\t{}\t{} has changed topic for {} to \"{}\"",
+                                  time,
+                                  p.network,
+                                  nick,
+                                  channel,
+                                  new_topic))
+                }
+            }
+            &Event { ty: Type::Connect, .. } => {
+                try!(writeln!(&mut output, "{}\t{}\tirc: connected to server", time, p.network))
+            }
+            &Event { ty: Type::Disconnect, .. } => {
                 try!(writeln!(&mut output,
-                              "{}\t--\tNotice({}): {}",
-                              time.with_format(&context.timezone_out, TIME_DATE_FORMAT),
+                              "{}\t{}\tirc: disconnected from server",
+                              time,
+                              p.network))
+            }
+            &Event { ty: Type::Notice { ref from, ref content }, .. } => {
+                // a notice to a channel other than the one of the log names it
+                match channel {
+                    Some(c) if context.channel.as_ref().map_or(true, |own| own != c) => {
+                        try!(writeln!(&mut output,
+                                      "{}\t{}\tNotice({}) -> {}: {}",
+                                      time,
+                                      p.network,
+                                      from,
+                                      c,
+                                      content))
+                    }
+                    _ => {
+                        try!(writeln!(&mut output,
+                                      "{}\t{}\tNotice({}): {}",
+                                      time,
+                                      p.network,
+                                      from,
+                                      content))
+                    }
+                }
+            }
+            &Event { ty: Type::Ctcp { ref from, ref content }, .. } => {
+                try!(writeln!(&mut output,
+                              "{}\t{}\tCTCP requested by {}: {}",
+                              time,
+                              p.network,
                               from,
                               content))
             }
-            _ => {
-                if option_env!("FUSE").is_some() {
-                    panic!("Shouldn't reach here, this is a bug!")
-                }
-                ()
+            &Event { ty: Type::Away { ref nick, ref reason }, .. } => {
+                try!(writeln!(&mut output,
+                              "{}\t{}\t{} is away: {}",
+                              time,
+                              p.network,
+                              nick,
+                              reason.as_ref().map(|r| r as &str).unwrap_or("")))
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use ilc_base::event::{Event, Time, Type};
+    use ilc_base::{Context, Decode, Encode, Policy};
+    use ilc_base::policy::Outcome;
+
+    use super::{Prefixes, Weechat};
+
+    fn decode(line: &str) -> Vec<Type<'static>> {
+        let ctx = Context::default();
+        let mut input = line.as_bytes();
+        Weechat::default()
+            .decode(&ctx, &mut input)
+            .map(|e| e.unwrap().ty.map_nicks(str::to_owned))
+            .collect()
+    }
+
+    fn roundtrip(line: &str) {
+        roundtrip_with(&Weechat::default(), line);
+    }
+
+    fn roundtrip_with(weechat: &Weechat, line: &str) {
+        let ctx = Context::default();
+        let mut input = line.as_bytes();
+        let mut out = Vec::new();
+        for e in weechat.decode(&ctx, &mut input) {
+            weechat.encode(&ctx, &mut out, &e.unwrap()).unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), line);
+    }
+
+    fn encode(ctx: &Context, ty: Type, channel: Option<&'static str>) -> String {
+        let mut out = Vec::new();
+        Weechat::default()
+            .encode(ctx,
+                    &mut out,
+                    &Event {
+                        ty: ty,
+                        time: Time::Timestamp(1456364470),
+                        channel: channel.map(Cow::Borrowed),
+                    })
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn kick() {
+        let line = "2016-02-25 01:40:21\t<--\tBar has kicked Foo (Some reason)\n";
+        assert_eq!(decode(line),
+                   vec![Type::Kick {
+                            kicked_nick: Cow::Borrowed("Foo"),
+                            kicking_nick: Some(Cow::Borrowed("Bar")),
+                            kick_message: Some(Cow::Borrowed("Some reason")),
+                        }]);
+        roundtrip(line);
+    }
+
+    #[test]
+    fn mode() {
+        assert_eq!(decode("2016-02-25 01:41:10\t--\tMode #example [+o Foo] by ChanServ\n"),
+                   vec![Type::Mode {
+                            nick: Some(Cow::Borrowed("ChanServ")),
+                            mode: Cow::Borrowed("+o"),
+                            masks: Cow::Borrowed("Foo"),
+                        }]);
+        assert_eq!(decode("2016-02-25 01:41:10\t--\tMode #example [+nt]\n"),
+                   vec![Type::Mode {
+                            nick: None,
+                            mode: Cow::Borrowed("+nt"),
+                            masks: Cow::Borrowed(""),
+                        }]);
+        assert_eq!(decode("2016-02-25 01:41:10\t--\tUser mode [+i] by Foo\n"),
+                   vec![Type::Mode {
+                            nick: Some(Cow::Borrowed("Foo")),
+                            mode: Cow::Borrowed("+i"),
+                            masks: Cow::Borrowed(""),
+                        }]);
+        roundtrip("2016-02-25 01:41:10\t--\tMode #example [+o Foo] by ChanServ\n");
+        roundtrip("2016-02-25 01:41:10\t--\tMode #example [+nt]\n");
+        roundtrip("2016-02-25 01:41:10\t--\tUser mode [+i] by Foo\n");
+    }
+
+    #[test]
+    fn channel_mode_without_channel() {
+        let op = || {
+            Type::Mode {
+                nick: Some(Cow::Borrowed("ChanServ")),
+                mode: Cow::Borrowed("+o"),
+                masks: Cow::Borrowed("Foo"),
+            }
+        };
+        let mut ctx = Context::default();
+        ctx.policy = Policy::Fallback;
+        assert_eq!(encode(&ctx, op(), None),
+                   "2016-02-25 01:41:10\t--\tMode * [+o Foo] by ChanServ\n");
+        ctx.policy = Policy::Skip;
+        assert_eq!(encode(&ctx, op(), None), "");
+        assert_eq!(ctx.losses.entries(),
+                   vec![("mode", "channel", Outcome::Skipped, 1),
+                        ("mode", "channel", Outcome::Substituted, 1)]);
+    }
+
+    #[test]
+    fn away() {
+        let line = "2016-02-25 01:44:30\t--\tFoo is away: gone fishing\n";
+        assert_eq!(decode(line),
+                   vec![Type::Away {
+                            nick: Cow::Borrowed("Foo"),
+                            reason: Some(Cow::Borrowed("gone fishing")),
+                        }]);
+        roundtrip(line);
+    }
+
+    #[test]
+    fn ctcp() {
+        let line = "2016-02-25 01:43:12\t--\tCTCP requested by Foo: VERSION\n";
+        assert_eq!(decode(line),
+                   vec![Type::Ctcp {
+                            from: Cow::Borrowed("Foo"),
+                            content: Cow::Borrowed("VERSION"),
+                        }]);
+        roundtrip(line);
+    }
+
+    #[test]
+    fn notice() {
+        roundtrip("2016-02-25 04:32:15\t--\tNotice(NickServ): You are now identified\n");
+        let line = "2016-02-25 04:32:15\t--\tNotice(Foo) -> #example: Hello all\n";
+        assert_eq!(decode(line),
+                   vec![Type::Notice {
+                            from: Cow::Borrowed("Foo"),
+                            content: Cow::Borrowed("Hello all"),
+                        }]);
+        roundtrip(line);
+        // in the log of that channel, it's the channel of every event
+        let mut ctx = Context::default();
+        ctx.channel = Some("#example".to_owned());
+        let notice = Type::Notice {
+            from: Cow::Borrowed("Foo"),
+            content: Cow::Borrowed("Hello all"),
+        };
+        assert_eq!(encode(&ctx, notice, Some("#example")),
+                   "2016-02-25 01:41:10\t--\tNotice(Foo): Hello all\n");
+    }
+
+    #[test]
+    fn prefixes() {
+        let weechat = Weechat {
+            prefixes: Prefixes {
+                join: "=>".to_owned(),
+                quit: "<=".to_owned(),
+                network: "-!-".to_owned(),
+                action: "*".to_owned(),
+            },
+        };
+        for line in &["2016-02-25 01:15:05\t=>\tFoo (foo@example.com) has joined #example\n",
+                      "2016-02-25 01:36:13\t<=\tFoo (foo@example.com) has left #example (bye)\n",
+                      "2016-02-25 01:40:21\t<=\tBar has kicked Foo (Some reason)\n",
+                      "2016-02-25 01:41:10\t-!-\tMode #example [+o Foo] by ChanServ\n",
+                      "2016-02-25 01:42:00\t-!-\tFoo is now known as Bar\n",
+                      "2016-02-25 01:43:00\t*\tFoo waves\n"] {
+            roundtrip_with(&weechat, line);
+        }
+        // the default prefixes are just nicks then
+        let ctx = Context::default();
+        let mut input = "2016-02-25 01:42:00\t--\tFoo is now known as Bar\n".as_bytes();
+        let e = weechat.decode(&ctx, &mut input).next().unwrap().unwrap();
+        assert_eq!(e.ty.type_desc(), "message");
+    }
+
+    #[test]
+    fn topic() {
+        assert_eq!(decode("2016-02-25 01:15:05\t--\tTopic for #example is \"Hi there\"\n"),
+                   vec![Type::Topic { topic: Cow::Borrowed("Hi there") }]);
+        assert_eq!(decode("2016-02-25 01:42:00\t--\tFoo has changed topic for #example to \
+                           \"Hi\"\n"),
+                   vec![Type::TopicChange {
+                            nick: Some(Cow::Borrowed("Foo")),
+                            new_topic: Cow::Borrowed("Hi"),
+                        }]);
+        assert_eq!(decode("2016-02-25 01:42:00\t--\tFoo has changed topic for #example from \
+                           \"Hi\" to \"Ho\"\n"),
+                   vec![Type::TopicChange {
+                            nick: Some(Cow::Borrowed("Foo")),
+                            new_topic: Cow::Borrowed("Ho"),
+                        }]);
+        assert_eq!(decode("2016-02-25 01:42:00\t--\tFoo has unset topic for #example\n"),
+                   vec![Type::TopicChange {
+                            nick: Some(Cow::Borrowed("Foo")),
+                            new_topic: Cow::Borrowed(""),
+                        }]);
+        roundtrip("2016-02-25 01:42:00\t--\tFoo has changed topic for #example to \"Hi\"\n");
+        roundtrip("2016-02-25 01:42:00\t--\tFoo has unset topic for #example\n");
+    }
+}
@@ -0,0 +1,257 @@
+//! What channel operators did
+//!
+//! Kicks, bans, quiets, op and deop, and topic changes, by the operator who did
+//! them. Quiets are the `q` list mode where the server has one, and bans on
+//! `~q:` or `m:` masks otherwise. The whole log is read to see when bans are
+//! lifted, even if only some time range is reported.
+use ilc_base::{self, Context, Decode, Time};
+use ilc_base::event::Type;
+use members::channel_of;
+use modes::{ModeSpec, parse_modes};
+use stats::strip_nick;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+
+use serde::ser::{MapVisitor, Serialize, Serializer};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Kick,
+    Ban,
+    Unban,
+    Quiet,
+    Unquiet,
+    Op,
+    Deop,
+    Topic,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Kind::Kick => "kick",
+            Kind::Ban => "ban",
+            Kind::Unban => "unban",
+            Kind::Quiet => "quiet",
+            Kind::Unquiet => "unquiet",
+            Kind::Op => "op",
+            Kind::Deop => "deop",
+            Kind::Topic => "topic",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Action {
+    pub time: Option<i64>,
+    /// In canonical case, or empty if it's unknown
+    pub channel: String,
+    pub kind: Kind,
+    /// The nick or mask acted on, or the new topic
+    pub target: String,
+    /// The kick message
+    pub reason: Option<String>,
+    /// How long a ban or quiet was in effect, in seconds, if both ends are known
+    pub lasted: Option<i64>,
+}
+
+impl Serialize for Action {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a Action);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("time", self.0.time));
+                try!(s.serialize_struct_elt("channel", &self.0.channel));
+                try!(s.serialize_struct_elt("kind", self.0.kind.name()));
+                try!(s.serialize_struct_elt("target", &self.0.target));
+                try!(s.serialize_struct_elt("reason", &self.0.reason));
+                try!(s.serialize_struct_elt("lasted", self.0.lasted));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(6)
+            }
+        }
+
+        s.serialize_struct("Action", Visitor(self))
+    }
+}
+
+pub struct Operator {
+    /// The spelling seen first, or empty for actions whose operator isn't logged
+    pub nick: String,
+    /// In order of time
+    pub actions: Vec<Action>,
+}
+
+impl Serialize for Operator {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a Operator);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("nick", &self.0.nick));
+                try!(s.serialize_struct_elt("actions", &self.0.actions));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(2)
+            }
+        }
+
+        s.serialize_struct("Operator", Visitor(self))
+    }
+}
+
+fn is_quiet(spec: &ModeSpec, mode: char, mask: &str) -> bool {
+    (mode == 'q' && spec.is_list('q')) ||
+    (mode == 'b' && (mask.starts_with("~q:") || mask.starts_with("m:")))
+}
+
+/// Every moderation action from `since` until `until`, both inclusive, grouped by operator and
+/// sorted by their nick. Without a range, events without a full date are included too.
+pub fn audit(ctx: &Context,
+             input: &mut BufRead,
+             decoder: &mut Decode,
+             spec: &ModeSpec,
+             since: Option<i64>,
+             until: Option<i64>)
+             -> ilc_base::Result<Vec<Operator>> {
+    let cm = ctx.casemapping;
+    let mut operators: BTreeMap<String, Operator> = BTreeMap::new();
+    // channel, mode and mask of bans and quiets in effect, to when they were set, and where
+    // they were recorded
+    let mut open: HashMap<(String, char, String), (Option<i64>, Option<(String, usize)>)> =
+        HashMap::new();
+
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        let time = match e.time {
+            Time::Timestamp(t) => Some(t),
+            _ => None,
+        };
+        let in_range = match time {
+            Some(t) => since.map_or(true, |s| t >= s) && until.map_or(true, |u| t <= u),
+            None => since.is_none() && until.is_none(),
+        };
+        let channel = channel_of(ctx, &e);
+
+        let (by, actions) = match e.ty {
+            Type::Kick { ref kicked_nick, ref kicking_nick, ref kick_message } => {
+                (kicking_nick,
+                 vec![Action {
+                          time: time,
+                          channel: channel,
+                          kind: Kind::Kick,
+                          target: strip_nick(kicked_nick).to_owned(),
+                          reason: kick_message.as_ref().map(|m| m.to_string()),
+                          lasted: None,
+                      }])
+            }
+            Type::TopicChange { ref nick, ref new_topic } => {
+                (nick,
+                 vec![Action {
+                          time: time,
+                          channel: channel,
+                          kind: Kind::Topic,
+                          target: new_topic.to_string(),
+                          reason: None,
+                          lasted: None,
+                      }])
+            }
+            // user modes have no channel
+            Type::Mode { ref nick, ref mode, ref masks } if !channel.is_empty() => {
+                let mut actions: Vec<Action> = Vec::new();
+                for change in parse_modes(spec, mode, masks) {
+                    let param = match change.param {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    let kind = match (change.mode, change.add) {
+                        ('o', true) => Kind::Op,
+                        ('o', false) => Kind::Deop,
+                        (m, add) if is_quiet(spec, m, &param) => {
+                            if add { Kind::Quiet } else { Kind::Unquiet }
+                        }
+                        ('b', true) => Kind::Ban,
+                        ('b', false) => Kind::Unban,
+                        _ => continue,
+                    };
+                    let mut action = Action {
+                        time: time,
+                        channel: channel.clone(),
+                        kind: kind,
+                        target: param.clone(),
+                        reason: None,
+                        lasted: None,
+                    };
+                    let key = (channel.clone(), change.mode, param);
+                    match kind {
+                        Kind::Ban | Kind::Quiet => {
+                            let at = if in_range {
+                                let op = nick.as_ref().map_or(String::new(), |n| {
+                                    cm.to_lower(strip_nick(n))
+                                });
+                                let index = operators.get(&op).map_or(0, |o| o.actions.len()) +
+                                            actions.len();
+                                Some((op, index))
+                            } else {
+                                None
+                            };
+                            open.entry(key).or_insert((time, at));
+                        }
+                        Kind::Unban | Kind::Unquiet => {
+                            if let Some((set, at)) = open.remove(&key) {
+                                let lasted = match (set, time) {
+                                    (Some(set), Some(t)) => Some(t - set),
+                                    _ => None,
+                                };
+                                action.lasted = lasted;
+                                if let Some((op, i)) = at {
+                                    let stored = operators.get_mut(&op).map_or(&mut [][..], |o| {
+                                        &mut o.actions[..]
+                                    });
+                                    // set and lifted by the same event
+                                    if i >= stored.len() {
+                                        actions[i - stored.len()].lasted = lasted;
+                                    } else {
+                                        stored[i].lasted = lasted;
+                                    }
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                    actions.push(action);
+                }
+                (nick, actions)
+            }
+            _ => continue,
+        };
+
+        if !in_range || actions.is_empty() {
+            continue;
+        }
+        let nick = by.as_ref().map_or("", |n| strip_nick(n));
+        operators.entry(cm.to_lower(nick))
+                 .or_insert_with(|| {
+                     Operator {
+                         nick: nick.to_owned(),
+                         actions: Vec::new(),
+                     }
+                 })
+                 .actions
+                 .extend(actions);
+    }
+    Ok(operators.into_iter().map(|(_, o)| o).collect())
+}
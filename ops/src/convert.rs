@@ -1,8 +1,19 @@
 //! Log format conversion
 use regex::Regex;
 
-use ilc_base::{self, Context, Decode, Encode, Event};
+use ilc_base::{self, CaseMapping, Context, Decode, Encode, Event};
+use parallel::{self, Input};
 use std::io::{BufRead, Write};
+use std::mem;
+use std::panic;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+
+/// Converted output is passed from the workers to the writer in chunks of about this many bytes.
+const CHUNK: usize = 65536;
+
+/// How many chunks each worker may be ahead of the writer, before it waits.
+const BACKLOG: usize = 16;
 
 #[derive(Copy, Clone)]
 pub enum Subject {
@@ -24,13 +35,14 @@ pub enum Operator {
 pub struct Filter(pub Subject, pub Operator);
 
 impl Filter {
-    pub fn satisfied_by(&self, e: &Event) -> bool {
+    /// Nicks are compared according to `casemap`.
+    pub fn satisfied_by(&self, e: &Event, casemap: CaseMapping) -> bool {
         use self::Subject::*;
         use self::Operator::*;
 
         match (self.0, &self.1) {
-            (Nick, &Exactly(ref s)) => e.ty.actor().map_or(false, |e| e == s),
-            (Nick, &Contains(ref s)) => e.ty.actor().map_or(false, |e| e.contains(s)),
+            (Nick, &Exactly(ref s)) => e.ty.actor().map_or(false, |e| casemap.eq(e, s)),
+            (Nick, &Contains(ref s)) => e.ty.actor().map_or(false, |e| casemap.contains(e, s)),
             (Nick, &Matches(ref r)) => e.ty.actor().map_or(false, |e| r.is_match(e)),
             (Nick, _op) => false,
             (Time, &Equal(t)) => e.time.as_timestamp() == t,
@@ -63,7 +75,7 @@ pub fn convert(ctx: &Context,
     if let Some(f) = filter {
         for e in decoder.decode(&ctx, input) {
             let e = try!(e);
-            if not ^ f.satisfied_by(&e) {
+            if not ^ f.satisfied_by(&e, ctx.casemapping) {
                 try!(encoder.encode(&ctx, output, &e))
             }
         }
@@ -75,3 +87,73 @@ pub fn convert(ctx: &Context,
     }
     Ok(())
 }
+
+/// Like `convert`, but every input is converted in a worker thread of its own, up to `threads`
+/// at once. The output of each input is written in the order of `inputs`, while the workers of
+/// later ones wait once they are `BACKLOG` chunks ahead.
+pub fn convert_files(ctx: &Context,
+                     inputs: Vec<Input>,
+                     decoder: Box<Decode + Send + Sync>,
+                     output: &mut Write,
+                     encoder: Box<Encode + Send + Sync>,
+                     filter: Option<Filter>,
+                     not: bool,
+                     threads: usize)
+                     -> ilc_base::Result<()> {
+    type Part = SyncSender<ilc_base::Result<Vec<u8>>>;
+    // a failed send means the writer gave up, so there's nobody left to tell
+    let convert_part = move |ctx: &Context, input: &mut BufRead, part: Part, decoder: &Decode| {
+        let mut buf = Vec::new();
+        for e in decoder.decode(ctx, input) {
+            let e = match e {
+                Ok(e) => e,
+                Err(e) => {
+                    let _ = part.send(Ok(buf)).and_then(|()| part.send(Err(e)));
+                    return;
+                }
+            };
+            let keep = filter.as_ref().map_or(true, |f| not ^ f.satisfied_by(&e, ctx.casemapping));
+            if keep {
+                if let Err(e) = encoder.encode(ctx, &mut buf, &e) {
+                    let _ = part.send(Ok(buf)).and_then(|()| part.send(Err(e)));
+                    return;
+                }
+            }
+            if buf.len() >= CHUNK && part.send(Ok(mem::replace(&mut buf, Vec::new()))).is_err() {
+                return;
+            }
+        }
+        let _ = part.send(Ok(buf));
+    };
+
+    let mut parts = Vec::new();
+    let mut chunks = Vec::new();
+    for input in inputs {
+        let (tx, rx) = mpsc::sync_channel(BACKLOG);
+        parts.push((input, tx));
+        chunks.push(rx);
+    }
+    // the workers are waited for in a thread of their own, while their output is written here
+    let forked = ctx.fork();
+    let workers = thread::spawn(move || {
+        parallel::each_with(&forked, parts, decoder, threads, convert_part);
+        forked
+    });
+
+    // dropping the remaining receivers on error stops the workers at their next chunk
+    let mut written = Ok(());
+    'parts: for part in chunks {
+        for chunk in part {
+            if let Err(e) = chunk.and_then(|chunk| Ok(try!(output.write_all(&chunk)))) {
+                written = Err(e);
+                break 'parts;
+            }
+        }
+    }
+
+    match workers.join() {
+        Ok(forked) => ctx.losses.absorb(forked.losses),
+        Err(e) => panic::resume_unwind(e),
+    }
+    written
+}
@@ -0,0 +1,55 @@
+//! Decoders and events shared by the tests of the operators.
+
+use std::borrow::Cow;
+use std::io::BufRead;
+
+use ilc_base::{self, Context, Decode, Event, Time};
+use ilc_base::event::Type;
+
+/// Decodes to the same events, whatever the input.
+pub struct Events(pub Vec<Event<'static>>);
+
+impl Decode for Events {
+    fn decode<'a>(&'a self,
+                  _context: &'a Context,
+                  _input: &'a mut BufRead)
+                  -> Box<Iterator<Item = ilc_base::Result<Event<'a>>> + 'a> {
+        Box::new(self.0.iter().map(|e| -> ilc_base::Result<Event<'a>> { Ok(e.clone()) }))
+    }
+}
+
+/// Decodes lines of a timestamp, a nick and a message, for tests that need real offsets.
+pub struct Lines;
+
+impl Decode for Lines {
+    fn decode<'a>(&'a self,
+                  _context: &'a Context,
+                  input: &'a mut BufRead)
+                  -> Box<Iterator<Item = ilc_base::Result<Event<'a>>> + 'a> {
+        Box::new(input.lines().map(|line| {
+            let line = try!(line);
+            let mut parts = line.splitn(3, ' ');
+            let time = parts.next().unwrap().parse().unwrap();
+            let from = parts.next().unwrap();
+            let content = parts.next().unwrap_or("");
+            Ok(msg(from, content, time))
+        }))
+    }
+}
+
+/// An event in `#c` at the given timestamp.
+pub fn event(ty: Type<'static>, time: i64) -> Event<'static> {
+    Event {
+        ty: ty,
+        time: Time::Timestamp(time),
+        channel: Some(Cow::Borrowed("#c")),
+    }
+}
+
+pub fn msg(from: &str, content: &str, time: i64) -> Event<'static> {
+    event(Type::Msg {
+              from: Cow::Owned(from.to_owned()),
+              content: Cow::Owned(content.to_owned()),
+          },
+          time)
+}
@@ -0,0 +1,232 @@
+//! Searching logs
+//!
+//! Like grep, but on events rather than lines: patterns are matched against the
+//! nicks, text or masks of an event, and context is counted in events. Line
+//! numbers are tracked by counting the lines the decoder consumed, so they are
+//! exact for formats with one event per line.
+use regex::{self, Regex};
+
+use ilc_base::{self, Context, Decode, Event};
+use ilc_base::event::Type;
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// Every nick taking part, including the new nick of nick changes and the kicked nick
+    Nick,
+    /// Messages, reasons and topics, see `Type::text`
+    Text,
+    /// `user@host` of joins, parts and quits, and the parameters of mode changes
+    Mask,
+}
+
+pub struct Matcher {
+    regex: Regex,
+    /// Empty for all
+    fields: Vec<Field>,
+    invert: bool,
+}
+
+impl Matcher {
+    /// With `fixed`, `pattern` is matched literally instead of as a regular expression.
+    pub fn new(pattern: &str,
+               fixed: bool,
+               ignore_case: bool,
+               fields: Vec<Field>,
+               invert: bool)
+               -> Result<Matcher, regex::Error> {
+        let pattern = if fixed { regex::quote(pattern) } else { pattern.to_owned() };
+        let pattern = if ignore_case { format!("(?i){}", pattern) } else { pattern };
+        Ok(Matcher {
+            regex: try!(Regex::new(&pattern)),
+            fields: fields,
+            invert: invert,
+        })
+    }
+
+    fn searches(&self, field: Field) -> bool {
+        self.fields.is_empty() || self.fields.contains(&field)
+    }
+
+    /// The values of the fields of `e` that are searched.
+    fn values<'a>(&self, e: &'a Event) -> Vec<&'a str> {
+        let mut values = Vec::new();
+        if self.searches(Field::Nick) {
+            values.extend(e.ty.actor());
+            match e.ty {
+                Type::Nick { ref new_nick, .. } => values.push(&**new_nick),
+                Type::Kick { ref kicked_nick, .. } => values.push(&**kicked_nick),
+                _ => (),
+            }
+        }
+        if self.searches(Field::Text) {
+            values.extend(e.ty.text());
+        }
+        if self.searches(Field::Mask) {
+            match e.ty {
+                Type::Join { ref mask, .. } |
+                Type::Part { ref mask, .. } |
+                Type::Quit { ref mask, .. } => values.extend(mask.as_ref().map(|m| &**m)),
+                Type::Mode { ref masks, .. } => values.push(&**masks),
+                _ => (),
+            }
+        }
+        values
+    }
+
+    /// Whether `e` is a hit, with the text that matched, to highlight. Inverted matches have
+    /// nothing to highlight.
+    pub fn find(&self, e: &Event) -> Option<Vec<String>> {
+        let mut found = Vec::new();
+        for value in self.values(e) {
+            found.extend(self.regex.find_iter(value).map(|(a, b)| value[a..b].to_owned()));
+        }
+        match (found.is_empty(), self.invert) {
+            (false, false) => Some(found),
+            (true, true) => Some(Vec::new()),
+            _ => None,
+        }
+    }
+}
+
+/// Counts the lines and bytes read through it, so they can be read while a decoder holds the
+/// reader.
+pub struct LineCount<R> {
+    inner: R,
+    lines: Rc<Cell<usize>>,
+    bytes: Rc<Cell<u64>>,
+}
+
+impl<R: BufRead> LineCount<R> {
+    pub fn new(inner: R) -> LineCount<R> {
+        LineCount {
+            inner: inner,
+            lines: Rc::new(Cell::new(0)),
+            bytes: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// The number of complete lines read so far, shared with the reader.
+    pub fn counter(&self) -> Rc<Cell<usize>> {
+        self.lines.clone()
+    }
+
+    /// The number of bytes read so far, shared with the reader.
+    pub fn offset(&self) -> Rc<Cell<u64>> {
+        self.bytes.clone()
+    }
+}
+
+fn count(lines: &Cell<usize>, bytes: &Cell<u64>, buf: &[u8]) {
+    lines.set(lines.get() + buf.iter().filter(|&&b| b == b'\n').count());
+    bytes.set(bytes.get() + buf.len() as u64);
+}
+
+impl<R: BufRead> Read for LineCount<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        count(&self.lines, &self.bytes, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for LineCount<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // the buffer is still filled, so this doesn't read
+        if let Ok(buf) = self.inner.fill_buf() {
+            count(&self.lines, &self.bytes, &buf[..amt]);
+        }
+        self.inner.consume(amt)
+    }
+}
+
+pub struct Line {
+    pub event: Event<'static>,
+    /// The line the event ended on, counting from 1
+    pub line: usize,
+    /// Whether the event is a hit, rather than context
+    pub hit: bool,
+    /// The text that matched, to highlight
+    pub matches: Vec<String>,
+    /// Whether events were left out before this one, since the last line passed on
+    pub gap: bool,
+}
+
+/// Pass every hit to `f`, with `before` events of context before and `after` events after it.
+/// Events are passed on in order and only once, even if they are context to several hits.
+pub fn grep<F>(ctx: &Context,
+               input: &mut BufRead,
+               decoder: &mut Decode,
+               matcher: &Matcher,
+               before: usize,
+               after: usize,
+               mut f: F)
+               -> ilc_base::Result<()>
+    where F: FnMut(Line) -> ilc_base::Result<()>
+{
+    let mut input = LineCount::new(input);
+    let lines = input.counter();
+    let mut context: VecDeque<Line> = VecDeque::new();
+    // how many more events to pass on after the last hit
+    let mut left = 0;
+    // whether an event was left out since the last line passed on
+    let mut gap = false;
+    for e in decoder.decode(&ctx, &mut input) {
+        let e = try!(e);
+        let line = lines.get();
+        match matcher.find(&e) {
+            Some(matches) => {
+                for mut l in context.drain(..) {
+                    l.gap = gap;
+                    gap = false;
+                    try!(f(l));
+                }
+                try!(f(Line {
+                    event: e.into_owned(),
+                    line: line,
+                    hit: true,
+                    matches: matches,
+                    gap: gap,
+                }));
+                gap = false;
+                left = after;
+            }
+            None if left > 0 => {
+                try!(f(Line {
+                    event: e.into_owned(),
+                    line: line,
+                    hit: false,
+                    matches: Vec::new(),
+                    gap: false,
+                }));
+                left -= 1;
+            }
+            None => {
+                if before == 0 {
+                    gap = true;
+                    continue;
+                }
+                if context.len() == before {
+                    context.pop_front();
+                    gap = true;
+                }
+                context.push_back(Line {
+                    event: e.into_owned(),
+                    line: line,
+                    hit: false,
+                    matches: Vec::new(),
+                    gap: false,
+                });
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,352 @@
+//! Nick alias resolution
+//!
+//! People change nicks, reconnect as `nick_`, or use several nicks on purpose.
+//! `Identities` links nicks that belong to the same person, from nick changes,
+//! a user-supplied alias file and, if asked for, shared hostmasks, and resolves
+//! each nick to one canonical name.
+use ilc_base::{self, CaseMapping, Context, Decode, Event};
+use ilc_base::event::Type;
+use state::Persist;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, Read, Write};
+
+#[derive(Clone)]
+pub struct Identities {
+    casemap: CaseMapping,
+    /// Union-find forest over nicks in canonical case
+    parent: HashMap<String, String>,
+    /// The spelling each nick was first seen with
+    spelling: HashMap<String, String>,
+    /// The first nick seen with a given `user@host`
+    masks: HashMap<String, String>,
+    /// Nicks in canonical case that share a `user@host` with the first nick seen with it.
+    /// Kept apart, as everyone behind the same bouncer or web client shares one.
+    shared: HashSet<(String, String)>,
+    /// Whether nicks sharing a `user@host` are the same person
+    merging_masks: bool,
+    /// Nicks the alias file names as canonical
+    preferred: HashSet<String>,
+}
+
+impl Identities {
+    pub fn new(casemap: CaseMapping) -> Identities {
+        Identities {
+            casemap: casemap,
+            parent: HashMap::new(),
+            spelling: HashMap::new(),
+            masks: HashMap::new(),
+            shared: HashSet::new(),
+            merging_masks: false,
+            preferred: HashSet::new(),
+        }
+    }
+
+    /// Consider nicks that share a `user@host` the same person, from now on and for everything
+    /// observed before.
+    pub fn merge_masks(&mut self) {
+        self.merging_masks = true;
+        let shared: Vec<_> = self.shared.iter().cloned().collect();
+        for (first, nick) in shared {
+            self.alias(&first, &nick);
+        }
+    }
+
+    /// The case mapping nicks are compared with.
+    pub fn casemapping(&self) -> CaseMapping {
+        self.casemap
+    }
+
+    fn key(&mut self, nick: &str) -> String {
+        let key = self.casemap.to_lower(nick);
+        if !self.parent.contains_key(&key) {
+            self.parent.insert(key.clone(), key.clone());
+            self.spelling.insert(key.clone(), nick.to_owned());
+        }
+        key
+    }
+
+    fn root(&self, key: &str) -> String {
+        let mut key = key;
+        while let Some(p) = self.parent.get(key) {
+            if p == key {
+                break;
+            }
+            key = p;
+        }
+        key.to_owned()
+    }
+
+    /// Declare `a` and `b` to be the same person. Unless `b`'s group has a
+    /// preferred name and `a`'s doesn't, `a`'s group keeps its canonical name.
+    pub fn alias(&mut self, a: &str, b: &str) {
+        let (a, b) = (self.key(a), self.key(b));
+        let (ra, rb) = (self.root(&a), self.root(&b));
+        if ra == rb {
+            return;
+        }
+        if self.preferred.contains(&rb) && !self.preferred.contains(&ra) {
+            self.parent.insert(ra, rb);
+        } else {
+            self.parent.insert(rb, ra);
+        }
+    }
+
+    /// Learn from a single event. Nick changes link both nicks, and joins,
+    /// parts and quits link nicks that share a `user@host`, with `merge_masks`.
+    pub fn observe(&mut self, e: &Event) {
+        if let Some(s) = Sighting::of(e) {
+            self.learn(s);
+        }
+    }
+
+    fn learn(&mut self, s: Sighting) {
+        match s {
+            Sighting::Rename(old_nick, new_nick) => self.alias(&old_nick, &new_nick),
+            Sighting::Mask(nick, mask) => {
+                let nick = self.key(&nick);
+                let first = self.masks.entry(mask).or_insert_with(|| nick.clone()).clone();
+                if first != nick && self.shared.insert((first.clone(), nick.clone())) &&
+                   self.merging_masks {
+                    self.alias(&first, &nick);
+                }
+            }
+            Sighting::Actor(nick) => {
+                self.key(&nick);
+            }
+        }
+    }
+
+    /// Learn what was seen in another part of the log, which follows everything
+    /// observed so far.
+    pub fn learn_all(&mut self, sightings: Sightings) {
+        for s in sightings.list {
+            self.learn(s);
+        }
+    }
+
+    /// Read an alias file. Each line lists the nicks of one person, separated
+    /// by whitespace, with the canonical name first. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn load_aliases(&mut self, input: &mut BufRead) -> ilc_base::Result<()> {
+        for line in input.lines() {
+            let line = try!(line);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut nicks = line.split_whitespace();
+            if let Some(canonical) = nicks.next() {
+                let key = self.key(canonical);
+                // the alias file has the final say on spelling, too
+                self.spelling.insert(key.clone(), canonical.to_owned());
+                let root = self.root(&key);
+                if root != key {
+                    self.parent.insert(root, key.clone());
+                    self.parent.insert(key.clone(), key.clone());
+                }
+                self.preferred.insert(key);
+                for nick in nicks {
+                    self.alias(canonical, nick);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The canonical name of the person using `nick`.
+    pub fn resolve(&self, nick: &str) -> String {
+        let root = self.root(&self.casemap.to_lower(nick));
+        match self.spelling.get(&root) {
+            Some(s) => s.clone(),
+            None => nick.to_owned(),
+        }
+    }
+
+    /// Re-key `map` from nicks in canonical case to people, merging the values of nicks of the
+    /// same person under their canonical name.
+    ///
+    /// A nick change may come after the nick was last seen, so everything counted per nick has
+    /// to be merged this way once the whole log is known, not while it is being read.
+    pub fn merge<T: Mergeable>(&self, map: HashMap<String, T>) -> HashMap<String, T> {
+        let mut merged: HashMap<String, T> = HashMap::new();
+        for (_, value) in map {
+            let person = self.resolve(value.nick());
+            merged.entry(self.casemap.to_lower(&person))
+                  .or_insert_with(|| T::empty(&person))
+                  .merge(value);
+        }
+        merged
+    }
+
+    /// Whether `a` and `b` are nicks of the same person.
+    pub fn same(&self, a: &str, b: &str) -> bool {
+        self.casemap.eq(&self.resolve(a), &self.resolve(b))
+    }
+
+    /// All known people with more than one nick, with their nicks sorted.
+    pub fn groups(&self) -> BTreeMap<String, Vec<String>> {
+        let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (key, spelling) in &self.spelling {
+            let root = self.root(key);
+            groups.entry(self.spelling[&root].clone())
+                  .or_insert_with(Vec::new)
+                  .push(spelling.clone());
+        }
+        let mut groups: BTreeMap<String, Vec<String>> = groups.into_iter()
+                                                              .filter(|&(_, ref v)| v.len() > 1)
+                                                              .collect();
+        for nicks in groups.values_mut() {
+            nicks.sort();
+        }
+        groups
+    }
+}
+
+impl Persist for Identities {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(self.casemap.save(out));
+        try!(self.parent.save(out));
+        try!(self.spelling.save(out));
+        try!(self.masks.save(out));
+        try!(self.shared.save(out));
+        try!(self.preferred.save(out));
+        Ok(())
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Identities> {
+        Ok(Identities {
+            casemap: try!(Persist::load(input)),
+            parent: try!(Persist::load(input)),
+            spelling: try!(Persist::load(input)),
+            masks: try!(Persist::load(input)),
+            shared: try!(Persist::load(input)),
+            // a setting of each run, not something learned
+            merging_masks: false,
+            preferred: try!(Persist::load(input)),
+        })
+    }
+}
+
+/// Something counted per nick, that can be added up per person by `Identities::merge`.
+pub trait Mergeable {
+    /// The spelling of the nick this is about
+    fn nick(&self) -> &str;
+    /// Nothing counted yet, for `person`
+    fn empty(person: &str) -> Self;
+    /// Add what was counted for `other`, another nick of the same person, keeping the name.
+    fn merge(&mut self, other: Self);
+}
+
+/// What `Identities` can learn from a single event.
+#[derive(Clone, Debug)]
+pub enum Sighting {
+    /// From the old to the new nick
+    Rename(String, String),
+    /// A nick and its `user@host`
+    Mask(String, String),
+    /// Any other event a nick acted in
+    Actor(String),
+}
+
+impl Sighting {
+    pub fn of(e: &Event) -> Option<Sighting> {
+        match e.ty {
+            Type::Nick { ref old_nick, ref new_nick } => {
+                Some(Sighting::Rename(old_nick.to_string(), new_nick.to_string()))
+            }
+            Type::Join { ref nick, mask: Some(ref mask) } |
+            Type::Part { ref nick, mask: Some(ref mask), .. } |
+            Type::Quit { ref nick, mask: Some(ref mask), .. } => {
+                let mask = match mask.find('!') {
+                    Some(i) => &mask[i + 1..],
+                    None => mask,
+                };
+                if mask.contains('@') {
+                    Some(Sighting::Mask(nick.to_string(), mask.to_owned()))
+                } else {
+                    None
+                }
+            }
+            _ => e.ty.actor().map(|nick| Sighting::Actor(nick.to_owned())),
+        }
+    }
+}
+
+/// What `Identities` would learn from part of a log, e.g. one read in another
+/// thread, to be learned in order with the other parts. Nicks only acting again
+/// don't tell anything new and are left out.
+pub struct Sightings {
+    casemap: CaseMapping,
+    list: Vec<Sighting>,
+    actors: HashSet<String>,
+}
+
+impl Sightings {
+    pub fn new(casemap: CaseMapping) -> Sightings {
+        Sightings {
+            casemap: casemap,
+            list: Vec::new(),
+            actors: HashSet::new(),
+        }
+    }
+
+    pub fn record(&mut self, e: &Event) {
+        if let Some(s) = Sighting::of(e) {
+            if let Sighting::Actor(ref nick) = s {
+                if !self.actors.insert(self.casemap.to_lower(nick)) {
+                    return;
+                }
+            }
+            self.list.push(s);
+        }
+    }
+}
+
+/// Build the identity graph of a log, on top of `identities`, which may
+/// already contain aliases.
+pub fn identities(ctx: &Context,
+                  input: &mut BufRead,
+                  decoder: &mut Decode,
+                  mut identities: Identities)
+                  -> ilc_base::Result<Identities> {
+    for e in decoder.decode(&ctx, input) {
+        identities.observe(&try!(e));
+    }
+    Ok(identities)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use ilc_base::{CaseMapping, Event};
+    use ilc_base::event::{Time, Type};
+
+    use super::Identities;
+
+    fn join(nick: &'static str, mask: &'static str) -> Event<'static> {
+        Event {
+            ty: Type::Join {
+                nick: Cow::Borrowed(nick),
+                mask: Some(Cow::Borrowed(mask)),
+            },
+            time: Time::Unknown,
+            channel: None,
+        }
+    }
+
+    #[test]
+    fn masks_only_merge_when_asked() {
+        let mut ids = Identities::new(CaseMapping::Rfc1459);
+        ids.observe(&join("Foo", "u@shared"));
+        ids.observe(&join("Bar", "u@shared"));
+        assert!(!ids.same("Foo", "Bar"));
+
+        ids.merge_masks();
+        assert!(ids.same("Foo", "Bar"));
+        ids.observe(&join("Baz", "u@shared"));
+        assert!(ids.same("Foo", "Baz"));
+        assert_eq!(ids.resolve("bar"), "Foo");
+    }
+}
@@ -0,0 +1,804 @@
+//! A full-text index over log files
+//!
+//! Reading hundreds of gigabytes per search is slow, so `Index` keeps an
+//! inverted index of every event: the words of its text with their positions,
+//! for phrases, and its nick, channel and type as `nick:alice`,
+//! `channel:#chan` and `type:message`. Events are found again by their file
+//! and byte offset. Logs only grow, so updating continues files where they were
+//! left off, and only reads a file again if it shrank.
+//!
+//! The postings are kept in segments, one per update, which are never changed
+//! once written. Each ends with a dictionary of where the postings of every
+//! term are, so that a search only reads the terms it asks for. The index
+//! itself only lists the files and segments. Segments with events of files that
+//! were read again are written anew without them.
+use ilc_base::{self, CaseMapping, Context, Decode, Error, Event, Time};
+use grep::LineCount;
+use members::channel_of;
+use state::Persist;
+use stats::strip_nick;
+use urls::strip_formatting;
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::u64;
+
+/// Starts every index file, the digit is the version of the format.
+const MAGIC: &'static [u8] = b"ilcindx2";
+
+/// Starts every segment, the digit is the version of the format.
+const SEGMENT_MAGIC: &'static [u8] = b"ilcsegm1";
+
+/// The length of files that were read again. Their entries stay, so that the numbers of the
+/// other files don't change.
+const FORGOTTEN: u64 = u64::MAX;
+
+/// How many bytes a `Doc` takes in a segment, where they are all the same size so that any of
+/// them can be read directly.
+const DOC_SIZE: u64 = 21;
+
+pub struct IndexedFile {
+    pub path: String,
+    /// How many bytes of the file are in the index
+    pub len: u64,
+}
+
+struct Doc {
+    /// Into `Index::files`
+    file: u32,
+    offset: u64,
+    /// Only known for logs with full dates
+    time: Option<i64>,
+}
+
+struct Posting {
+    /// Into `Index::docs`
+    doc: u32,
+    /// Of the word in the text, 0 for nick, channel and type
+    pos: u32,
+}
+
+impl Persist for IndexedFile {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(self.path.save(out));
+        self.len.save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<IndexedFile> {
+        Ok(IndexedFile {
+            path: try!(Persist::load(input)),
+            len: try!(Persist::load(input)),
+        })
+    }
+}
+
+impl Persist for Doc {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(self.file.save(out));
+        try!(self.offset.save(out));
+        try!((self.time.is_some() as u8).save(out));
+        self.time.unwrap_or(0).save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Doc> {
+        let file = try!(Persist::load(input));
+        let offset = try!(Persist::load(input));
+        let known = try!(u8::load(input));
+        let time = try!(i64::load(input));
+        Ok(Doc {
+            file: file,
+            offset: offset,
+            time: if known == 1 { Some(time) } else { None },
+        })
+    }
+}
+
+impl Persist for Posting {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(self.doc.save(out));
+        self.pos.save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Posting> {
+        Ok(Posting {
+            doc: try!(Persist::load(input)),
+            pos: try!(Persist::load(input)),
+        })
+    }
+}
+
+/// The words of `text`, in lower case and without formatting.
+pub fn words(text: &str) -> Vec<String> {
+    strip_formatting(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Clause {
+    /// A word, or a field like `nick:alice`
+    Term(String),
+    /// Words following each other
+    Phrase(Vec<String>),
+}
+
+/// Alternatives, each of clauses that all have to match, and clauses that must not.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    pub any: Vec<(Vec<Clause>, Vec<Clause>)>,
+}
+
+impl Query {
+    /// Parse a query: words and `"quoted phrases"` all have to match, unless prefixed with `-`
+    /// or `NOT`, in which case they must not. `OR` separates alternatives. `nick:`,
+    /// `channel:` and `type:` search those fields instead of the text.
+    pub fn parse(casemap: CaseMapping, s: &str) -> Result<Query, String> {
+        let mut tokens = Vec::new();
+        let mut rest = s.trim_left();
+        while !rest.is_empty() {
+            let negated = rest.starts_with('-');
+            let body = if negated { &rest[1..] } else { rest };
+            let (token, quoted, after) = if body.starts_with('"') {
+                match body[1..].find('"') {
+                    Some(end) => (&body[1..end + 1], true, &body[end + 2..]),
+                    None => return Err("unclosed quote".to_owned()),
+                }
+            } else {
+                let end = body.find(char::is_whitespace).unwrap_or(body.len());
+                (&body[..end], false, &body[end..])
+            };
+            tokens.push((negated, quoted, token));
+            rest = after.trim_left();
+        }
+
+        let mut query = Query::default();
+        let (mut all, mut none) = (Vec::new(), Vec::new());
+        let mut not = false;
+        for (negated, quoted, token) in tokens {
+            if !quoted && !negated && token == "OR" {
+                if all.is_empty() && none.is_empty() {
+                    return Err("OR without anything before it".to_owned());
+                }
+                query.any.push((all, none));
+                all = Vec::new();
+                none = Vec::new();
+                continue;
+            }
+            if !quoted && !negated && token == "NOT" {
+                not = true;
+                continue;
+            }
+            let field = if quoted { None } else { token.find(':').map(|i| token.split_at(i)) };
+            let clause = match field {
+                Some(("nick", value)) => {
+                    Clause::Term(format!("nick:{}", casemap.to_lower(strip_nick(&value[1..]))))
+                }
+                Some(("channel", value)) => {
+                    Clause::Term(format!("channel:{}", casemap.to_lower(&value[1..])))
+                }
+                Some(("type", value)) => Clause::Term(format!("type:{}", &value[1..])),
+                _ => {
+                    let mut words = words(token);
+                    match words.len() {
+                        0 => continue,
+                        1 => Clause::Term(words.pop().unwrap()),
+                        _ => Clause::Phrase(words),
+                    }
+                }
+            };
+            if negated || not {
+                none.push(clause);
+            } else {
+                all.push(clause);
+            }
+            not = false;
+        }
+        if all.is_empty() && none.is_empty() {
+            if !query.any.is_empty() {
+                return Err("OR without anything after it".to_owned());
+            }
+            return Err("empty query".to_owned());
+        }
+        query.any.push((all, none));
+        Ok(query)
+    }
+}
+
+/// Where an event was found.
+pub struct Hit<'a> {
+    pub path: &'a str,
+    pub offset: u64,
+    pub time: Option<i64>,
+}
+
+/// Where the segments of an index are kept, by their number.
+pub trait Store {
+    type Segment: Read + Seek;
+
+    fn open(&self, segment: u32) -> ilc_base::Result<Self::Segment>;
+
+    /// Create the segment, with what `write` writes.
+    fn create(&self,
+              segment: u32,
+              write: &mut FnMut(&mut Write) -> ilc_base::Result<()>)
+              -> ilc_base::Result<()>;
+}
+
+/// What the index knows about a segment without reading it.
+#[derive(Clone)]
+struct SegmentInfo {
+    id: u32,
+    docs: u32,
+    /// The files with events in it, into `Index::files`
+    files: Vec<u32>,
+}
+
+impl Persist for SegmentInfo {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(self.id.save(out));
+        try!(self.docs.save(out));
+        self.files.save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<SegmentInfo> {
+        Ok(SegmentInfo {
+            id: try!(Persist::load(input)),
+            docs: try!(Persist::load(input)),
+            files: try!(Persist::load(input)),
+        })
+    }
+}
+
+/// A segment in memory, while it is built or compacted.
+#[derive(Default)]
+struct Segment {
+    docs: Vec<Doc>,
+    /// Sorted by document, then position
+    terms: BTreeMap<String, Vec<Posting>>,
+}
+
+impl Segment {
+    /// The segment without the events of the files `keep` says no to.
+    fn retain<F: Fn(u32) -> bool>(self, keep: F) -> Segment {
+        let mut renumbered = Vec::with_capacity(self.docs.len());
+        let mut docs = Vec::new();
+        for doc in self.docs {
+            if keep(doc.file) {
+                renumbered.push(Some(docs.len() as u32));
+                docs.push(doc);
+            } else {
+                renumbered.push(None);
+            }
+        }
+        let mut terms = BTreeMap::new();
+        for (term, postings) in self.terms {
+            let postings: Vec<Posting> = postings.into_iter()
+                                                 .filter_map(|p| {
+                                                     renumbered[p.doc as usize].map(|doc| {
+                                                         Posting {
+                                                             doc: doc,
+                                                             pos: p.pos,
+                                                         }
+                                                     })
+                                                 })
+                                                 .collect();
+            if !postings.is_empty() {
+                terms.insert(term, postings);
+            }
+        }
+        Segment {
+            docs: docs,
+            terms: terms,
+        }
+    }
+
+    /// Write the documents, then the postings of every term, then the dictionary of where those
+    /// are and how many, and last where the dictionary starts.
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(out.write_all(SEGMENT_MAGIC));
+        try!((self.docs.len() as u32).save(out));
+        for doc in &self.docs {
+            try!(doc.save(out));
+        }
+        let mut at = SEGMENT_MAGIC.len() as u64 + 4 + DOC_SIZE * self.docs.len() as u64;
+        let mut dictionary = BTreeMap::new();
+        for (term, postings) in &self.terms {
+            for posting in postings {
+                try!(posting.save(out));
+            }
+            dictionary.insert(term.clone(), (at, postings.len() as u32));
+            at += 8 * postings.len() as u64;
+        }
+        try!(dictionary.save(out));
+        at.save(out)
+    }
+
+    fn load<R: Read + Seek>(input: &mut R) -> ilc_base::Result<Segment> {
+        let (count, dictionary) = try!(read_dictionary(input));
+        let mut docs = Vec::new();
+        for i in 0..count {
+            docs.push(try!(read_doc(input, i)));
+        }
+        let mut terms = BTreeMap::new();
+        for (term, at) in dictionary {
+            terms.insert(term, try!(read_postings(input, at)));
+        }
+        Ok(Segment {
+            docs: docs,
+            terms: terms,
+        })
+    }
+}
+
+/// How many documents a segment has, and where the postings of each term are.
+fn read_dictionary<R: Read + Seek>(input: &mut R)
+                                   -> ilc_base::Result<(u32, BTreeMap<String, (u64, u32)>)> {
+    let mut magic = [0u8; 8];
+    try!(input.seek(SeekFrom::Start(0)));
+    if input.read_exact(&mut magic).is_err() || &magic[..] != SEGMENT_MAGIC {
+        return Err(Error::Parse("not an ilc index segment, or an incompatible version"
+                                    .to_owned()));
+    }
+    let count = try!(u32::load(input));
+    try!(input.seek(SeekFrom::End(-8)));
+    let start = try!(u64::load(input));
+    try!(input.seek(SeekFrom::Start(start)));
+    Ok((count, try!(Persist::load(input))))
+}
+
+fn read_doc<R: Read + Seek>(input: &mut R, doc: u32) -> ilc_base::Result<Doc> {
+    try!(input.seek(SeekFrom::Start(SEGMENT_MAGIC.len() as u64 + 4 + DOC_SIZE * doc as u64)));
+    Doc::load(input)
+}
+
+fn read_postings<R: Read + Seek>(input: &mut R,
+                                 (at, count): (u64, u32))
+                                 -> ilc_base::Result<Vec<Posting>> {
+    try!(input.seek(SeekFrom::Start(at)));
+    let mut postings = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        postings.push(try!(Posting::load(input)));
+    }
+    Ok(postings)
+}
+
+pub struct Index {
+    casemap: CaseMapping,
+    files: Vec<IndexedFile>,
+    /// In the order their events were indexed
+    segments: Vec<SegmentInfo>,
+    /// What was added since the last `commit`
+    pending: Segment,
+}
+
+impl Index {
+    pub fn new(casemap: CaseMapping) -> Index {
+        Index {
+            casemap: casemap,
+            files: Vec::new(),
+            segments: Vec::new(),
+            pending: Segment::default(),
+        }
+    }
+
+    /// Read an index file, as written by `save`.
+    pub fn load(input: &mut Read) -> ilc_base::Result<Index> {
+        let mut magic = [0u8; 8];
+        if input.read_exact(&mut magic).is_err() || &magic[..] != MAGIC {
+            return Err(Error::Parse("not an ilc index file, or an incompatible version"
+                                        .to_owned()));
+        }
+        Ok(Index {
+            casemap: try!(Persist::load(input)),
+            files: try!(Persist::load(input)),
+            segments: try!(Persist::load(input)),
+            pending: Segment::default(),
+        })
+    }
+
+    /// Write the list of files and segments. What was added since the last `commit` is lost.
+    pub fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(out.write_all(MAGIC));
+        try!(self.casemap.save(out));
+        try!(self.files.save(out));
+        try!(self.segments.save(out));
+        Ok(())
+    }
+
+    pub fn casemapping(&self) -> CaseMapping {
+        self.casemap
+    }
+
+    /// How many events are in the index.
+    pub fn len(&self) -> usize {
+        let pending = self.pending.docs.iter().filter(|d| self.live(d.file));
+        self.segments.iter().map(|s| s.docs as usize).sum::<usize>() + pending.count()
+    }
+
+    /// How many files are in the index.
+    pub fn file_count(&self) -> usize {
+        self.files.iter().filter(|f| f.len != FORGOTTEN).count()
+    }
+
+    /// Where to continue reading the file at `path`, which is `len` bytes long now. If the file
+    /// shrank, it was replaced, and its events are taken out of the index on the next `commit`.
+    pub fn resume_at(&mut self, path: &str, len: u64) -> u64 {
+        let file = match self.files.iter().position(|f| f.len != FORGOTTEN && f.path == path) {
+            Some(i) => i,
+            None => return 0,
+        };
+        let indexed = self.files[file].len;
+        if len >= indexed {
+            return indexed;
+        }
+        self.files[file].len = FORGOTTEN;
+        0
+    }
+
+    fn add(&mut self, ctx: &Context, e: &Event, file: u32, offset: u64) {
+        let segment = &mut self.pending;
+        let doc = segment.docs.len() as u32;
+        segment.docs.push(Doc {
+            file: file,
+            offset: offset,
+            time: match e.time {
+                Time::Timestamp(t) => Some(t),
+                _ => None,
+            },
+        });
+        let mut terms = vec![format!("type:{}", e.ty.type_desc())];
+        if let Some(nick) = e.ty.actor() {
+            terms.push(format!("nick:{}", self.casemap.to_lower(strip_nick(nick))));
+        }
+        let channel = channel_of(ctx, e);
+        if !channel.is_empty() {
+            terms.push(format!("channel:{}", channel));
+        }
+        for term in terms {
+            segment.terms.entry(term).or_insert_with(Vec::new).push(Posting { doc: doc, pos: 0 });
+        }
+        if let Some(text) = e.ty.text() {
+            for (pos, word) in words(text).into_iter().enumerate() {
+                segment.terms.entry(word).or_insert_with(Vec::new).push(Posting {
+                    doc: doc,
+                    pos: pos as u32,
+                });
+            }
+        }
+    }
+
+    /// Add the events of the file at `path`, read from `start`, where `resume_at` said to
+    /// continue. `input` has to be at that position already.
+    pub fn update(&mut self,
+                  ctx: &Context,
+                  path: &str,
+                  start: u64,
+                  input: &mut BufRead,
+                  decoder: &mut Decode)
+                  -> ilc_base::Result<()> {
+        if ctx.casemapping != self.casemap {
+            return Err(Error::Parse(format!("the index was built with the case mapping {:?}",
+                                            self.casemap)));
+        }
+        let file = match self.files.iter().position(|f| f.len != FORGOTTEN && f.path == path) {
+            Some(i) => i,
+            None => {
+                self.files.push(IndexedFile {
+                    path: path.to_owned(),
+                    len: 0,
+                });
+                self.files.len() - 1
+            }
+        };
+        let mut input = LineCount::new(input);
+        let read = input.offset();
+        // the event starts right after the one before it
+        let mut offset = 0;
+        for e in decoder.decode(&ctx, &mut input) {
+            let e = try!(e);
+            self.add(ctx, &e, file as u32, start + offset);
+            offset = read.get();
+        }
+        self.files[file].len = start + read.get();
+        Ok(())
+    }
+
+    /// Whether the file is still in the index.
+    fn live(&self, file: u32) -> bool {
+        self.files[file as usize].len != FORGOTTEN
+    }
+
+    /// Write what was added since the last commit as a new segment, and write the segments with
+    /// events of files that were read again anew. Returns the segments that aren't used anymore
+    /// once the index is saved.
+    pub fn commit<S: Store>(&mut self, store: &S) -> ilc_base::Result<Vec<u32>> {
+        let pending = mem::replace(&mut self.pending, Segment::default());
+        let mut next = self.segments.iter().map(|s| s.id + 1).max().unwrap_or(0);
+        let mut obsolete = Vec::new();
+        let mut segments = Vec::new();
+        for info in &self.segments {
+            if info.files.iter().all(|&f| self.live(f)) {
+                segments.push(info.clone());
+                continue;
+            }
+            let segment = try!(Segment::load(&mut try!(store.open(info.id))));
+            // written where it was, to keep the order of events
+            segments.extend(try!(self.write(store, segment, &mut next)));
+            obsolete.push(info.id);
+        }
+        segments.extend(try!(self.write(store, pending, &mut next)));
+        self.segments = segments;
+        Ok(obsolete)
+    }
+
+    /// Write `segment` as number `next` without events of files that were read again, unless
+    /// nothing is left.
+    fn write<S: Store>(&self,
+                       store: &S,
+                       segment: Segment,
+                       next: &mut u32)
+                       -> ilc_base::Result<Option<SegmentInfo>> {
+        let segment = segment.retain(|f| self.live(f));
+        if segment.docs.is_empty() {
+            return Ok(None);
+        }
+        try!(store.create(*next, &mut |out| segment.save(out)));
+        let mut files: Vec<u32> = segment.docs.iter().map(|d| d.file).collect();
+        files.sort();
+        files.dedup();
+        *next += 1;
+        Ok(Some(SegmentInfo {
+            id: *next - 1,
+            docs: segment.docs.len() as u32,
+            files: files,
+        }))
+    }
+
+    /// The documents of one segment that `clause` matches.
+    fn docs_of<R: Read + Seek>(input: &mut R,
+                               dictionary: &BTreeMap<String, (u64, u32)>,
+                               clause: &Clause)
+                               -> ilc_base::Result<HashSet<u32>> {
+        match *clause {
+            Clause::Term(ref term) => {
+                match dictionary.get(term) {
+                    Some(&at) => Ok(try!(read_postings(input, at)).iter().map(|p| p.doc).collect()),
+                    None => Ok(HashSet::new()),
+                }
+            }
+            Clause::Phrase(ref words) => {
+                let mut postings = Vec::new();
+                for word in words {
+                    match dictionary.get(word) {
+                        Some(&at) => postings.push(try!(read_postings(input, at))),
+                        None => return Ok(HashSet::new()),
+                    }
+                }
+                let mut starts: Vec<(u32, u32)> = postings[0].iter()
+                                                             .map(|p| (p.doc, p.pos))
+                                                             .collect();
+                for (i, next) in postings.iter().enumerate().skip(1) {
+                    let at: HashSet<(u32, u32)> = next.iter().map(|p| (p.doc, p.pos)).collect();
+                    starts.retain(|&(doc, pos)| at.contains(&(doc, pos + i as u32)));
+                }
+                Ok(starts.into_iter().map(|(doc, _)| doc).collect())
+            }
+        }
+    }
+
+    /// The events matching `query` from `since` until `until`, both inclusive, in the order
+    /// they were indexed. Only what was committed is searched.
+    pub fn search<S: Store>(&self,
+                            store: &S,
+                            query: &Query,
+                            since: Option<i64>,
+                            until: Option<i64>)
+                            -> ilc_base::Result<Vec<Hit>> {
+        let mut hits = Vec::new();
+        for info in &self.segments {
+            let mut input = try!(store.open(info.id));
+            let (count, dictionary) = try!(read_dictionary(&mut input));
+            let mut found: HashSet<u32> = HashSet::new();
+            for &(ref all, ref none) in &query.any {
+                let mut docs: HashSet<u32> = match all.first() {
+                    Some(clause) => try!(Index::docs_of(&mut input, &dictionary, clause)),
+                    None => (0..count).collect(),
+                };
+                for clause in all.iter().skip(1) {
+                    let other = try!(Index::docs_of(&mut input, &dictionary, clause));
+                    docs.retain(|d| other.contains(d));
+                }
+                for clause in none {
+                    let other = try!(Index::docs_of(&mut input, &dictionary, clause));
+                    docs.retain(|d| !other.contains(d));
+                }
+                found.extend(docs);
+            }
+
+            let mut found: Vec<u32> = found.into_iter().collect();
+            found.sort();
+            for doc in found {
+                let d = try!(read_doc(&mut input, doc));
+                let wanted = match d.time {
+                    Some(t) => since.map_or(true, |s| t >= s) && until.map_or(true, |u| t <= u),
+                    None => since.is_none() && until.is_none(),
+                };
+                if self.live(d.file) && wanted {
+                    hits.push(Hit {
+                        path: &self.files[d.file as usize].path,
+                        offset: d.offset,
+                        time: d.time,
+                    });
+                }
+            }
+        }
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io::{Cursor, Write};
+
+    use ilc_base::{self, CaseMapping, Context, Error};
+
+    use fixtures::Lines;
+    use super::{Clause, Index, Query, Store};
+    use super::Clause::*;
+
+    /// Keeps segments in memory.
+    #[derive(Default)]
+    struct Memory(RefCell<HashMap<u32, Vec<u8>>>);
+
+    impl Store for Memory {
+        type Segment = Cursor<Vec<u8>>;
+
+        fn open(&self, segment: u32) -> ilc_base::Result<Cursor<Vec<u8>>> {
+            match self.0.borrow().get(&segment) {
+                Some(bytes) => Ok(Cursor::new(bytes.clone())),
+                None => Err(Error::Parse(format!("no segment {}", segment))),
+            }
+        }
+
+        fn create(&self,
+                  segment: u32,
+                  write: &mut FnMut(&mut Write) -> ilc_base::Result<()>)
+                  -> ilc_base::Result<()> {
+            let mut bytes = Vec::new();
+            try!(write(&mut bytes));
+            self.0.borrow_mut().insert(segment, bytes);
+            Ok(())
+        }
+    }
+
+    fn parse(s: &str) -> Vec<(Vec<Clause>, Vec<Clause>)> {
+        Query::parse(CaseMapping::Rfc1459, s).unwrap().any
+    }
+
+    fn term(s: &str) -> Clause {
+        Term(s.to_owned())
+    }
+
+    fn phrase(words: &[&str]) -> Clause {
+        Phrase(words.iter().map(|w| w.to_string()).collect())
+    }
+
+    #[test]
+    fn parse_terms() {
+        assert_eq!(parse("Hello  world"), vec![(vec![term("hello"), term("world")], vec![])]);
+        assert_eq!(parse("\"Hello, world\" it's"),
+                   vec![(vec![phrase(&["hello", "world"]), phrase(&["it", "s"])], vec![])]);
+        assert_eq!(parse("nick:@Foo[m] channel:#ILC type:message"),
+                   vec![(vec![term("nick:foo{m}"), term("channel:#ilc"), term("type:message")],
+                         vec![])]);
+        // only punctuation has no words to look for
+        assert_eq!(parse("hi -- !"), vec![(vec![term("hi")], vec![])]);
+    }
+
+    #[test]
+    fn parse_negation_and_alternatives() {
+        assert_eq!(parse("hello -world NOT \"good bye\""),
+                   vec![(vec![term("hello")], vec![term("world"), phrase(&["good", "bye"])])]);
+        assert_eq!(parse("hello OR -nick:bar OR a b"),
+                   vec![(vec![term("hello")], vec![]),
+                        (vec![], vec![term("nick:bar")]),
+                        (vec![term("a"), term("b")], vec![])]);
+        // quoted, OR is a word
+        assert_eq!(parse("\"OR\""), vec![(vec![term("or")], vec![])]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for query in &["", "  ", "!!", "\"unclosed", "OR hello", "hello OR", "a OR OR b"] {
+            assert!(Query::parse(CaseMapping::Rfc1459, query).is_err(), "{}", query);
+        }
+    }
+
+    fn update(index: &mut Index, path: &str, log: &str) {
+        let ctx = Context::default();
+        let start = index.resume_at(path, log.len() as u64);
+        let mut input = &log.as_bytes()[start as usize..];
+        index.update(&ctx, path, start, &mut input, &mut Lines).unwrap();
+    }
+
+    fn search(index: &Index, store: &Memory, query: &str) -> Vec<(String, u64)> {
+        let query = Query::parse(CaseMapping::Rfc1459, query).unwrap();
+        index.search(store, &query, None, None)
+             .unwrap()
+             .into_iter()
+             .map(|hit| (hit.path.to_owned(), hit.offset))
+             .collect()
+    }
+
+    fn hit(path: &str, offset: u64) -> (String, u64) {
+        (path.to_owned(), offset)
+    }
+
+    fn roundtrip(index: &Index) -> Index {
+        let mut out = Vec::new();
+        index.save(&mut out).unwrap();
+        Index::load(&mut &out[..]).unwrap()
+    }
+
+    #[test]
+    fn segments() {
+        let store = Memory::default();
+        let mut index = Index::new(CaseMapping::Rfc1459);
+        let a = "10 foo hello world\n20 bar good bye world\n";
+        update(&mut index, "a", a);
+        assert_eq!(index.commit(&store).unwrap(), Vec::<u32>::new());
+        let mut index = roundtrip(&index);
+        assert_eq!(search(&index, &store, "world"), vec![hit("a", 0), hit("a", 19)]);
+
+        // only the new events are read, into a segment of their own
+        let a = "10 foo hello world\n20 bar good bye world\n30 foo bye\n";
+        update(&mut index, "a", a);
+        update(&mut index, "b", "40 baz hello bye\n");
+        index.commit(&store).unwrap();
+        let index = roundtrip(&index);
+        assert_eq!(store.0.borrow().len(), 2);
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.file_count(), 2);
+        assert_eq!(search(&index, &store, "bye -good"), vec![hit("a", 41), hit("b", 0)]);
+        assert_eq!(search(&index, &store, "\"good bye\" OR nick:BAZ"),
+                   vec![hit("a", 19), hit("b", 0)]);
+        assert_eq!(search(&index, &store, "\"world good\""), vec![]);
+        let query = Query::parse(CaseMapping::Rfc1459, "bye").unwrap();
+        let times: Vec<_> = index.search(&store, &query, Some(25), Some(40))
+                                 .unwrap()
+                                 .into_iter()
+                                 .map(|hit| hit.time)
+                                 .collect();
+        assert_eq!(times, vec![Some(30), Some(40)]);
+    }
+
+    #[test]
+    fn compaction() {
+        let store = Memory::default();
+        let mut index = Index::new(CaseMapping::Rfc1459);
+        update(&mut index, "a", "10 foo hello\n20 bar hello\n");
+        update(&mut index, "b", "30 baz hello\n");
+        index.commit(&store).unwrap();
+        update(&mut index, "b", "30 baz hello\n40 baz hello again\n");
+        index.commit(&store).unwrap();
+
+        // a shrank, so it was replaced
+        update(&mut index, "a", "50 qux hello\n");
+        let obsolete = index.commit(&store).unwrap();
+        assert_eq!(obsolete, vec![0]);
+        let index = roundtrip(&index);
+        for segment in obsolete {
+            store.0.borrow_mut().remove(&segment);
+        }
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.file_count(), 2);
+        assert_eq!(search(&index, &store, "hello"),
+                   vec![hit("b", 0), hit("b", 13), hit("a", 0)]);
+        assert_eq!(search(&index, &store, "nick:foo OR nick:bar"), vec![]);
+    }
+}
@@ -0,0 +1,270 @@
+//! Who talks to whom
+//!
+//! Messages and actions are connected to the nicks they address (`nick: text`),
+//! tag (`@nick`) or mention, and to the message they directly follow. Only nicks
+//! that acted earlier in the same log are recognised, so the graph isn't
+//! cluttered with every word that could be a nick.
+use ilc_base::{self, Context, Decode, Event, Time};
+use ilc_base::event::Type;
+use identity::{Identities, Mergeable};
+use stats::strip_nick;
+
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+use serde::ser::{MapVisitor, Serialize, Serializer};
+
+/// Replies further apart than this, in seconds, are not counted as replies.
+pub const REPLY_WINDOW: i64 = 300;
+
+#[derive(Clone, Debug, Default)]
+pub struct Edge {
+    /// `nick: text` or `nick, text`
+    pub addressed: u32,
+    /// `@nick`, or the nick anywhere else in the text
+    pub mentioned: u32,
+    /// Spoke right after the other nick, in the same channel
+    pub replied: u32,
+}
+
+impl Edge {
+    pub fn weight(&self) -> u32 {
+        self.addressed + self.mentioned + self.replied
+    }
+
+    fn merge(&mut self, other: &Edge) {
+        self.addressed += other.addressed;
+        self.mentioned += other.mentioned;
+        self.replied += other.replied;
+    }
+}
+
+pub struct Node {
+    pub nick: String,
+    /// Messages and actions
+    pub lines: u32,
+}
+
+impl Mergeable for Node {
+    fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    fn empty(person: &str) -> Node {
+        Node {
+            nick: person.to_owned(),
+            lines: 0,
+        }
+    }
+
+    fn merge(&mut self, other: Node) {
+        self.lines += other.lines;
+    }
+}
+
+/// A weighted, directed graph, keyed by nicks in canonical case.
+pub struct Graph {
+    pub nodes: HashMap<String, Node>,
+    /// From the speaker to the nick spoken to
+    pub edges: HashMap<(String, String), Edge>,
+}
+
+impl Serialize for Graph {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct NodeSer<'a>(&'a str, &'a Node);
+        impl<'a> Serialize for NodeSer<'a> {
+            fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+                where S: Serializer
+            {
+                struct Visitor<'a>(&'a NodeSer<'a>);
+                impl<'a> MapVisitor for Visitor<'a> {
+                    fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                        where S: Serializer
+                    {
+                        try!(s.serialize_struct_elt("id", (self.0).0));
+                        try!(s.serialize_struct_elt("nick", &(self.0).1.nick));
+                        try!(s.serialize_struct_elt("lines", (self.0).1.lines));
+                        Ok(None)
+                    }
+
+                    fn len(&self) -> Option<usize> {
+                        Some(3)
+                    }
+                }
+                s.serialize_struct("Node", Visitor(self))
+            }
+        }
+
+        struct EdgeSer<'a>(&'a (String, String), &'a Edge);
+        impl<'a> Serialize for EdgeSer<'a> {
+            fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+                where S: Serializer
+            {
+                struct Visitor<'a>(&'a EdgeSer<'a>);
+                impl<'a> MapVisitor for Visitor<'a> {
+                    fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                        where S: Serializer
+                    {
+                        let edge = (self.0).1;
+                        try!(s.serialize_struct_elt("from", &((self.0).0).0));
+                        try!(s.serialize_struct_elt("to", &((self.0).0).1));
+                        try!(s.serialize_struct_elt("weight", edge.weight()));
+                        try!(s.serialize_struct_elt("addressed", edge.addressed));
+                        try!(s.serialize_struct_elt("mentioned", edge.mentioned));
+                        try!(s.serialize_struct_elt("replied", edge.replied));
+                        Ok(None)
+                    }
+
+                    fn len(&self) -> Option<usize> {
+                        Some(6)
+                    }
+                }
+                s.serialize_struct("Edge", Visitor(self))
+            }
+        }
+
+        struct Visitor<'a>(&'a Graph);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                let mut nodes: Vec<NodeSer> = self.0
+                                                  .nodes
+                                                  .iter()
+                                                  .map(|(k, n)| NodeSer(k, n))
+                                                  .collect();
+                nodes.sort_by(|a, b| a.0.cmp(b.0));
+                let mut edges: Vec<EdgeSer> = self.0
+                                                  .edges
+                                                  .iter()
+                                                  .map(|(k, e)| EdgeSer(k, e))
+                                                  .collect();
+                edges.sort_by(|a, b| a.0.cmp(b.0));
+                try!(s.serialize_struct_elt("nodes", nodes));
+                try!(s.serialize_struct_elt("edges", edges));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(2)
+            }
+        }
+
+        s.serialize_struct("Graph", Visitor(self))
+    }
+}
+
+fn trim_nick(s: &str) -> &str {
+    s.trim_matches(|c| ".,:;!?'\"()<>".contains(c))
+}
+
+/// Build the interaction graph of a log. Edges of less than `min_weight` are left out.
+/// With `identities`, nicks of the same person are one node, under their canonical name.
+pub fn interactions(ctx: &Context,
+                    input: &mut BufRead,
+                    decoder: &mut Decode,
+                    min_weight: u32,
+                    mut identities: Option<&mut Identities>)
+                    -> ilc_base::Result<Graph> {
+    let cm = ctx.casemapping;
+    // canonical case to spelling
+    let mut actors: HashMap<String, String> = HashMap::new();
+    let mut nodes: HashMap<String, Node> = HashMap::new();
+    let mut edges: HashMap<(String, String), Edge> = HashMap::new();
+    // the last speaker and time, per channel
+    let mut last: HashMap<String, (String, i64)> = HashMap::new();
+
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        if let Some(ref mut ids) = identities {
+            ids.observe(&e);
+        }
+        let new_nick = match e.ty {
+            Type::Nick { ref new_nick, .. } => Some(new_nick),
+            _ => None,
+        };
+        for actor in e.ty.actor().into_iter().chain(new_nick.map(|n| n as &str)) {
+            let actor = strip_nick(actor);
+            actors.entry(cm.to_lower(actor)).or_insert_with(|| actor.to_owned());
+        }
+
+        let (from, content) = match e {
+            Event { ty: Type::Msg { ref from, ref content }, .. } |
+            Event { ty: Type::Action { ref from, ref content }, .. } => (from, content),
+            _ => continue,
+        };
+        let nick = strip_nick(from);
+        let key = cm.to_lower(nick);
+        nodes.entry(key.clone())
+             .or_insert_with(|| {
+                 Node {
+                     nick: nick.to_owned(),
+                     lines: 0,
+                 }
+             })
+             .lines += 1;
+
+        let mut seen = HashSet::new();
+        for (i, word) in content.split_whitespace().enumerate() {
+            let addressed = i == 0 && (word.ends_with(':') || word.ends_with(','));
+            let target = cm.to_lower(strip_nick(trim_nick(word.trim_left_matches('@'))));
+            if target == key || !actors.contains_key(&target) || !seen.insert(target.clone()) {
+                continue;
+            }
+            let edge = edges.entry((key.clone(), target)).or_insert_with(Edge::default);
+            if addressed {
+                edge.addressed += 1;
+            } else {
+                edge.mentioned += 1;
+            }
+        }
+
+        // how long ago the last message was is unknown without a full date
+        let time = match e.time {
+            Time::Timestamp(t) => t,
+            _ => continue,
+        };
+        let channel = e.channel.as_ref().map(|c| cm.to_lower(c)).unwrap_or_default();
+        if let Some(&(ref prev, prev_time)) = last.get(&channel) {
+            if *prev != key && time - prev_time <= REPLY_WINDOW {
+                edges.entry((key.clone(), prev.clone()))
+                     .or_insert_with(Edge::default)
+                     .replied += 1;
+            }
+        }
+        last.insert(channel, (key, time));
+    }
+
+    for &(_, ref to) in edges.keys() {
+        if !nodes.contains_key(to) {
+            nodes.insert(to.clone(),
+                         Node {
+                             nick: actors[to].clone(),
+                             lines: 0,
+                         });
+        }
+    }
+
+    if let Some(ids) = identities {
+        let person = |key: &str, nodes: &HashMap<String, Node>| {
+            let nick = nodes.get(key).map_or(key, |n| &n.nick);
+            cm.to_lower(&ids.resolve(nick))
+        };
+        let mut merged_edges: HashMap<(String, String), Edge> = HashMap::new();
+        for ((from, to), edge) in edges {
+            let (from, to) = (person(&from, &nodes), person(&to, &nodes));
+            if from != to {
+                merged_edges.entry((from, to)).or_insert_with(Edge::default).merge(&edge);
+            }
+        }
+        edges = merged_edges;
+        nodes = ids.merge(nodes);
+    }
+
+    Ok(Graph {
+        nodes: nodes,
+        edges: edges.into_iter().filter(|&(_, ref e)| e.weight() >= min_weight).collect(),
+    })
+}
@@ -6,25 +6,60 @@ extern crate serde;
 extern crate chrono;
 extern crate ilc_base;
 extern crate regex;
+extern crate unicode_segmentation;
 
 mod ageset;
+#[cfg(test)]
+mod fixtures;
 pub mod stats;
 pub mod convert;
+pub mod audit;
+pub mod grep;
+pub mod identity;
+pub mod index;
+pub mod interactions;
+pub mod members;
+pub mod mentions;
+pub mod modes;
+pub mod parallel;
+pub mod seen;
+pub mod sessions;
+pub mod state;
+pub mod timeindex;
+pub mod topics;
+pub mod urls;
+pub mod words;
 
 /// No-op log parsing
 pub mod parse {
     use ilc_base::{self, Context, Decode};
+    use parallel::{self, Input};
     use std::io::BufRead;
 
-    /// Simply parse the input, without further validation or conversion. No information is stored.
-    /// This will return `Err` if the decoder yields `Err`.
-    pub fn parse(ctx: &Context, input: &mut BufRead, decoder: &mut Decode) -> ilc_base::Result<()> {
+    fn log_events(ctx: &Context, input: &mut BufRead, decoder: &Decode) {
         for e in decoder.decode(&ctx, input) {
             match e {
                 Ok(e) => debug!("{:?}", e),
                 Err(e) => error!("{:?}", e),
             }
         }
+    }
+
+    /// Simply parse the input, without further validation or conversion. No information is stored.
+    /// This will return `Err` if the decoder yields `Err`.
+    pub fn parse(ctx: &Context, input: &mut BufRead, decoder: &mut Decode) -> ilc_base::Result<()> {
+        log_events(ctx, input, decoder);
+        Ok(())
+    }
+
+    /// Like `parse`, with every input parsed in a worker thread of its own, up to `threads` at
+    /// once.
+    pub fn parse_files(ctx: &Context,
+                       inputs: Vec<Input>,
+                       decoder: Box<Decode + Send + Sync>,
+                       threads: usize)
+                       -> ilc_base::Result<()> {
+        parallel::each(ctx, inputs, decoder, threads, log_events);
         Ok(())
     }
 }
@@ -63,17 +98,31 @@ pub mod dedup {
     use std::io::{BufRead, Write};
     use std::hash::{Hash, Hasher};
     use ageset::AgeSet;
-    use ilc_base::{self, Context, Decode, Encode, Event};
+    use ilc_base::{self, Context, Decode, Encode};
+    use ilc_base::event::Type;
+
+    /// An event with nicks and channel in canonical case, compared without regard to time.
+    #[derive(Clone, Debug)]
+    struct NoTimeHash {
+        ty: Type<'static>,
+        channel: Option<String>,
+        time: i64,
+    }
 
-    #[derive(Clone, Debug, PartialEq, Eq)]
-    struct NoTimeHash<'a>(pub Event<'a>);
+    impl PartialEq for NoTimeHash {
+        fn eq(&self, other: &NoTimeHash) -> bool {
+            self.ty == other.ty && self.channel == other.channel
+        }
+    }
 
-    impl<'a> Hash for NoTimeHash<'a> {
+    impl Eq for NoTimeHash {}
+
+    impl Hash for NoTimeHash {
         fn hash<H>(&self, state: &mut H)
             where H: Hasher
         {
-            self.0.ty.hash(state);
-            self.0.channel.hash(state);
+            self.ty.hash(state);
+            self.channel.hash(state);
         }
     }
 
@@ -81,6 +130,9 @@ pub mod dedup {
     /// operation. This will **not** read all events into memory, and only
     /// operate on a short window of events. Therefore, it'll only work correctly
     /// on sorted or very short logs.
+    ///
+    /// Nicks and channels that only differ by case (as defined by the context's
+    /// case mapping) are considered identical.
     pub fn dedup(ctx: &Context,
                  input: &mut BufRead,
                  decoder: &mut Decode,
@@ -88,18 +140,23 @@ pub mod dedup {
                  encoder: &Encode)
                  -> ilc_base::Result<()> {
         let mut backlog = AgeSet::new();
+        let casemap = ctx.casemapping;
 
         for e in decoder.decode(&ctx, input) {
             if let Ok(e) = e {
-                let newest_event = e.clone();
+                let newest = e.time.as_timestamp();
                 backlog.prune(move |a: &NoTimeHash| {
-                    let age = newest_event.time.as_timestamp() - a.0.time.as_timestamp();
+                    let age = newest - a.time;
                     age > 5000
                 });
                 // write `e` if it's a new event
-                let n = NoTimeHash(e);
+                let n = NoTimeHash {
+                    ty: e.ty.map_nicks(|n| casemap.to_lower(n)),
+                    channel: e.channel.as_ref().map(|c| casemap.to_lower(c)),
+                    time: newest,
+                };
                 if !backlog.contains(&n) {
-                    try!(encoder.encode(&ctx, output, &n.0));
+                    try!(encoder.encode(&ctx, output, &e));
                     backlog.push(n);
                 }
             }
@@ -0,0 +1,177 @@
+//! Who is in a channel
+//!
+//! Logs rarely say who is in a channel, only who comes and goes. `Members`
+//! follows joins, parts, quits, kicks and nick changes, and counts anyone
+//! talking in a channel as present, since logs usually start with people
+//! already there. When the logger disconnects, it can't know who is around
+//! anymore, so every channel is emptied until people show up again. None of
+//! the formats keep NAMES replies, so there are no snapshots of a channel to
+//! start from.
+use ilc_base::{self, CaseMapping, Context, Decode, Event, Time};
+use ilc_base::event::Type;
+use stats::strip_nick;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+
+use serde::ser::{Serialize, Serializer};
+
+pub struct Members {
+    casemap: CaseMapping,
+    /// Members by channel, both in canonical case, with the spelling of the nick
+    channels: HashMap<String, BTreeMap<String, String>>,
+}
+
+/// The channel of `e` in canonical case, or empty if it's unknown.
+pub fn channel_of(ctx: &Context, e: &Event) -> String {
+    e.channel
+     .as_ref()
+     .map(|c| ctx.casemapping.to_lower(c))
+     .or(ctx.channel.as_ref().map(|c| ctx.casemapping.to_lower(c)))
+     .unwrap_or_default()
+}
+
+impl Members {
+    pub fn new(casemap: CaseMapping) -> Members {
+        Members {
+            casemap: casemap,
+            channels: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, channel: String, nick: &str) {
+        let nick = strip_nick(nick);
+        self.channels
+            .entry(channel)
+            .or_insert_with(BTreeMap::new)
+            .insert(self.casemap.to_lower(nick), nick.to_owned());
+    }
+
+    fn remove(&mut self, channel: &str, nick: &str) {
+        let key = self.casemap.to_lower(strip_nick(nick));
+        if let Some(members) = self.channels.get_mut(channel) {
+            members.remove(&key);
+        }
+    }
+
+    /// Follow a single event.
+    pub fn apply(&mut self, ctx: &Context, e: &Event) {
+        match e.ty {
+            Type::Join { ref nick, .. } |
+            Type::Msg { from: ref nick, .. } |
+            Type::Action { from: ref nick, .. } => self.add(channel_of(ctx, e), nick),
+            Type::Part { ref nick, .. } |
+            Type::Kick { kicked_nick: ref nick, .. } => self.remove(&channel_of(ctx, e), nick),
+            Type::Quit { ref nick, .. } => {
+                let key = self.casemap.to_lower(strip_nick(nick));
+                for members in self.channels.values_mut() {
+                    members.remove(&key);
+                }
+            }
+            Type::Nick { ref old_nick, ref new_nick } => {
+                let old = self.casemap.to_lower(strip_nick(old_nick));
+                let new_nick = strip_nick(new_nick);
+                let new = self.casemap.to_lower(new_nick);
+                for members in self.channels.values_mut() {
+                    if members.remove(&old).is_some() {
+                        members.insert(new.clone(), new_nick.to_owned());
+                    }
+                }
+            }
+            Type::Disconnect => {
+                for members in self.channels.values_mut() {
+                    members.clear();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Channels with members, in canonical case and sorted.
+    pub fn channels(&self) -> Vec<&str> {
+        let mut channels: Vec<&str> = self.channels
+                                          .iter()
+                                          .filter(|&(_, m)| !m.is_empty())
+                                          .map(|(c, _)| c.as_str())
+                                          .collect();
+        channels.sort();
+        channels
+    }
+
+    /// The nicks in `channel`, in order of their canonical case.
+    pub fn members(&self, channel: &str) -> Vec<&str> {
+        self.channels
+            .get(&self.casemap.to_lower(channel))
+            .map(|m| m.values().map(|n| n.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn count(&self, channel: &str) -> usize {
+        self.channels.get(&self.casemap.to_lower(channel)).map_or(0, |m| m.len())
+    }
+
+    pub fn contains(&self, channel: &str, nick: &str) -> bool {
+        self.channels
+            .get(&self.casemap.to_lower(channel))
+            .map_or(false, |m| m.contains_key(&self.casemap.to_lower(strip_nick(nick))))
+    }
+}
+
+impl Serialize for Members {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        let channels: BTreeMap<&str, Vec<&str>> = self.channels()
+                                                      .into_iter()
+                                                      .map(|c| (c, self.members(c)))
+                                                      .collect();
+        channels.serialize(s)
+    }
+}
+
+/// Who was in which channel at `time`, after every event up to and including it.
+/// The input may consist of several logs, so it is read to the end. Events without a full date
+/// are followed, unless they come after a later one.
+pub fn members_at(ctx: &Context,
+                  input: &mut BufRead,
+                  decoder: &mut Decode,
+                  time: i64)
+                  -> ilc_base::Result<Members> {
+    let mut members = Members::new(ctx.casemapping);
+    let mut past = false;
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        if let Time::Timestamp(t) = e.time {
+            past = t > time;
+        }
+        if !past {
+            members.apply(ctx, &e);
+        }
+    }
+    Ok(members)
+}
+
+/// How many members every channel had over time, as the times the number changed, and the new
+/// number. Only events with a full date are counted.
+pub fn population(ctx: &Context,
+                  input: &mut BufRead,
+                  decoder: &mut Decode)
+                  -> ilc_base::Result<HashMap<String, Vec<(i64, usize)>>> {
+    let mut members = Members::new(ctx.casemapping);
+    let mut population: HashMap<String, Vec<(i64, usize)>> = HashMap::new();
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        members.apply(ctx, &e);
+        let time = match e.time {
+            Time::Timestamp(t) => t,
+            _ => continue,
+        };
+        for (channel, m) in &members.channels {
+            let last = population.get(channel).and_then(|c| c.last()).map_or(0, |&(_, n)| n);
+            if last != m.len() {
+                population.entry(channel.clone()).or_insert_with(Vec::new).push((time, m.len()));
+            }
+        }
+    }
+    Ok(population)
+}
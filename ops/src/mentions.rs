@@ -0,0 +1,121 @@
+//! Finding where a nick was mentioned
+//!
+//! A message, action or notice mentions a nick if one of its words is the
+//! nick, so `bob` is found in `bob: hi` and `@bob`, but not in `bobcat`. With
+//! identities, the other nicks of the same person count too, as far as they are
+//! known at that point of the log. Every hit comes with surrounding events from
+//! the same channel, and hits close enough to share context are kept in one
+//! excerpt.
+use ilc_base::{self, Context, Decode, Event};
+use ilc_base::event::Type;
+use identity::Identities;
+use members::channel_of;
+use stats::strip_nick;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::BufRead;
+
+/// Consecutive events of one channel.
+pub struct Excerpt {
+    /// In canonical case, or empty if it's unknown
+    pub channel: String,
+    /// With whether each event is a hit, rather than context
+    pub events: Vec<(Event<'static>, bool)>,
+    /// The position of the first event in the log, to order excerpts of different channels
+    start: usize,
+}
+
+/// Characters nicks may consist of, see RFC 2812.
+fn is_nick_char(c: char) -> bool {
+    c.is_alphanumeric() || "[]\\`_^{|}-".contains(c)
+}
+
+/// Where the context of a channel is at.
+#[derive(Default)]
+struct Channel {
+    /// The latest excerpt, which later hits may still extend
+    current: Option<Excerpt>,
+    /// How many events still belong to the current excerpt as context after its last hit
+    left: usize,
+    /// The last events that aren't part of an excerpt, at most as many as the context
+    before: VecDeque<(usize, Event<'static>)>,
+    /// Events since the current excerpt ended
+    gap: usize,
+}
+
+/// Every mention of `nick`, with `context` events before and after each hit. Events `nick`
+/// acted in themselves are not hits.
+pub fn mentions(ctx: &Context,
+                input: &mut BufRead,
+                decoder: &mut Decode,
+                nick: &str,
+                context: usize,
+                mut identities: Option<&mut Identities>)
+                -> ilc_base::Result<Vec<Excerpt>> {
+    let cm = ctx.casemapping;
+    let mut channels: HashMap<String, Channel> = HashMap::new();
+    let mut excerpts = Vec::new();
+
+    for (i, e) in decoder.decode(&ctx, input).enumerate() {
+        let e = try!(e);
+        if let Some(ref mut ids) = identities {
+            ids.observe(&e);
+        }
+        let hit = {
+            let is = |other: &str| {
+                match identities {
+                    Some(ref ids) => ids.same(other, nick),
+                    None => cm.eq(other, nick),
+                }
+            };
+            match e.ty {
+                Type::Msg { ref from, ref content } |
+                Type::Action { ref from, ref content } |
+                Type::Notice { ref from, ref content } => {
+                    !is(strip_nick(from)) &&
+                    content.split(|c| !is_nick_char(c)).any(|w| !w.is_empty() && is(w))
+                }
+                _ => false,
+            }
+        };
+
+        let name = channel_of(ctx, &e);
+        let channel = channels.entry(name.clone()).or_insert_with(Channel::default);
+        if hit {
+            // with no more events in between than the context, the excerpts touch
+            if channel.gap > context {
+                excerpts.extend(channel.current.take());
+            }
+            let before = &mut channel.before;
+            let excerpt = channel.current.get_or_insert_with(|| {
+                Excerpt {
+                    channel: name,
+                    events: Vec::new(),
+                    start: before.front().map_or(i, |&(j, _)| j),
+                }
+            });
+            excerpt.events.extend(before.drain(..).map(|(_, e)| (e, false)));
+            excerpt.events.push((e.into_owned(), true));
+            channel.left = context;
+            channel.gap = 0;
+        } else if channel.current.is_some() && channel.left > 0 {
+            channel.current.as_mut().unwrap().events.push((e.into_owned(), false));
+            channel.left -= 1;
+        } else {
+            channel.before.push_back((i, e.into_owned()));
+            if channel.before.len() > context {
+                channel.before.pop_front();
+            }
+            channel.gap += 1;
+            if channel.gap > context {
+                excerpts.extend(channel.current.take());
+            }
+        }
+    }
+
+    for (_, channel) in channels {
+        excerpts.extend(channel.current);
+    }
+    excerpts.sort_by_key(|e| e.start);
+    Ok(excerpts)
+}
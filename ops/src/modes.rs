@@ -0,0 +1,424 @@
+//! Channel modes over time
+//!
+//! Mode events only carry the mode string and its parameters, e.g. `+ov-b` and
+//! `alice bob *!*@host`. Which letters take a parameter depends on the server,
+//! which announces it as `CHANMODES` and `PREFIX` in `RPL_ISUPPORT`. `ModeSpec`
+//! holds that information, `parse_modes` splits mode strings into single
+//! changes, and `Modes` replays them to know the state of every channel.
+use ilc_base::{self, CaseMapping, Context, Decode, Event, Time};
+use ilc_base::event::Type;
+use members::channel_of;
+use stats::strip_nick;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+
+#[derive(Clone, Debug)]
+pub struct ModeSpec {
+    /// Modes that maintain a list of masks, e.g. bans, always with a parameter
+    pub lists: String,
+    /// Always with a parameter, e.g. the key
+    pub always: String,
+    /// Only with a parameter when set, e.g. the user limit
+    pub when_set: String,
+    /// Never with a parameter
+    pub flags: String,
+    /// Modes given to members, with the prefix shown in front of their nick, highest first
+    pub prefixes: Vec<(char, char)>,
+}
+
+impl Default for ModeSpec {
+    /// What most networks use.
+    fn default() -> ModeSpec {
+        ModeSpec::parse("beI,k,l,imnpst", "(qaohv)~&@%+").unwrap()
+    }
+}
+
+impl ModeSpec {
+    /// From `CHANMODES` and `PREFIX` as servers announce them, e.g. `beI,k,l,imnpst` and
+    /// `(ov)@+`.
+    pub fn parse(chanmodes: &str, prefix: &str) -> Option<ModeSpec> {
+        let groups: Vec<&str> = chanmodes.split(',').collect();
+        if groups.len() < 4 || !prefix.starts_with('(') {
+            return None;
+        }
+        let close = match prefix.find(')') {
+            Some(i) => i,
+            None => return None,
+        };
+        let (modes, symbols) = (&prefix[1..close], &prefix[close + 1..]);
+        if modes.chars().count() != symbols.chars().count() {
+            return None;
+        }
+        Some(ModeSpec {
+            lists: groups[0].to_owned(),
+            always: groups[1].to_owned(),
+            when_set: groups[2].to_owned(),
+            flags: groups[3].to_owned(),
+            prefixes: modes.chars().zip(symbols.chars()).collect(),
+        })
+    }
+
+    pub fn is_list(&self, mode: char) -> bool {
+        self.lists.contains(mode)
+    }
+
+    pub fn is_prefix(&self, mode: char) -> bool {
+        self.prefixes.iter().any(|&(m, _)| m == mode)
+    }
+
+    /// Letters nobody announced are assumed to be flags.
+    fn takes_param(&self, mode: char, add: bool) -> bool {
+        self.is_list(mode) || self.is_prefix(mode) || self.always.contains(mode) ||
+        (add && self.when_set.contains(mode))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModeChange {
+    /// Whether the mode is set, rather than unset
+    pub add: bool,
+    pub mode: char,
+    pub param: Option<String>,
+}
+
+/// Split `modes`, e.g. `+ov-b`, into single changes, taking parameters from `params` in order.
+/// Changes missing a parameter they need are left out.
+pub fn parse_modes(spec: &ModeSpec, modes: &str, params: &str) -> Vec<ModeChange> {
+    let mut params = params.split_whitespace();
+    let mut add = true;
+    let mut changes = Vec::new();
+    for c in modes.chars() {
+        match c {
+            '+' => add = true,
+            '-' => add = false,
+            mode => {
+                let param = if spec.takes_param(mode, add) {
+                    match params.next() {
+                        Some(p) => Some(p.to_owned()),
+                        None => continue,
+                    }
+                } else {
+                    None
+                };
+                changes.push(ModeChange {
+                    add: add,
+                    mode: mode,
+                    param: param,
+                });
+            }
+        }
+    }
+    changes
+}
+
+#[derive(Clone, Debug)]
+pub struct ListEntry {
+    pub mask: String,
+    pub set_by: Option<String>,
+    /// Only known for logs with full dates
+    pub time: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChannelModes {
+    /// Members holding each prefix mode, in canonical case, with their spelling
+    pub prefixes: BTreeMap<char, BTreeMap<String, String>>,
+    /// Entries of each list mode, by mask
+    pub lists: BTreeMap<char, BTreeMap<String, ListEntry>>,
+    /// Other modes that are set, with their parameter if they have one
+    pub flags: BTreeMap<char, Option<String>>,
+}
+
+impl ChannelModes {
+    /// Flags and their parameters, as in `+lnt 10`.
+    pub fn flag_string(&self) -> String {
+        if self.flags.is_empty() {
+            return String::new();
+        }
+        let mut s: String = "+".to_owned();
+        s.extend(self.flags.keys());
+        for param in self.flags.values().filter_map(|p| p.as_ref()) {
+            s.push(' ');
+            s.push_str(param);
+        }
+        s
+    }
+}
+
+fn time_of(e: &Event) -> Option<i64> {
+    match e.time {
+        Time::Timestamp(t) => Some(t),
+        _ => None,
+    }
+}
+
+/// The modes of every channel, as far as the log tells. Prefix modes are
+/// forgotten when their holder leaves, and when the logger disconnects.
+pub struct Modes {
+    spec: ModeSpec,
+    casemap: CaseMapping,
+    /// By channel in canonical case
+    channels: HashMap<String, ChannelModes>,
+}
+
+impl Modes {
+    pub fn new(spec: ModeSpec, casemap: CaseMapping) -> Modes {
+        Modes {
+            spec: spec,
+            casemap: casemap,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Follow a single event. Returns the mode changes it made, if any.
+    pub fn apply(&mut self, ctx: &Context, e: &Event) -> Vec<ModeChange> {
+        let cm = self.casemap;
+        match e.ty {
+            Type::Mode { ref nick, ref mode, ref masks } => {
+                let channel = channel_of(ctx, e);
+                // user modes
+                if channel.is_empty() {
+                    return Vec::new();
+                }
+                let changes = parse_modes(&self.spec, mode, masks);
+                let spec = &self.spec;
+                let channel = self.channels.entry(channel).or_insert_with(Default::default);
+                for change in &changes {
+                    let param = change.param.as_ref();
+                    match (change.mode, param) {
+                        (m, Some(p)) if spec.is_prefix(m) => {
+                            let holders = channel.prefixes.entry(m).or_insert_with(BTreeMap::new);
+                            let p = strip_nick(p);
+                            if change.add {
+                                holders.insert(cm.to_lower(p), p.to_owned());
+                            } else {
+                                holders.remove(&cm.to_lower(p));
+                            }
+                        }
+                        (m, Some(p)) if spec.is_list(m) => {
+                            let list = channel.lists.entry(m).or_insert_with(BTreeMap::new);
+                            if change.add {
+                                list.insert(p.clone(),
+                                            ListEntry {
+                                                mask: p.clone(),
+                                                set_by: nick.as_ref().map(|n| n.to_string()),
+                                                time: time_of(e),
+                                            });
+                            } else {
+                                list.remove(p);
+                            }
+                        }
+                        (m, _) => {
+                            if change.add {
+                                channel.flags.insert(m, param.cloned());
+                            } else {
+                                channel.flags.remove(&m);
+                            }
+                        }
+                    }
+                }
+                changes
+            }
+            Type::Part { ref nick, .. } |
+            Type::Kick { kicked_nick: ref nick, .. } => {
+                let key = cm.to_lower(strip_nick(nick));
+                if let Some(channel) = self.channels.get_mut(&channel_of(ctx, e)) {
+                    for holders in channel.prefixes.values_mut() {
+                        holders.remove(&key);
+                    }
+                }
+                Vec::new()
+            }
+            Type::Quit { ref nick, .. } => {
+                let key = cm.to_lower(strip_nick(nick));
+                for channel in self.channels.values_mut() {
+                    for holders in channel.prefixes.values_mut() {
+                        holders.remove(&key);
+                    }
+                }
+                Vec::new()
+            }
+            Type::Nick { ref old_nick, ref new_nick } => {
+                let old = cm.to_lower(strip_nick(old_nick));
+                let new_nick = strip_nick(new_nick);
+                for channel in self.channels.values_mut() {
+                    for holders in channel.prefixes.values_mut() {
+                        if holders.remove(&old).is_some() {
+                            holders.insert(cm.to_lower(new_nick), new_nick.to_owned());
+                        }
+                    }
+                }
+                Vec::new()
+            }
+            Type::Disconnect => {
+                for channel in self.channels.values_mut() {
+                    channel.prefixes.clear();
+                }
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn spec(&self) -> &ModeSpec {
+        &self.spec
+    }
+
+    /// Channels any modes are known of, in canonical case and sorted.
+    pub fn channels(&self) -> Vec<&str> {
+        let mut channels: Vec<&str> = self.channels.keys().map(|c| c.as_str()).collect();
+        channels.sort();
+        channels
+    }
+
+    pub fn channel(&self, channel: &str) -> Option<&ChannelModes> {
+        self.channels.get(&self.casemap.to_lower(channel))
+    }
+}
+
+/// The modes of every channel at `time`, after every event up to and including it.
+/// The input may consist of several logs, so it is read to the end. Events without a full date
+/// are followed, unless they come after a later one.
+pub fn modes_at(ctx: &Context,
+                input: &mut BufRead,
+                decoder: &mut Decode,
+                spec: ModeSpec,
+                time: i64)
+                -> ilc_base::Result<Modes> {
+    let mut modes = Modes::new(spec, ctx.casemapping);
+    let mut past = false;
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        if let Some(t) = time_of(&e) {
+            past = t > time;
+        }
+        if !past {
+            modes.apply(ctx, &e);
+        }
+    }
+    Ok(modes)
+}
+
+/// An entry of a list mode, e.g. a ban, from being set until being removed.
+pub struct ListRecord {
+    /// In canonical case
+    pub channel: String,
+    pub mode: char,
+    pub mask: String,
+    pub set_by: Option<String>,
+    pub set: Option<i64>,
+    /// `None` if the entry was never seen being removed
+    pub removed_by: Option<String>,
+    pub removed: Option<i64>,
+}
+
+/// Every ban, exception and other list entry set or removed, in the order they were set.
+/// Entries that were only seen being removed come first.
+pub fn list_history(ctx: &Context,
+                    input: &mut BufRead,
+                    decoder: &mut Decode,
+                    spec: ModeSpec)
+                    -> ilc_base::Result<Vec<ListRecord>> {
+    let mut modes = Modes::new(spec, ctx.casemapping);
+    let mut history = Vec::new();
+    // channel, mode and mask of entries in effect, to their index in `history`
+    let mut active: HashMap<(String, char, String), usize> = HashMap::new();
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        let by = match e.ty {
+            Type::Mode { ref nick, .. } => nick.as_ref().map(|n| n.to_string()),
+            _ => None,
+        };
+        for change in modes.apply(ctx, &e) {
+            let mask = match change.param {
+                Some(ref p) if modes.spec().is_list(change.mode) => p.clone(),
+                _ => continue,
+            };
+            let key = (channel_of(ctx, &e), change.mode, mask);
+            if change.add {
+                if active.contains_key(&key) {
+                    continue;
+                }
+                active.insert(key.clone(), history.len());
+                history.push(ListRecord {
+                    channel: key.0,
+                    mode: key.1,
+                    mask: key.2,
+                    set_by: by.clone(),
+                    set: time_of(&e),
+                    removed_by: None,
+                    removed: None,
+                });
+            } else {
+                let i = match active.remove(&key) {
+                    Some(i) => i,
+                    None => {
+                        history.push(ListRecord {
+                            channel: key.0,
+                            mode: key.1,
+                            mask: key.2,
+                            set_by: None,
+                            set: None,
+                            removed_by: None,
+                            removed: None,
+                        });
+                        history.len() - 1
+                    }
+                };
+                history[i].removed_by = by.clone();
+                history[i].removed = time_of(&e);
+            }
+        }
+    }
+    history.sort_by(|a, b| a.set.cmp(&b.set));
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModeChange, ModeSpec, parse_modes};
+
+    fn change(add: bool, mode: char, param: Option<&str>) -> ModeChange {
+        ModeChange {
+            add: add,
+            mode: mode,
+            param: param.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn spec() {
+        let spec = ModeSpec::parse("b,k,l,imnt", "(ov)@+").unwrap();
+        assert_eq!(spec.lists, "b");
+        assert_eq!(spec.when_set, "l");
+        assert_eq!(spec.prefixes, vec![('o', '@'), ('v', '+')]);
+        assert!(ModeSpec::parse("b,k,l", "(ov)@+").is_none());
+        assert!(ModeSpec::parse("b,k,l,imnt", "ov@+").is_none());
+        assert!(ModeSpec::parse("b,k,l,imnt", "(ov)@").is_none());
+    }
+
+    #[test]
+    fn params() {
+        let spec = ModeSpec::default();
+        assert_eq!(parse_modes(&spec, "+ovb-t", "bob bob *!*@bad"),
+                   vec![change(true, 'o', Some("bob")),
+                        change(true, 'v', Some("bob")),
+                        change(true, 'b', Some("*!*@bad")),
+                        change(false, 't', None)]);
+        // the limit only takes a parameter when set, the key both ways
+        assert_eq!(parse_modes(&spec, "+lk-lk", "10 secret secret"),
+                   vec![change(true, 'l', Some("10")),
+                        change(true, 'k', Some("secret")),
+                        change(false, 'l', None),
+                        change(false, 'k', Some("secret"))]);
+    }
+
+    #[test]
+    fn missing_params() {
+        let spec = ModeSpec::default();
+        assert_eq!(parse_modes(&spec, "+bn", ""), vec![change(true, 'n', None)]);
+        assert_eq!(parse_modes(&spec, "+Xo", "alice"),
+                   vec![change(true, 'X', None), change(true, 'o', Some("alice"))]);
+    }
+}
@@ -0,0 +1,102 @@
+//! Working on several log files at once
+//!
+//! Every file is decoded in a worker thread of its own, with its own copy of
+//! the `Context`. Results come back in the order of the files, so they can be
+//! combined as if the files had been read one after the other.
+use ilc_base::{Context, Decode};
+
+use std::cmp;
+use std::io::BufRead;
+use std::panic;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+/// An input that can be handed to another thread.
+pub type Input = Box<BufRead + Send>;
+
+/// Apply `f` to every item in up to `threads` worker threads, and return the results in the
+/// order of `items`. A panic in a worker is passed on once all of them are done.
+pub fn map<I, T, F>(items: Vec<I>, threads: usize, f: F) -> Vec<T>
+    where I: Send + 'static,
+          T: Send + 'static,
+          F: Fn(I) -> T + Send + Sync + 'static
+{
+    let len = items.len();
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+    let workers: Vec<_> = (0..cmp::max(1, cmp::min(threads, len)))
+                              .map(|_| {
+                                  let (queue, f, tx) = (queue.clone(), f.clone(), tx.clone());
+                                  thread::spawn(move || {
+                                      loop {
+                                          let next = queue.lock().unwrap().next();
+                                          match next {
+                                              Some((i, item)) => {
+                                                  let _ = tx.send((i, f(item)));
+                                              }
+                                              None => break,
+                                          }
+                                      }
+                                  })
+                              })
+                              .collect();
+    drop(tx);
+
+    let mut results: Vec<Option<T>> = (0..len).map(|_| None).collect();
+    for (i, t) in rx {
+        results[i] = Some(t);
+    }
+    for worker in workers {
+        if let Err(e) = worker.join() {
+            panic::resume_unwind(e);
+        }
+    }
+    results.into_iter().map(|t| t.expect("worker finished without a result")).collect()
+}
+
+/// Decode every input with `decoder` and apply `f`, in up to `threads` worker threads.
+/// Results are in the order of `inputs`, and what was lost in the workers is recorded in `ctx`.
+pub fn each<T, F>(ctx: &Context,
+                  inputs: Vec<Input>,
+                  decoder: Box<Decode + Send + Sync>,
+                  threads: usize,
+                  f: F)
+                  -> Vec<T>
+    where T: Send + 'static,
+          F: Fn(&Context, &mut BufRead, &Decode) -> T + Send + Sync + 'static
+{
+    let inputs = inputs.into_iter().map(|input| (input, ())).collect();
+    each_with(ctx,
+              inputs,
+              decoder,
+              threads,
+              move |ctx, input, (), decoder| f(ctx, input, decoder))
+}
+
+/// Like `each`, but every input comes with something of its own for `f`.
+pub fn each_with<D, T, F>(ctx: &Context,
+                          inputs: Vec<(Input, D)>,
+                          decoder: Box<Decode + Send + Sync>,
+                          threads: usize,
+                          f: F)
+                          -> Vec<T>
+    where D: Send + 'static,
+          T: Send + 'static,
+          F: Fn(&Context, &mut BufRead, D, &Decode) -> T + Send + Sync + 'static
+{
+    let decoder = Arc::new(decoder);
+    let jobs = inputs.into_iter()
+                     .map(|(input, data)| (ctx.fork(), input, data, decoder.clone()))
+                     .collect();
+    let results = map(jobs, threads, move |(ctx, mut input, data, decoder)| {
+        let t = f(&ctx, &mut *input, data, &**decoder);
+        (ctx, t)
+    });
+    results.into_iter()
+           .map(|(forked, t)| {
+               ctx.losses.absorb(forked.losses);
+               t
+           })
+           .collect()
+}
@@ -0,0 +1,45 @@
+//! Last activity of a nick
+use ilc_base::{self, Context, Decode, Event};
+use identity::Identities;
+
+use std::collections::HashMap;
+use std::borrow::Cow;
+use std::io::BufRead;
+
+/// Find the last event a nick was the actor of.
+/// With `identities`, events of any nick of the same person count.
+pub fn seen(ctx: &Context,
+            input: &mut BufRead,
+            decoder: &mut Decode,
+            nick: &str,
+            mut identities: Option<&mut Identities>)
+            -> ilc_base::Result<Option<Event<'static>>> {
+    // The person behind a nick may only become known after their last event,
+    // so keep the latest event of every nick, in order of appearance.
+    let mut last: HashMap<String, (usize, Event<'static>)> = HashMap::new();
+    for (i, e) in decoder.decode(&ctx, input).enumerate() {
+        let e = try!(e);
+        if let Some(ref mut ids) = identities {
+            ids.observe(&e);
+        }
+        if let Some(actor) = e.ty.actor() {
+            let owned = Event {
+                ty: e.ty.map_nicks(str::to_owned),
+                time: e.time.clone(),
+                channel: e.channel.as_ref().map(|c| Cow::Owned(c.to_string())),
+            };
+            last.insert(ctx.casemapping.to_lower(actor), (i, owned));
+        }
+    }
+
+    let found = last.into_iter()
+                    .filter(|&(ref actor, _)| {
+                        match identities {
+                            Some(ref ids) => ids.same(actor, nick),
+                            None => ctx.casemapping.eq(actor, nick),
+                        }
+                    })
+                    .map(|(_, e)| e)
+                    .max_by_key(|&(i, _)| i);
+    Ok(found.map(|(_, e)| e))
+}
@@ -0,0 +1,244 @@
+//! How long people stay
+//!
+//! A session starts when a nick joins a channel, and ends when it parts, quits
+//! or is kicked. Nick changes don't end sessions. When the logger itself
+//! disconnects, nobody knows who left during the gap, so every open session is
+//! cut short there, and counted as interrupted. Only events with a full date
+//! are used.
+use ilc_base::{self, Context, Decode, Event, Time};
+use ilc_base::event::Type;
+use identity::{Identities, Mergeable};
+use members::channel_of;
+use stats::{Day, strip_nick};
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use chrono::Timelike;
+use chrono::offset::TimeZone;
+
+use serde::ser::{MapVisitor, Serialize, Serializer};
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// In canonical case, or empty if it's unknown
+    pub channel: String,
+    pub start: i64,
+    pub end: i64,
+    /// Whether the end is known, rather than the logger disconnecting or the log ending
+    pub complete: bool,
+}
+
+impl Session {
+    /// In seconds.
+    pub fn len(&self) -> i64 {
+        self.end - self.start
+    }
+}
+
+pub struct Presence {
+    /// The spelling the nick joined with first
+    pub nick: String,
+    pub sessions: Vec<Session>,
+    /// Minutes present per hour of the day, in `timezone_out`
+    pub hours: Day,
+}
+
+impl Presence {
+    fn new(nick: &str) -> Presence {
+        Presence {
+            nick: nick.to_owned(),
+            sessions: Vec::new(),
+            hours: [0; 24],
+        }
+    }
+
+    /// Seconds spent in all channels together, at least.
+    pub fn total(&self) -> i64 {
+        self.sessions.iter().map(Session::len).fold(0, |a, b| a + b)
+    }
+
+    /// Sessions whose end isn't known.
+    pub fn interrupted(&self) -> usize {
+        self.sessions.iter().filter(|s| !s.complete).count()
+    }
+
+    /// The median length of complete sessions, in seconds.
+    pub fn median(&self) -> Option<i64> {
+        let mut lens: Vec<i64> = self.sessions
+                                     .iter()
+                                     .filter(|s| s.complete)
+                                     .map(Session::len)
+                                     .collect();
+        if lens.is_empty() {
+            return None;
+        }
+        lens.sort();
+        let mid = lens.len() / 2;
+        Some(if lens.len() % 2 == 0 { (lens[mid - 1] + lens[mid]) / 2 } else { lens[mid] })
+    }
+
+    /// The `n` hours of the day the nick is around the most, in order.
+    pub fn typical_hours(&self, n: usize) -> Vec<usize> {
+        let mut hours: Vec<usize> = (0..24).filter(|&h| self.hours[h] > 0).collect();
+        hours.sort_by(|&a, &b| (self.hours[b], a).cmp(&(self.hours[a], b)));
+        hours.truncate(n);
+        hours.sort();
+        hours
+    }
+
+    fn add(&mut self, ctx: &Context, session: Session) {
+        let mut t = session.start;
+        while t < session.end {
+            let time = ctx.timezone_out.timestamp(t, 0);
+            let next = t - (time.minute() * 60 + time.second()) as i64 + 3600;
+            let until = cmp::min(next, session.end);
+            self.hours[time.hour() as usize] += ((until - t) / 60) as u32;
+            t = until;
+        }
+        self.sessions.push(session);
+    }
+}
+
+impl Mergeable for Presence {
+    fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    fn empty(person: &str) -> Presence {
+        Presence::new(person)
+    }
+
+    fn merge(&mut self, other: Presence) {
+        self.sessions.extend(other.sessions);
+        self.sessions.sort_by(|a, b| a.start.cmp(&b.start));
+        for (a, b) in self.hours.iter_mut().zip(other.hours.iter()) {
+            *a += *b;
+        }
+    }
+}
+
+impl Serialize for Presence {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a Presence);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("nick", &self.0.nick));
+                try!(s.serialize_struct_elt("sessions", self.0.sessions.len()));
+                try!(s.serialize_struct_elt("interrupted", self.0.interrupted()));
+                try!(s.serialize_struct_elt("total", self.0.total()));
+                try!(s.serialize_struct_elt("median", self.0.median()));
+                try!(s.serialize_struct_elt("hours", &self.0.hours));
+                try!(s.serialize_struct_elt("typical_hours", self.0.typical_hours(3)));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(7)
+            }
+        }
+
+        s.serialize_struct("Presence", Visitor(self))
+    }
+}
+
+fn close(ctx: &Context,
+         nicks: &mut HashMap<String, Presence>,
+         channel: String,
+         (nick, start): (String, i64),
+         end: i64,
+         complete: bool) {
+    let session = Session {
+        channel: channel,
+        start: start,
+        end: end,
+        complete: complete,
+    };
+    nicks.entry(ctx.casemapping.to_lower(&nick))
+         .or_insert_with(|| Presence::new(&nick))
+         .add(ctx, session);
+}
+
+/// Reconstruct the sessions of every nick that joined a channel, keyed by the nick in canonical
+/// case. With `identities`, keyed by person instead.
+pub fn sessions(ctx: &Context,
+                input: &mut BufRead,
+                decoder: &mut Decode,
+                mut identities: Option<&mut Identities>)
+                -> ilc_base::Result<HashMap<String, Presence>> {
+    let cm = ctx.casemapping;
+    let mut nicks: HashMap<String, Presence> = HashMap::new();
+    // by channel and the nick currently used, to the nick joined with and the start
+    let mut open: HashMap<(String, String), (String, i64)> = HashMap::new();
+    let mut last = None;
+
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        if let Some(ref mut ids) = identities {
+            ids.observe(&e);
+        }
+        let time = match e.time {
+            Time::Timestamp(t) => t,
+            _ => continue,
+        };
+        last = Some(time);
+        let channel = channel_of(ctx, &e);
+        match e {
+            Event { ty: Type::Join { ref nick, .. }, .. } => {
+                let nick = strip_nick(nick);
+                // joining while present means the leave wasn't logged, keep the earlier start
+                open.entry((channel, cm.to_lower(nick)))
+                    .or_insert_with(|| (nick.to_owned(), time));
+            }
+            Event { ty: Type::Part { ref nick, .. }, .. } |
+            Event { ty: Type::Kick { kicked_nick: ref nick, .. }, .. } => {
+                let key = (channel, cm.to_lower(strip_nick(nick)));
+                if let Some(s) = open.remove(&key) {
+                    close(ctx, &mut nicks, key.0, s, time, true);
+                }
+            }
+            Event { ty: Type::Quit { ref nick, .. }, .. } => {
+                let nick = cm.to_lower(strip_nick(nick));
+                let keys: Vec<_> = open.keys().filter(|k| k.1 == nick).cloned().collect();
+                for key in keys {
+                    let s = open.remove(&key).unwrap();
+                    close(ctx, &mut nicks, key.0, s, time, true);
+                }
+            }
+            Event { ty: Type::Nick { ref old_nick, ref new_nick }, .. } => {
+                let (old, new) = (cm.to_lower(strip_nick(old_nick)),
+                                  cm.to_lower(strip_nick(new_nick)));
+                let keys: Vec<_> = open.keys().filter(|k| k.1 == old).cloned().collect();
+                for key in keys {
+                    let s = open.remove(&key).unwrap();
+                    // the new nick can't be present as well, its leave wasn't logged
+                    if let Some(stale) = open.insert((key.0.clone(), new.clone()), s) {
+                        close(ctx, &mut nicks, key.0, stale, time, false);
+                    }
+                }
+            }
+            Event { ty: Type::Disconnect, .. } => {
+                for (key, s) in open.drain() {
+                    close(ctx, &mut nicks, key.0, s, time, false);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(end) = last {
+        for (key, s) in open.drain() {
+            close(ctx, &mut nicks, key.0, s, end, false);
+        }
+    }
+
+    if let Some(ids) = identities {
+        nicks = ids.merge(nicks);
+    }
+    Ok(nicks)
+}
@@ -0,0 +1,535 @@
+//! Statistics that can be added to later
+//!
+//! Counting years of logs takes a while. A `State` keeps everything needed to
+//! produce statistics in a compact binary file, so that only new logs have to
+//! be read, with the same results as reading everything again.
+use ilc_base::{self, CaseMapping, Context, Decode, Error};
+use identity::Identities;
+use stats::{Bucket, Day, NickSeries, NickStat, Point, Series, Stats, TopicStat, UrlStat, Week};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::io::{BufRead, Read, Write};
+
+use chrono::{Datelike, NaiveDate};
+
+/// Starts every state file, the digit is the version of the format.
+const MAGIC: &'static [u8] = b"ilcstat3";
+
+/// A binary encoding. Numbers are little-endian, collections and strings are prefixed with
+/// their length.
+pub trait Persist: Sized {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()>;
+    fn load(input: &mut Read) -> ilc_base::Result<Self>;
+}
+
+fn corrupt<T>(what: &str) -> ilc_base::Result<T> {
+    Err(Error::Parse(format!("corrupt state file: invalid {}", what)))
+}
+
+fn write_le(out: &mut Write, n: u64, bytes: usize) -> ilc_base::Result<()> {
+    let mut buf = [0u8; 8];
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = (n >> (i * 8)) as u8;
+    }
+    try!(out.write_all(&buf[..bytes]));
+    Ok(())
+}
+
+fn read_le(input: &mut Read, bytes: usize) -> ilc_base::Result<u64> {
+    let mut buf = [0u8; 8];
+    try!(input.read_exact(&mut buf[..bytes]));
+    Ok(buf[..bytes].iter().rev().fold(0, |n, &b| n << 8 | b as u64))
+}
+
+impl Persist for u8 {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        write_le(out, *self as u64, 1)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<u8> {
+        Ok(try!(read_le(input, 1)) as u8)
+    }
+}
+
+impl Persist for u32 {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        write_le(out, *self as u64, 4)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<u32> {
+        Ok(try!(read_le(input, 4)) as u32)
+    }
+}
+
+impl Persist for u64 {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        write_le(out, *self, 8)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<u64> {
+        read_le(input, 8)
+    }
+}
+
+impl Persist for i64 {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        write_le(out, *self as u64, 8)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<i64> {
+        Ok(try!(read_le(input, 8)) as i64)
+    }
+}
+
+fn save_len(out: &mut Write, len: usize) -> ilc_base::Result<()> {
+    (len as u64).save(out)
+}
+
+fn load_len(input: &mut Read) -> ilc_base::Result<usize> {
+    Ok(try!(u64::load(input)) as usize)
+}
+
+impl Persist for String {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(save_len(out, self.len()));
+        try!(out.write_all(self.as_bytes()));
+        Ok(())
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<String> {
+        let len = try!(load_len(input));
+        let mut buf = Vec::new();
+        // don't trust the length enough to allocate it up front
+        try!(Read::take(&mut *input, len as u64).read_to_end(&mut buf));
+        if buf.len() != len {
+            return corrupt("string length");
+        }
+        String::from_utf8(buf).or_else(|_| corrupt("UTF-8"))
+    }
+}
+
+impl<T: Persist> Persist for Option<T> {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        match *self {
+            Some(ref t) => {
+                try!(1u8.save(out));
+                t.save(out)
+            }
+            None => 0u8.save(out),
+        }
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Option<T>> {
+        match try!(u8::load(input)) {
+            0 => Ok(None),
+            1 => Ok(Some(try!(T::load(input)))),
+            _ => corrupt("option"),
+        }
+    }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(save_len(out, self.len()));
+        for t in self {
+            try!(t.save(out));
+        }
+        Ok(())
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Vec<T>> {
+        let len = try!(load_len(input));
+        let mut v = Vec::new();
+        for _ in 0..len {
+            v.push(try!(T::load(input)));
+        }
+        Ok(v)
+    }
+}
+
+impl<T: Persist + Eq + Hash> Persist for HashSet<T> {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(save_len(out, self.len()));
+        for t in self {
+            try!(t.save(out));
+        }
+        Ok(())
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<HashSet<T>> {
+        let len = try!(load_len(input));
+        let mut set = HashSet::new();
+        for _ in 0..len {
+            set.insert(try!(T::load(input)));
+        }
+        Ok(set)
+    }
+}
+
+impl<A: Persist, B: Persist> Persist for (A, B) {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(self.0.save(out));
+        self.1.save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<(A, B)> {
+        Ok((try!(Persist::load(input)), try!(Persist::load(input))))
+    }
+}
+
+impl<K: Persist + Eq + Hash, V: Persist> Persist for HashMap<K, V> {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(save_len(out, self.len()));
+        for (k, v) in self {
+            try!(k.save(out));
+            try!(v.save(out));
+        }
+        Ok(())
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<HashMap<K, V>> {
+        let len = try!(load_len(input));
+        let mut map = HashMap::new();
+        for _ in 0..len {
+            let k = try!(K::load(input));
+            map.insert(k, try!(V::load(input)));
+        }
+        Ok(map)
+    }
+}
+
+impl<K: Persist + Ord, V: Persist> Persist for BTreeMap<K, V> {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(save_len(out, self.len()));
+        for (k, v) in self {
+            try!(k.save(out));
+            try!(v.save(out));
+        }
+        Ok(())
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<BTreeMap<K, V>> {
+        let len = try!(load_len(input));
+        let mut map = BTreeMap::new();
+        for _ in 0..len {
+            let k = try!(K::load(input));
+            map.insert(k, try!(V::load(input)));
+        }
+        Ok(map)
+    }
+}
+
+impl Persist for Day {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        for n in self {
+            try!(n.save(out));
+        }
+        Ok(())
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Day> {
+        let mut day = [0; 24];
+        for n in &mut day {
+            *n = try!(u32::load(input));
+        }
+        Ok(day)
+    }
+}
+
+impl Persist for Week {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        for day in self {
+            try!(day.save(out));
+        }
+        Ok(())
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Week> {
+        let mut week = [[0; 24]; 7];
+        for day in &mut week {
+            *day = try!(Day::load(input));
+        }
+        Ok(week)
+    }
+}
+
+impl Persist for NaiveDate {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        (self.num_days_from_ce() as i64).save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<NaiveDate> {
+        match NaiveDate::from_num_days_from_ce_opt(try!(i64::load(input)) as i32) {
+            Some(date) => Ok(date),
+            None => corrupt("date"),
+        }
+    }
+}
+
+impl Persist for CaseMapping {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        let tag: u8 = match *self {
+            CaseMapping::Ascii => 0,
+            CaseMapping::Rfc1459 => 1,
+            CaseMapping::StrictRfc1459 => 2,
+        };
+        tag.save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<CaseMapping> {
+        match try!(u8::load(input)) {
+            0 => Ok(CaseMapping::Ascii),
+            1 => Ok(CaseMapping::Rfc1459),
+            2 => Ok(CaseMapping::StrictRfc1459),
+            _ => corrupt("case mapping"),
+        }
+    }
+}
+
+impl Persist for Bucket {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        let tag: u8 = match *self {
+            Bucket::Day => 0,
+            Bucket::Week => 1,
+            Bucket::Month => 2,
+        };
+        tag.save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Bucket> {
+        match try!(u8::load(input)) {
+            0 => Ok(Bucket::Day),
+            1 => Ok(Bucket::Week),
+            2 => Ok(Bucket::Month),
+            _ => corrupt("bucket"),
+        }
+    }
+}
+
+/// Implement `Persist` for a struct with public fields, by saving them in the given order.
+macro_rules! persist_struct {
+    ($name:ident { $($field:ident),* }) => {
+        impl Persist for $name {
+            fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+                $(try!(self.$field.save(out));)*
+                Ok(())
+            }
+
+            fn load(input: &mut Read) -> ilc_base::Result<$name> {
+                Ok($name {
+                    $($field: try!(Persist::load(input)),)*
+                })
+            }
+        }
+    }
+}
+
+persist_struct!(NickStat {
+    nick,
+    lines,
+    alpha_lines,
+    words,
+    chars,
+    hours,
+    quote,
+    smileys,
+    kicks_given,
+    kicks_received,
+    topics,
+    modes,
+    events,
+    quit_reasons
+});
+persist_struct!(UrlStat { count, last_by });
+persist_struct!(TopicStat { nick, topic, time });
+persist_struct!(Stats { freqs, week, words, urls, smileys, topics, totals, channel_totals });
+persist_struct!(Point { lines, words, speakers });
+persist_struct!(NickSeries { nick, points });
+persist_struct!(Series { bucket, channels, nicks });
+
+pub struct State {
+    pub stats: Stats,
+    /// By day, so it can be put into buckets of any size later
+    pub daily: Series,
+    /// Everything learned from the logs about who is who, without any alias file
+    pub identities: Identities,
+}
+
+impl State {
+    pub fn new(casemap: CaseMapping) -> State {
+        State {
+            stats: Stats::new(),
+            daily: Series::new(Bucket::Day),
+            identities: Identities::new(casemap),
+        }
+    }
+
+    /// Read a state file, as written by `save`.
+    pub fn load(input: &mut Read) -> ilc_base::Result<State> {
+        let mut magic = [0u8; 8];
+        if input.read_exact(&mut magic).is_err() || &magic[..] != MAGIC {
+            return Err(Error::Parse("not an ilc state file, or an incompatible version"
+                                        .to_owned()));
+        }
+        Ok(State {
+            stats: try!(Stats::load(input)),
+            daily: try!(Series::load(input)),
+            identities: try!(Identities::load(input)),
+        })
+    }
+
+    pub fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(out.write_all(MAGIC));
+        try!(self.stats.save(out));
+        try!(self.daily.save(out));
+        try!(self.identities.save(out));
+        Ok(())
+    }
+
+    /// Add the events of `input`, which has to follow everything added before, as if they had
+    /// been read together. Nothing is changed if `input` can't be decoded.
+    pub fn update(&mut self,
+                  ctx: &Context,
+                  input: &mut BufRead,
+                  decoder: &mut Decode)
+                  -> ilc_base::Result<()> {
+        if ctx.casemapping != self.identities.casemapping() {
+            return Err(Error::Parse(format!("the state was counted with the case mapping {:?}",
+                                            self.identities.casemapping())));
+        }
+        let mut stats = Stats::new();
+        let mut daily = Series::new(Bucket::Day);
+        let mut identities = self.identities.clone();
+        for e in decoder.decode(&ctx, input) {
+            let e = try!(e);
+            identities.observe(&e);
+            stats.add(ctx, &e);
+            daily.add(ctx, &e);
+        }
+        self.stats.combine(stats);
+        self.daily.combine(daily);
+        self.identities = identities;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use ilc_base::{CaseMapping, Context, Event};
+    use ilc_base::event::Type;
+
+    use fixtures::{Events, event, msg};
+    use super::State;
+
+    fn log() -> Vec<Event<'static>> {
+        vec![msg("Foo", "hello there :)", 1456362905),
+             msg("Bar", "see http://example.com/", 1456362910),
+             event(Type::Nick {
+                       old_nick: Cow::Borrowed("Foo"),
+                       new_nick: Cow::Borrowed("Foo_"),
+                   },
+                   1456362920),
+             event(Type::TopicChange {
+                       nick: Some(Cow::Borrowed("Foo_")),
+                       new_topic: Cow::Borrowed("Hi"),
+                   },
+                   1456449320),
+             msg("Foo_", "hello again", 1456449330)]
+    }
+
+    fn update(state: &mut State, events: Vec<Event<'static>>) {
+        let ctx = Context::default();
+        let mut input: &[u8] = &[];
+        state.update(&ctx, &mut input, &mut Events(events)).unwrap();
+    }
+
+    fn roundtrip(state: &State) -> State {
+        let mut out = Vec::new();
+        state.save(&mut out).unwrap();
+        State::load(&mut &out[..]).unwrap()
+    }
+
+    /// Everything a state is made of, in an order that doesn't depend on hashing.
+    fn summary(state: &State) -> Vec<String> {
+        let mut summary = Vec::new();
+        let s = &state.stats;
+        for (key, n) in &s.freqs {
+            summary.push(format!("nick {} {} {} {} {} {:?} {:?} {:?} {}",
+                                 key,
+                                 n.nick,
+                                 n.lines,
+                                 n.words,
+                                 n.chars,
+                                 n.hours,
+                                 n.quote,
+                                 n.events,
+                                 n.topics));
+        }
+        summary.push(format!("{:?} {:?} {:?}", s.week, s.totals, s.channel_totals));
+        for (word, n) in &s.words {
+            summary.push(format!("word {} {}", word, n));
+        }
+        for (url, u) in &s.urls {
+            summary.push(format!("url {} {} {}", url, u.count, u.last_by));
+        }
+        for (smiley, n) in &s.smileys {
+            summary.push(format!("smiley {} {}", smiley, n));
+        }
+        for t in &s.topics {
+            summary.push(format!("topic {:?} {} {:?}", t.nick, t.topic, t.time));
+        }
+        for (channel, points) in &state.daily.channels {
+            for (date, p) in points {
+                let mut speakers: Vec<_> = p.speakers.iter().collect();
+                speakers.sort();
+                summary.push(format!("channel {} {} {} {} {:?}",
+                                     channel,
+                                     date,
+                                     p.lines,
+                                     p.words,
+                                     speakers));
+            }
+        }
+        for (key, n) in &state.daily.nicks {
+            for (date, p) in &n.points {
+                summary.push(format!("series {} {} {} {} {}", key, n.nick, date, p.lines, p.words));
+            }
+        }
+        summary.push(format!("{:?}", state.identities.groups()));
+        summary.sort();
+        summary
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut state = State::new(CaseMapping::Rfc1459);
+        update(&mut state, log());
+        assert_eq!(summary(&roundtrip(&state)), summary(&state));
+    }
+
+    #[test]
+    fn load_and_continue() {
+        let mut whole = State::new(CaseMapping::Rfc1459);
+        update(&mut whole, log());
+
+        let mut log = log();
+        let rest = log.split_off(3);
+        let mut first = State::new(CaseMapping::Rfc1459);
+        update(&mut first, log);
+        let mut continued = roundtrip(&first);
+        update(&mut continued, rest);
+        assert_eq!(summary(&continued), summary(&whole));
+    }
+
+    #[test]
+    fn reject_other_files() {
+        assert!(State::load(&mut &b"ilctime1"[..]).is_err());
+        let mut out = Vec::new();
+        State::new(CaseMapping::Ascii).save(&mut out).unwrap();
+        out.truncate(out.len() - 1);
+        assert!(State::load(&mut &out[..]).is_err());
+    }
+}
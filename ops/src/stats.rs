@@ -1,21 +1,47 @@
 //! Per-nick word/line statistics
 use ilc_base::{self, Context, Decode, Event, Time};
 use ilc_base::event::Type;
+use identity::{Identities, Mergeable, Sightings};
+use parallel::{self, Input};
+use urls::find_urls;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::btree_map;
+use std::collections::hash_map::Entry;
 use std::io::BufRead;
+use std::mem;
+use std::str::FromStr;
 
-use chrono::{Datelike, NaiveDateTime, Timelike};
+use chrono::{Datelike, Duration, NaiveDate, Timelike};
+use chrono::offset::TimeZone;
 
 use serde::ser::{MapVisitor, Serialize, Serializer};
 
+use unicode_segmentation::{UnicodeSegmentation, UnicodeWords};
+
 pub type Day = [u32; 24];
 /// Weeks start on mondays.
 pub type Week = [Day; 7];
 
+/// Recognised smileys, compared against whole words.
+pub const SMILEYS: &'static [&'static str] = &[":)", ":-)", ":(", ":-(", ":D", ":-D", ";)", ";-)",
+                                               ":P", ":-P", ":p", ":-p", ":/", ":-/", ":o",
+                                               ":O", "xD", "XD", "<3", "^^", ":|", ":'("];
+
 pub struct Stats {
+    /// Keyed by the nick in canonical case
     pub freqs: HashMap<String, NickStat>,
     pub week: Week,
+    /// Occurrences of every word, in lowercase
+    pub words: HashMap<String, u32>,
+    pub urls: HashMap<String, UrlStat>,
+    pub smileys: HashMap<String, u32>,
+    /// Topic changes, oldest first
+    pub topics: Vec<TopicStat>,
+    /// Events of each type, as named by `Type::type_desc`
+    pub totals: BTreeMap<String, u32>,
+    /// `totals` of each channel, keyed by the channel in canonical case
+    pub channel_totals: BTreeMap<String, BTreeMap<String, u32>>,
 }
 
 impl Serialize for Stats {
@@ -29,11 +55,17 @@ impl Serialize for Stats {
             {
                 try!(s.serialize_struct_elt("freqs", &self.0.freqs));
                 try!(s.serialize_struct_elt("week", &self.0.week));
+                try!(s.serialize_struct_elt("words", &self.0.words));
+                try!(s.serialize_struct_elt("urls", &self.0.urls));
+                try!(s.serialize_struct_elt("smileys", &self.0.smileys));
+                try!(s.serialize_struct_elt("topics", &self.0.topics));
+                try!(s.serialize_struct_elt("totals", &self.0.totals));
+                try!(s.serialize_struct_elt("channel_totals", &self.0.channel_totals));
                 Ok(None)
             }
 
             fn len(&self) -> Option<usize> {
-                Some(1)
+                Some(8)
             }
         }
         s.serialize_struct("Stats", Visitor(self))
@@ -41,9 +73,87 @@ impl Serialize for Stats {
 }
 
 pub struct NickStat {
+    /// The spelling the nick was first seen with
+    pub nick: String,
     pub lines: u32,
     pub alpha_lines: u32,
     pub words: u32,
+    pub chars: u32,
+    /// Lines per hour of the day
+    pub hours: Day,
+    /// A line picked by its hash, which is as good as random but doesn't depend on the order
+    /// lines are counted in
+    pub quote: Option<String>,
+    pub smileys: u32,
+    pub kicks_given: u32,
+    pub kicks_received: u32,
+    pub topics: u32,
+    /// Single mode changes, e.g. `+oo` counts twice
+    pub modes: u32,
+    /// Events this nick was the actor of, by `Type::type_desc`
+    pub events: BTreeMap<String, u32>,
+    pub quit_reasons: BTreeMap<String, u32>,
+}
+
+impl NickStat {
+    pub fn new(nick: &str) -> NickStat {
+        NickStat {
+            nick: nick.to_owned(),
+            lines: 0,
+            alpha_lines: 0,
+            words: 0,
+            chars: 0,
+            hours: [0; 24],
+            quote: None,
+            smileys: 0,
+            kicks_given: 0,
+            kicks_received: 0,
+            topics: 0,
+            modes: 0,
+            events: BTreeMap::new(),
+            quit_reasons: BTreeMap::new(),
+        }
+    }
+
+    /// Add the numbers of `other` to these. The name of `self` is kept.
+    pub fn merge(&mut self, other: NickStat) {
+        self.lines += other.lines;
+        self.alpha_lines += other.alpha_lines;
+        self.words += other.words;
+        self.chars += other.chars;
+        for (a, b) in self.hours.iter_mut().zip(other.hours.iter()) {
+            *a += *b;
+        }
+        self.quote = match (self.quote.take(), other.quote) {
+            (Some(a), Some(b)) => Some(if quote_hash(&b) < quote_hash(&a) { b } else { a }),
+            (a, b) => a.or(b),
+        };
+        self.smileys += other.smileys;
+        self.kicks_given += other.kicks_given;
+        self.kicks_received += other.kicks_received;
+        self.topics += other.topics;
+        self.modes += other.modes;
+        for (ty, n) in other.events {
+            *self.events.entry(ty).or_insert(0) += n;
+        }
+        for (reason, n) in other.quit_reasons {
+            *self.quit_reasons.entry(reason).or_insert(0) += n;
+        }
+    }
+}
+
+impl Mergeable for NickStat {
+    fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    fn empty(person: &str) -> NickStat {
+        NickStat::new(person)
+    }
+
+    fn merge(&mut self, other: NickStat) {
+        NickStat::merge(self, other)
+    }
 }
 
 impl Serialize for NickStat {
@@ -55,14 +165,25 @@ impl Serialize for NickStat {
             fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
                 where S: Serializer
             {
+                try!(s.serialize_struct_elt("nick", &self.0.nick));
                 try!(s.serialize_struct_elt("lines", self.0.lines));
                 try!(s.serialize_struct_elt("alpha_lines", self.0.alpha_lines));
                 try!(s.serialize_struct_elt("words", self.0.words));
+                try!(s.serialize_struct_elt("chars", self.0.chars));
+                try!(s.serialize_struct_elt("hours", &self.0.hours));
+                try!(s.serialize_struct_elt("quote", &self.0.quote));
+                try!(s.serialize_struct_elt("smileys", self.0.smileys));
+                try!(s.serialize_struct_elt("kicks_given", self.0.kicks_given));
+                try!(s.serialize_struct_elt("kicks_received", self.0.kicks_received));
+                try!(s.serialize_struct_elt("topics", self.0.topics));
+                try!(s.serialize_struct_elt("modes", self.0.modes));
+                try!(s.serialize_struct_elt("events", &self.0.events));
+                try!(s.serialize_struct_elt("quit_reasons", &self.0.quit_reasons));
                 Ok(None)
             }
 
             fn len(&self) -> Option<usize> {
-                Some(3)
+                Some(14)
             }
         }
 
@@ -70,21 +191,84 @@ impl Serialize for NickStat {
     }
 }
 
-fn words_alpha(s: &str) -> (u32, bool) {
-    let mut alpha = false;
-    let mut words = 0;
-    for w in s.split_whitespace() {
-        if !w.is_empty() {
-            words += 1;
-            if w.chars().any(char::is_alphabetic) {
-                alpha = true
+pub struct UrlStat {
+    pub count: u32,
+    /// Who posted it most recently
+    pub last_by: String,
+}
+
+impl Serialize for UrlStat {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a UrlStat);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("count", self.0.count));
+                try!(s.serialize_struct_elt("last_by", &self.0.last_by));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(2)
             }
         }
+
+        s.serialize_struct("UrlStat", Visitor(self))
     }
-    (words, alpha)
 }
 
-fn strip_nick(s: &str) -> &str {
+pub struct TopicStat {
+    pub nick: Option<String>,
+    pub topic: String,
+    /// Only known for logs with full dates
+    pub time: Option<i64>,
+}
+
+impl Serialize for TopicStat {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a TopicStat);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("nick", &self.0.nick));
+                try!(s.serialize_struct_elt("topic", &self.0.topic));
+                try!(s.serialize_struct_elt("time", &self.0.time));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(3)
+            }
+        }
+
+        s.serialize_struct("TopicStat", Visitor(self))
+    }
+}
+
+/// FNV-1a, to pick quotes. Unlike a random pick, the line with the lowest hash is the same
+/// however a log is split up and counted.
+fn quote_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Words of `s`, split at Unicode word boundaries, without whitespace and punctuation, so
+/// e.g. `foo/bar` is two words and `don't` one. Every word count and ranking uses these.
+pub fn words(s: &str) -> UnicodeWords {
+    s.unicode_words()
+}
+
+/// Whether any whitespace-separated word of `s` contains letters, for lines worth quoting.
+fn words_alpha(s: &str) -> bool {
+    s.split_whitespace().any(|w| w.chars().any(char::is_alphabetic))
+}
+
+pub fn strip_nick(s: &str) -> &str {
     if s.is_empty() {
         return s;
     }
@@ -95,47 +279,720 @@ fn strip_nick(s: &str) -> &str {
     .trim_right_matches('_')
 }
 
-/// Return all active nicks, with lines, words and words per lines counted.
-pub fn stats(ctx: &Context, input: &mut BufRead, decoder: &mut Decode) -> ilc_base::Result<Stats> {
-    let mut freqs: HashMap<String, NickStat> = HashMap::new();
-    let mut week: Week = [[0; 24]; 7];
+fn nick_stat<'a>(freqs: &'a mut HashMap<String, NickStat>,
+                 ctx: &Context,
+                 nick: &str)
+                 -> &'a mut NickStat {
+    let nick = strip_nick(nick);
+    freqs.entry(ctx.casemapping.to_lower(nick)).or_insert_with(|| NickStat::new(nick))
+}
 
-    for e in decoder.decode(&ctx, input) {
-        let m = try!(e);
-        match m {
+fn count(map: &mut BTreeMap<String, u32>, key: &str) {
+    if let Some(n) = map.get_mut(key) {
+        *n += 1;
+        return;
+    }
+    map.insert(key.to_owned(), 1);
+}
+
+/// The hour of the day, in `timezone_out`.
+fn hour_of(ctx: &Context, time: &Time) -> Option<usize> {
+    match time {
+        &Time::Hms(h, _, _) => Some(h as usize % 24),
+        &Time::Timestamp(stamp) => Some(ctx.timezone_out.timestamp(stamp, 0).hour() as usize),
+        &Time::Unknown => None,
+    }
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            freqs: HashMap::new(),
+            week: [[0; 24]; 7],
+            words: HashMap::new(),
+            urls: HashMap::new(),
+            smileys: HashMap::new(),
+            topics: Vec::new(),
+            totals: BTreeMap::new(),
+            channel_totals: BTreeMap::new(),
+        }
+    }
+
+    /// Count a single event, in `timezone_out`.
+    pub fn add(&mut self, ctx: &Context, e: &Event) {
+        let ty = e.ty.type_desc();
+        count(&mut self.totals, ty);
+        let channel = e.channel
+                       .as_ref()
+                       .map(|c| ctx.casemapping.to_lower(c))
+                       .or(ctx.channel.as_ref().map(|c| ctx.casemapping.to_lower(c)))
+                       .unwrap_or_default();
+        count(self.channel_totals.entry(channel).or_insert_with(BTreeMap::new), ty);
+        if let Some(actor) = e.ty.actor() {
+            count(&mut nick_stat(&mut self.freqs, ctx, actor).events, ty);
+        }
+        match *e {
             Event { ty: Type::Msg { ref from, ref content, .. }, ref time, .. } => {
                 if let &Time::Timestamp(stamp) = time {
-                    let date = NaiveDateTime::from_timestamp(stamp, 0);
+                    let date = ctx.timezone_out.timestamp(stamp, 0);
                     let dow = date.weekday().num_days_from_monday() as usize;
                     let hour = date.hour() as usize;
-                    week[dow][hour] += 1;
+                    self.week[dow][hour] += 1;
                 }
 
-                let nick = strip_nick(from);
-                if freqs.contains_key(nick) {
-                    let p: &mut NickStat = freqs.get_mut(nick).unwrap();
-                    let (words, alpha) = words_alpha(content);
-                    p.lines += 1;
-                    if alpha {
-                        p.alpha_lines += 1
+                let p = nick_stat(&mut self.freqs, ctx, from);
+                p.lines += 1;
+                if words_alpha(content) {
+                    p.alpha_lines += 1;
+                    let better = p.quote
+                                  .as_ref()
+                                  .map_or(true, |q| quote_hash(content) < quote_hash(q));
+                    if better {
+                        p.quote = Some(content.to_string());
                     }
-                    p.words += words;
-                } else {
-                    let (words, alpha) = words_alpha(content);
-                    freqs.insert(nick.to_owned(),
-                                 NickStat {
-                                     lines: 1,
-                                     alpha_lines: if alpha { 1 } else { 0 },
-                                     words: words,
-                                 });
                 }
+                p.words += words(content).count() as u32;
+                p.chars += content.chars().count() as u32;
+                if let Some(h) = hour_of(ctx, time) {
+                    p.hours[h] += 1;
+                }
+
+                for url in find_urls(content) {
+                    let u = self.urls.entry(url).or_insert_with(|| {
+                        UrlStat {
+                            count: 0,
+                            last_by: String::new(),
+                        }
+                    });
+                    u.count += 1;
+                    u.last_by = p.nick.clone();
+                }
+                // smileys are punctuation, which words leave out
+                for w in content.split_whitespace().filter(|w| SMILEYS.contains(w)) {
+                    p.smileys += 1;
+                    *self.smileys.entry(w.to_owned()).or_insert(0) += 1;
+                }
+                let ranked = words(content).filter(|w| !SMILEYS.contains(w))
+                                           .filter(|w| w.chars().any(char::is_alphabetic));
+                for w in ranked {
+                    *self.words.entry(w.to_lowercase()).or_insert(0) += 1;
+                }
+            }
+            Event { ty: Type::Kick { ref kicked_nick, ref kicking_nick, .. }, .. } => {
+                nick_stat(&mut self.freqs, ctx, kicked_nick).kicks_received += 1;
+                if let &Some(ref kicker) = kicking_nick {
+                    nick_stat(&mut self.freqs, ctx, kicker).kicks_given += 1;
+                }
+            }
+            Event { ty: Type::TopicChange { ref nick, ref new_topic }, ref time, .. } => {
+                if let &Some(ref nick) = nick {
+                    nick_stat(&mut self.freqs, ctx, nick).topics += 1;
+                }
+                self.topics.push(TopicStat {
+                    nick: nick.as_ref().map(|n| n.to_string()),
+                    topic: new_topic.to_string(),
+                    time: match time {
+                        &Time::Timestamp(t) => Some(t),
+                        _ => None,
+                    },
+                });
+            }
+            Event { ty: Type::Mode { nick: Some(ref nick), ref mode, .. }, .. } => {
+                nick_stat(&mut self.freqs, ctx, nick).modes +=
+                    mode.chars().filter(|&c| c != '+' && c != '-').count() as u32;
+            }
+            Event { ty: Type::Quit { ref nick, reason: Some(ref reason), .. }, .. } => {
+                let p = nick_stat(&mut self.freqs, ctx, nick);
+                *p.quit_reasons.entry(reason.to_string()).or_insert(0) += 1;
             }
             _ => (),
         }
     }
 
-    Ok(Stats {
-        freqs: freqs,
-        week: week,
+    /// Add `other`, counted from the part of a log that follows the one these were counted
+    /// from. The result is the same as counting both parts at once.
+    pub fn combine(&mut self, other: Stats) {
+        for (key, stat) in other.freqs {
+            match self.freqs.entry(key) {
+                Entry::Occupied(mut e) => e.get_mut().merge(stat),
+                Entry::Vacant(e) => {
+                    e.insert(stat);
+                }
+            }
+        }
+        for (day, other_day) in self.week.iter_mut().zip(other.week.iter()) {
+            for (a, b) in day.iter_mut().zip(other_day.iter()) {
+                *a += *b;
+            }
+        }
+        for (word, n) in other.words {
+            *self.words.entry(word).or_insert(0) += n;
+        }
+        for (url, stat) in other.urls {
+            match self.urls.entry(url) {
+                Entry::Occupied(mut e) => {
+                    let u = e.get_mut();
+                    u.count += stat.count;
+                    u.last_by = stat.last_by;
+                }
+                Entry::Vacant(e) => {
+                    e.insert(stat);
+                }
+            }
+        }
+        for (smiley, n) in other.smileys {
+            *self.smileys.entry(smiley).or_insert(0) += n;
+        }
+        self.topics.extend(other.topics);
+        for (ty, n) in other.totals {
+            *self.totals.entry(ty).or_insert(0) += n;
+        }
+        for (channel, totals) in other.channel_totals {
+            let mine = self.channel_totals.entry(channel).or_insert_with(BTreeMap::new);
+            for (ty, n) in totals {
+                *mine.entry(ty).or_insert(0) += n;
+            }
+        }
+    }
+
+    /// Count nicks of the same person as one, under their canonical name.
+    pub fn resolve(mut self, ids: &Identities) -> Stats {
+        self.freqs = ids.merge(self.freqs);
+        for url in self.urls.values_mut() {
+            url.last_by = ids.resolve(&url.last_by);
+        }
+        for topic in &mut self.topics {
+            topic.nick = topic.nick.as_ref().map(|n| ids.resolve(n));
+        }
+        self
+    }
+}
+
+/// Return all active nicks, with lines, words and words per lines counted, and events of
+/// every type, per nick and in total. With `identities`, per person instead of per nick.
+pub fn stats(ctx: &Context,
+             input: &mut BufRead,
+             decoder: &mut Decode,
+             mut identities: Option<&mut Identities>)
+             -> ilc_base::Result<Stats> {
+    let mut stats = Stats::new();
+    for e in decoder.decode(&ctx, input) {
+        let m = try!(e);
+        if let Some(ref mut ids) = identities {
+            ids.observe(&m);
+        }
+        stats.add(ctx, &m);
+    }
+    Ok(match identities {
+        Some(ids) => stats.resolve(ids),
+        None => stats,
+    })
+}
+
+/// Like `stats`, but every input is read in a worker thread of its own, up to `threads` at once.
+/// The results are the same as for the inputs read one after the other.
+pub fn stats_files(ctx: &Context,
+                   inputs: Vec<Input>,
+                   decoder: Box<Decode + Send + Sync>,
+                   threads: usize,
+                   identities: Option<&mut Identities>)
+                   -> ilc_base::Result<Stats> {
+    fn part(ctx: &Context,
+            input: &mut BufRead,
+            decoder: &Decode)
+            -> ilc_base::Result<(Stats, Sightings)> {
+        let mut stats = Stats::new();
+        let mut sightings = Sightings::new(ctx.casemapping);
+        for e in decoder.decode(ctx, input) {
+            let e = try!(e);
+            sightings.record(&e);
+            stats.add(ctx, &e);
+        }
+        Ok((stats, sightings))
+    }
+
+    let parts = parallel::each(ctx, inputs, decoder, threads, part);
+    let mut stats = Stats::new();
+    let mut identities = identities;
+    for part in parts {
+        let (part, sightings) = try!(part);
+        stats.combine(part);
+        if let Some(ref mut ids) = identities {
+            ids.learn_all(sightings);
+        }
+    }
+    Ok(match identities {
+        Some(ids) => stats.resolve(ids),
+        None => stats,
     })
 }
+
+/// The length of the intervals a `Series` is split into.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bucket {
+    Day,
+    /// Weeks start on mondays, too.
+    Week,
+    Month,
+}
+
+impl FromStr for Bucket {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Bucket, ()> {
+        match s {
+            "day" => Ok(Bucket::Day),
+            "week" => Ok(Bucket::Week),
+            "month" => Ok(Bucket::Month),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Bucket {
+    /// The first day of the bucket `date` falls into.
+    pub fn start(&self, date: NaiveDate) -> NaiveDate {
+        match *self {
+            Bucket::Day => date,
+            Bucket::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Bucket::Month => NaiveDate::from_ymd(date.year(), date.month(), 1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Point {
+    pub lines: u32,
+    pub words: u32,
+    /// Nicks that said something, in canonical case, only kept for channels
+    pub speakers: HashSet<String>,
+}
+
+impl Point {
+    /// How many nicks said something.
+    pub fn nicks(&self) -> usize {
+        self.speakers.len()
+    }
+
+    fn merge(&mut self, other: Point) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.speakers.extend(other.speakers);
+    }
+}
+
+impl Serialize for Point {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a Point);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("lines", self.0.lines));
+                try!(s.serialize_struct_elt("words", self.0.words));
+                try!(s.serialize_struct_elt("nicks", self.0.nicks()));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(3)
+            }
+        }
+
+        s.serialize_struct("Point", Visitor(self))
+    }
+}
+
+/// Points keyed by the first day of their bucket, serialized as ISO 8601 dates.
+pub type Points = BTreeMap<NaiveDate, Point>;
+
+struct PointsMap<'a>(&'a Points);
+
+impl<'a> Serialize for PointsMap<'a> {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(btree_map::Iter<'a, NaiveDate, Point>, usize);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                match self.0.next() {
+                    Some((date, point)) => {
+                        try!(s.serialize_map_elt(date.to_string(), point));
+                        Ok(Some(()))
+                    }
+                    None => Ok(None),
+                }
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(self.1)
+            }
+        }
+
+        s.serialize_map(Visitor(self.0.iter(), self.0.len()))
+    }
+}
+
+pub struct NickSeries {
+    pub nick: String,
+    pub points: Points,
+}
+
+impl Mergeable for NickSeries {
+    fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    fn empty(person: &str) -> NickSeries {
+        NickSeries {
+            nick: person.to_owned(),
+            points: BTreeMap::new(),
+        }
+    }
+
+    fn merge(&mut self, other: NickSeries) {
+        // both are in the same buckets already
+        for (date, point) in other.points {
+            self.points.entry(date).or_insert_with(Point::default).merge(point);
+        }
+    }
+}
+
+impl Serialize for NickSeries {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a NickSeries);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("nick", &self.0.nick));
+                try!(s.serialize_struct_elt("points", PointsMap(&self.0.points)));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(2)
+            }
+        }
+
+        s.serialize_struct("NickSeries", Visitor(self))
+    }
+}
+
+/// Activity over time, per channel and per nick.
+pub struct Series {
+    pub bucket: Bucket,
+    /// Keyed by the channel in canonical case, or empty if it's unknown
+    pub channels: HashMap<String, Points>,
+    /// Keyed by the nick in canonical case
+    pub nicks: HashMap<String, NickSeries>,
+}
+
+impl Serialize for Series {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a Series);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                let bucket = match self.0.bucket {
+                    Bucket::Day => "day",
+                    Bucket::Week => "week",
+                    Bucket::Month => "month",
+                };
+                let channels: BTreeMap<&str, PointsMap> = self.0
+                                                              .channels
+                                                              .iter()
+                                                              .map(|(c, p)| {
+                                                                  (c as &str, PointsMap(p))
+                                                              })
+                                                              .collect();
+                try!(s.serialize_struct_elt("bucket", bucket));
+                try!(s.serialize_struct_elt("channels", channels));
+                try!(s.serialize_struct_elt("nicks", &self.0.nicks));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(3)
+            }
+        }
+
+        s.serialize_struct("Series", Visitor(self))
+    }
+}
+
+fn add_points(into: &mut Points, points: Points, bucket: Bucket) {
+    for (date, point) in points {
+        into.entry(bucket.start(date)).or_insert_with(Point::default).merge(point);
+    }
+}
+
+impl Series {
+    pub fn new(bucket: Bucket) -> Series {
+        Series {
+            bucket: bucket,
+            channels: HashMap::new(),
+            nicks: HashMap::new(),
+        }
+    }
+
+    /// Count a single event, in `timezone_out`. Events without a full date are ignored.
+    pub fn add(&mut self, ctx: &Context, e: &Event) {
+        if let Event { ty: Type::Msg { ref from, ref content, .. }, time: Time::Timestamp(t), .. } =
+               *e {
+            let date = self.bucket.start(ctx.timezone_out.timestamp(t, 0).date().naive_local());
+            let words = words(content).count() as u32;
+            let nick = strip_nick(from);
+            let key = ctx.casemapping.to_lower(nick);
+            let channel = e.channel
+                           .as_ref()
+                           .map(|c| ctx.casemapping.to_lower(c))
+                           .or(ctx.channel.as_ref().map(|c| ctx.casemapping.to_lower(c)))
+                           .unwrap_or_default();
+
+            {
+                let n = self.nicks.entry(key.clone()).or_insert_with(|| {
+                    NickSeries {
+                        nick: nick.to_owned(),
+                        points: BTreeMap::new(),
+                    }
+                });
+                let p = n.points.entry(date).or_insert_with(Point::default);
+                p.lines += 1;
+                p.words += words;
+            }
+            let p = self.channels
+                        .entry(channel)
+                        .or_insert_with(BTreeMap::new)
+                        .entry(date)
+                        .or_insert_with(Point::default);
+            p.lines += 1;
+            p.words += words;
+            p.speakers.insert(key);
+        }
+    }
+
+    /// Add `other`, which has to use the same bucket.
+    pub fn combine(&mut self, other: Series) {
+        assert_eq!(self.bucket, other.bucket);
+        let bucket = self.bucket;
+        for (channel, points) in other.channels {
+            add_points(self.channels.entry(channel).or_insert_with(BTreeMap::new),
+                       points,
+                       bucket);
+        }
+        for (key, n) in other.nicks {
+            let nick = n.nick;
+            let m = self.nicks.entry(key).or_insert_with(|| {
+                NickSeries {
+                    nick: nick,
+                    points: BTreeMap::new(),
+                }
+            });
+            add_points(&mut m.points, n.points, bucket);
+        }
+    }
+
+    /// The same activity in larger buckets, i.e. weeks or months from days.
+    pub fn rebucket(self, bucket: Bucket) -> Series {
+        let mut series = Series::new(bucket);
+        for (channel, points) in self.channels {
+            add_points(series.channels.entry(channel).or_insert_with(BTreeMap::new),
+                       points,
+                       bucket);
+        }
+        for (key, mut n) in self.nicks {
+            let points = mem::replace(&mut n.points, BTreeMap::new());
+            add_points(&mut n.points, points, bucket);
+            series.nicks.insert(key, n);
+        }
+        series
+    }
+
+    /// Count nicks of the same person as one, under their canonical name.
+    pub fn resolve(mut self, ctx: &Context, ids: &Identities) -> Series {
+        self.nicks = ids.merge(self.nicks);
+        for points in self.channels.values_mut() {
+            for p in points.values_mut() {
+                p.speakers = p.speakers
+                              .iter()
+                              .map(|n| ctx.casemapping.to_lower(&ids.resolve(n)))
+                              .collect();
+            }
+        }
+        self
+    }
+}
+
+/// Count lines and words per `bucket`, in `timezone_out`. Events without a full date are ignored.
+/// With `identities`, nicks are counted per person.
+pub fn series(ctx: &Context,
+              input: &mut BufRead,
+              decoder: &mut Decode,
+              bucket: Bucket,
+              mut identities: Option<&mut Identities>)
+              -> ilc_base::Result<Series> {
+    let mut series = Series::new(bucket);
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        if let Some(ref mut ids) = identities {
+            ids.observe(&e);
+        }
+        series.add(ctx, &e);
+    }
+    Ok(match identities {
+        Some(ids) => series.resolve(ctx, ids),
+        None => series,
+    })
+}
+
+/// Like `series`, but every input is read in a worker thread of its own, up to `threads` at
+/// once. The results are the same as for the inputs read one after the other.
+pub fn series_files(ctx: &Context,
+                    inputs: Vec<Input>,
+                    decoder: Box<Decode + Send + Sync>,
+                    bucket: Bucket,
+                    threads: usize,
+                    identities: Option<&mut Identities>)
+                    -> ilc_base::Result<Series> {
+    fn part(ctx: &Context,
+            input: &mut BufRead,
+            decoder: &Decode,
+            bucket: Bucket)
+            -> ilc_base::Result<(Series, Sightings)> {
+        let mut series = Series::new(bucket);
+        let mut sightings = Sightings::new(ctx.casemapping);
+        for e in decoder.decode(ctx, input) {
+            let e = try!(e);
+            sightings.record(&e);
+            series.add(ctx, &e);
+        }
+        Ok((series, sightings))
+    }
+
+    let parts = parallel::each(ctx, inputs, decoder, threads, move |ctx, input, decoder| {
+        part(ctx, input, decoder, bucket)
+    });
+    let mut series = Series::new(bucket);
+    let mut identities = identities;
+    for part in parts {
+        let (part, sightings) = try!(part);
+        series.combine(part);
+        if let Some(ref mut ids) = identities {
+            ids.learn_all(sightings);
+        }
+    }
+    Ok(match identities {
+        Some(ids) => series.resolve(ctx, ids),
+        None => series,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use chrono::{FixedOffset, NaiveDate};
+
+    use ilc_base::{CaseMapping, Context, Event};
+    use ilc_base::event::Type;
+
+    use fixtures::{Events, event, msg};
+    use identity::Identities;
+    use super::{Bucket, Series, Stats, stats};
+
+    /// Sunday 2016-02-28 23:30 UTC, which is already monday two hours east.
+    const SUNDAY_NIGHT: i64 = 1456702200;
+    /// Monday 2016-02-29 23:30 UTC, which is already march two hours east.
+    const LEAP_NIGHT: i64 = 1456788600;
+
+    fn nick(old: &'static str, new: &'static str, time: i64) -> Event<'static> {
+        event(Type::Nick {
+                  old_nick: Cow::Borrowed(old),
+                  new_nick: Cow::Borrowed(new),
+              },
+              time)
+    }
+
+    fn east(hours: i32) -> Context {
+        let mut ctx = Context::default();
+        ctx.timezone_out = FixedOffset::east(hours * 3600);
+        ctx
+    }
+
+    #[test]
+    fn counts_per_type() {
+        let ctx = Context::default();
+        let mut stats = Stats::new();
+        for e in &[msg("Foo", "hello there :)", 100),
+                   msg("Bar", "hi Foo", 110),
+                   nick("Foo", "Fob", 120),
+                   msg("@Fob_", "xD foo/bar", 130)] {
+            stats.add(&ctx, e);
+        }
+        assert_eq!(stats.totals.get("message"), Some(&3));
+        assert_eq!(stats.totals.get("nick"), Some(&1));
+        assert_eq!(stats.channel_totals["#c"].get("message"), Some(&3));
+        assert_eq!(stats.freqs["foo"].lines, 1);
+        assert_eq!(stats.freqs["foo"].events.get("nick"), Some(&1));
+        // prefixes and trailing underscores are not part of the nick
+        assert_eq!(stats.freqs["fob"].words, 3);
+        assert_eq!(stats.freqs["fob"].smileys, 1);
+        assert_eq!(stats.smileys.get("xD"), Some(&1));
+        assert_eq!(stats.words.get("foo"), Some(&2));
+        assert_eq!(stats.words.get("xd"), None);
+    }
+
+    #[test]
+    fn week_in_timezone_out() {
+        let mut stats = Stats::new();
+        stats.add(&east(2), &msg("Foo", "late", SUNDAY_NIGHT));
+        assert_eq!(stats.week[0][1], 1);
+        assert_eq!(stats.week[6][23], 0);
+    }
+
+    #[test]
+    fn buckets_in_timezone_out() {
+        let dates = |ctx: &Context, bucket: Bucket| {
+            let mut series = Series::new(bucket);
+            series.add(ctx, &msg("Foo", "late", SUNDAY_NIGHT));
+            series.add(ctx, &msg("Foo", "later", LEAP_NIGHT));
+            series.channels["#c"].keys().cloned().collect::<Vec<_>>()
+        };
+        let d = |m, d| NaiveDate::from_ymd(2016, m, d);
+        assert_eq!(dates(&Context::default(), Bucket::Week), vec![d(2, 22), d(2, 29)]);
+        assert_eq!(dates(&east(2), Bucket::Week), vec![d(2, 29)]);
+        assert_eq!(dates(&Context::default(), Bucket::Month), vec![d(2, 1)]);
+        assert_eq!(dates(&east(2), Bucket::Month), vec![d(2, 1), d(3, 1)]);
+    }
+
+    #[test]
+    fn merges_nicks_of_a_person() {
+        let log = vec![msg("Foo", "hello", 100),
+                       nick("Foo", "Fob", 110),
+                       msg("Fob", "hello again", 120),
+                       msg("Bar", "hi", 130),
+                       msg("Baz", "hi", 140)];
+        let ctx = Context::default();
+        let mut ids = Identities::new(CaseMapping::Rfc1459);
+        ids.alias("Bar", "Baz");
+        let mut input: &[u8] = &[];
+        let stats = stats(&ctx, &mut input, &mut Events(log), Some(&mut ids)).unwrap();
+        let mut people = stats.freqs.keys().cloned().collect::<Vec<_>>();
+        people.sort();
+        assert_eq!(people, vec!["bar", "foo"]);
+        assert_eq!(stats.freqs["foo"].nick, "Foo");
+        assert_eq!(stats.freqs["foo"].lines, 2);
+        assert_eq!(stats.freqs["foo"].words, 3);
+        assert_eq!(stats.freqs["foo"].events.get("nick"), Some(&1));
+        assert_eq!(stats.freqs["bar"].lines, 2);
+    }
+}
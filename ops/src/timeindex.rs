@@ -0,0 +1,254 @@
+//! Seeking by time
+//!
+//! Decoding a multi-gigabyte log to get at one hour of it takes a while. A
+//! `TimeIndex` remembers the time of an event every so many bytes, so that
+//! reading can start close before the time of interest. It is kept in a small
+//! file next to the log, and only works for logs sorted by time, in formats
+//! that can start decoding at the beginning of any event, like line-based ones.
+use ilc_base::{self, Context, Decode, Error, Event, Time};
+use grep::LineCount;
+use state::Persist;
+
+use std::cmp;
+use std::io::{BufRead, Read, Write};
+
+use chrono::NaiveDate;
+use chrono::offset::TimeZone;
+
+/// Starts every time index file, the digit is the version of the format.
+const MAGIC: &'static [u8] = b"ilctime1";
+
+/// Where an event starts, and its time.
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint {
+    pub time: i64,
+    pub offset: u64,
+}
+
+impl Persist for Checkpoint {
+    fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(self.time.save(out));
+        self.offset.save(out)
+    }
+
+    fn load(input: &mut Read) -> ilc_base::Result<Checkpoint> {
+        Ok(Checkpoint {
+            time: try!(Persist::load(input)),
+            offset: try!(Persist::load(input)),
+        })
+    }
+}
+
+/// The timestamp of the Unix epoch in `timezone_in`, as the times of events depend on it.
+fn zone(ctx: &Context) -> i64 {
+    ctx.timezone_in
+       .from_local_datetime(&NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0))
+       .single()
+       .map_or(0, |t| t.timestamp())
+}
+
+pub struct TimeIndex {
+    /// How long the log was when it was indexed
+    pub len: u64,
+    /// See `zone`
+    zone: i64,
+    /// In order of offset
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+impl TimeIndex {
+    /// Index a whole log, with a checkpoint at least every `every` bytes.
+    pub fn build(ctx: &Context,
+                 input: &mut BufRead,
+                 decoder: &mut Decode,
+                 every: u64)
+                 -> ilc_base::Result<TimeIndex> {
+        let mut input = LineCount::new(input);
+        let read = input.offset();
+        let mut checkpoints: Vec<Checkpoint> = Vec::new();
+        // the event starts right after the one before it
+        let mut offset = 0;
+        for e in decoder.decode(&ctx, &mut input) {
+            if let Time::Timestamp(time) = try!(e).time {
+                if checkpoints.last().map_or(true, |c| offset >= c.offset + every) {
+                    checkpoints.push(Checkpoint {
+                        time: time,
+                        offset: offset,
+                    });
+                }
+            }
+            offset = read.get();
+        }
+        Ok(TimeIndex {
+            len: read.get(),
+            zone: zone(ctx),
+            checkpoints: checkpoints,
+        })
+    }
+
+    /// Read a time index file, as written by `save`.
+    pub fn load(input: &mut Read) -> ilc_base::Result<TimeIndex> {
+        let mut magic = [0u8; 8];
+        if input.read_exact(&mut magic).is_err() || &magic[..] != MAGIC {
+            return Err(Error::Parse("not an ilc time index file, or an incompatible version"
+                                        .to_owned()));
+        }
+        Ok(TimeIndex {
+            len: try!(Persist::load(input)),
+            zone: try!(Persist::load(input)),
+            checkpoints: try!(Persist::load(input)),
+        })
+    }
+
+    pub fn save(&self, out: &mut Write) -> ilc_base::Result<()> {
+        try!(out.write_all(MAGIC));
+        try!(self.len.save(out));
+        try!(self.zone.save(out));
+        try!(self.checkpoints.save(out));
+        Ok(())
+    }
+
+    /// Whether the index fits a log that is `len` bytes long now, read with `ctx`. Logs that
+    /// grew still fit, since they are only appended to.
+    pub fn fits(&self, ctx: &Context, len: u64) -> bool {
+        len >= self.len && self.zone == zone(ctx)
+    }
+
+    /// Where to start reading to get every event from `since` on.
+    pub fn start_of(&self, since: i64) -> u64 {
+        // everything before a checkpoint is at most as late as it
+        match self.checkpoints.iter().rposition(|c| c.time < since) {
+            Some(i) => self.checkpoints[i].offset,
+            None => 0,
+        }
+    }
+
+    /// Where to stop reading to get every event until `until`, if not at the end.
+    pub fn end_of(&self, until: i64) -> Option<u64> {
+        // everything after a checkpoint is at least as late as it
+        self.checkpoints.iter().find(|c| c.time > until).map(|c| c.offset)
+    }
+
+    /// Where to start and stop reading to get every event from `since` until `until`, without
+    /// stopping before the start if `since` comes after `until`.
+    pub fn range(&self, since: Option<i64>, until: Option<i64>) -> (u64, u64) {
+        let start = since.map_or(0, |s| self.start_of(s));
+        let end = until.and_then(|u| self.end_of(u)).unwrap_or(u64::max_value());
+        (start, cmp::max(start, end))
+    }
+}
+
+/// A decoder that leaves out events before `since` and after `until`. Events without a full
+/// date are kept.
+pub struct Within {
+    pub inner: Box<Decode + Send + Sync>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+impl Decode for Within {
+    fn decode<'a>(&'a self,
+                  context: &'a Context,
+                  input: &'a mut BufRead)
+                  -> Box<Iterator<Item = ilc_base::Result<Event<'a>>> + 'a> {
+        let (since, until) = (self.since, self.until);
+        Box::new(self.inner
+                     .decode(context, input)
+                     .filter(move |e| {
+                         match *e {
+                             Ok(Event { time: Time::Timestamp(t), .. }) => {
+                                 since.map_or(true, |s| t >= s) && until.map_or(true, |u| t <= u)
+                             }
+                             _ => true,
+                         }
+                     }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ilc_base::{Context, Decode, Event, Time};
+
+    use fixtures::{Events, msg};
+    use super::{Checkpoint, TimeIndex, Within};
+
+    fn index() -> TimeIndex {
+        TimeIndex {
+            len: 400,
+            zone: 0,
+            checkpoints: vec![Checkpoint {
+                                  time: 100,
+                                  offset: 0,
+                              },
+                              Checkpoint {
+                                  time: 200,
+                                  offset: 100,
+                              },
+                              Checkpoint {
+                                  time: 200,
+                                  offset: 200,
+                              },
+                              Checkpoint {
+                                  time: 300,
+                                  offset: 300,
+                              }],
+        }
+    }
+
+    #[test]
+    fn start_of() {
+        let index = index();
+        assert_eq!(index.start_of(50), 0);
+        assert_eq!(index.start_of(100), 0);
+        assert_eq!(index.start_of(150), 0);
+        // events of that time may come before the checkpoint
+        assert_eq!(index.start_of(200), 0);
+        assert_eq!(index.start_of(250), 200);
+        assert_eq!(index.start_of(1000), 300);
+    }
+
+    #[test]
+    fn end_of() {
+        let index = index();
+        assert_eq!(index.end_of(50), Some(0));
+        assert_eq!(index.end_of(100), Some(100));
+        assert_eq!(index.end_of(200), Some(300));
+        assert_eq!(index.end_of(300), None);
+    }
+
+    #[test]
+    fn range() {
+        let index = index();
+        assert_eq!(index.range(None, None), (0, u64::max_value()));
+        assert_eq!(index.range(Some(250), Some(250)), (200, 300));
+        assert_eq!(index.range(Some(250), None), (200, u64::max_value()));
+        // nothing to read, rather than a negative length
+        assert_eq!(index.range(Some(1000), Some(50)), (300, 300));
+    }
+
+    fn within(since: Option<i64>, until: Option<i64>) -> Vec<String> {
+        let undated = Event { time: Time::Hms(10, 0, 0), ..msg("Foo", "undated", 0) };
+        let decoder = Within {
+            inner: Box::new(Events(vec![msg("Foo", "early", 100),
+                                        undated,
+                                        msg("Foo", "start", 200),
+                                        msg("Foo", "end", 300),
+                                        msg("Foo", "late", 400)])),
+            since: since,
+            until: until,
+        };
+        let ctx = Context::default();
+        let mut input: &[u8] = &[];
+        decoder.decode(&ctx, &mut input)
+               .map(|e| e.unwrap().ty.text().unwrap().to_owned())
+               .collect()
+    }
+
+    #[test]
+    fn within_bounds() {
+        assert_eq!(within(Some(200), Some(300)), vec!["undated", "start", "end"]);
+        assert_eq!(within(Some(250), None), vec!["undated", "end", "late"]);
+        assert_eq!(within(None, Some(199)), vec!["early", "undated"]);
+        assert_eq!(within(None, None).len(), 5);
+    }
+}
@@ -0,0 +1,244 @@
+//! Topic timelines
+//!
+//! Every channel's topics, in order, with who set them and for how long they
+//! stayed. Topics the server announces on joining only start a new entry if
+//! they differ from the topic already known, since the log may have missed the
+//! change while the logger was away.
+use ilc_base::{self, Context, Decode, Time};
+use ilc_base::event::Type;
+use members::channel_of;
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+
+use serde::ser::{MapVisitor, Serialize, Serializer};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+impl Serialize for Change {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a Change);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                let (op, text) = match *self.0 {
+                    Change::Same(ref w) => ("=", w),
+                    Change::Added(ref w) => ("+", w),
+                    Change::Removed(ref w) => ("-", w),
+                };
+                try!(s.serialize_struct_elt("op", op));
+                try!(s.serialize_struct_elt("text", text));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(2)
+            }
+        }
+
+        s.serialize_struct("Change", Visitor(self))
+    }
+}
+
+/// The words of `new` compared to `old`, by their longest common subsequence.
+pub fn diff(old: &str, new: &str) -> Vec<Change> {
+    let a: Vec<&str> = old.split_whitespace().collect();
+    let b: Vec<&str> = new.split_whitespace().collect();
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            changes.push(Change::Same(a[i].to_owned()));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            changes.push(Change::Removed(a[i].to_owned()));
+            i += 1;
+        } else {
+            changes.push(Change::Added(b[j].to_owned()));
+            j += 1;
+        }
+    }
+    changes
+}
+
+#[derive(Clone, Debug)]
+pub struct TopicEntry {
+    pub topic: String,
+    /// `None` for topics announced by the server
+    pub nick: Option<String>,
+    /// Only known for logs with full dates
+    pub start: Option<i64>,
+    /// When the next topic was set, if known
+    pub end: Option<i64>,
+    /// Whether no topic replaced this one, which `end` cannot tell in logs without full dates
+    pub current: bool,
+    /// Against the previous topic, `None` for the first one
+    pub diff: Option<Vec<Change>>,
+}
+
+impl TopicEntry {
+    /// How long the topic stayed, in seconds, if both ends are known.
+    pub fn lasted(&self) -> Option<i64> {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => Some(end - start),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for TopicEntry {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a TopicEntry);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("topic", &self.0.topic));
+                try!(s.serialize_struct_elt("nick", &self.0.nick));
+                try!(s.serialize_struct_elt("start", self.0.start));
+                try!(s.serialize_struct_elt("end", self.0.end));
+                try!(s.serialize_struct_elt("lasted", self.0.lasted()));
+                try!(s.serialize_struct_elt("current", self.0.current));
+                try!(s.serialize_struct_elt("diff", &self.0.diff));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(7)
+            }
+        }
+
+        s.serialize_struct("TopicEntry", Visitor(self))
+    }
+}
+
+/// The topics of every channel, oldest first, keyed by the channel in canonical case.
+pub fn topics(ctx: &Context,
+              input: &mut BufRead,
+              decoder: &mut Decode)
+              -> ilc_base::Result<BTreeMap<String, Vec<TopicEntry>>> {
+    let mut channels: BTreeMap<String, Vec<TopicEntry>> = BTreeMap::new();
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        let (nick, topic) = match e.ty {
+            Type::Topic { ref topic } => (None, topic),
+            Type::TopicChange { ref nick, ref new_topic } => (nick.as_ref(), new_topic),
+            _ => continue,
+        };
+        let time = match e.time {
+            Time::Timestamp(t) => Some(t),
+            _ => None,
+        };
+        let timeline = channels.entry(channel_of(ctx, &e)).or_insert_with(Vec::new);
+        let diff = match timeline.last_mut() {
+            Some(ref last) if nick.is_none() && last.topic == **topic => continue,
+            Some(last) => {
+                last.end = time;
+                last.current = false;
+                Some(diff(&last.topic, topic))
+            }
+            None => None,
+        };
+        timeline.push(TopicEntry {
+            topic: topic.to_string(),
+            nick: nick.map(|n| n.to_string()),
+            start: time,
+            end: None,
+            current: true,
+            diff: diff,
+        });
+    }
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use ilc_base::{Context, Event, Time};
+    use ilc_base::event::Type;
+
+    use fixtures::{Events, event};
+    use super::{Change, diff, topics};
+    use super::Change::*;
+
+    fn words(changes: &[Change]) -> Vec<String> {
+        changes.iter()
+               .map(|c| {
+                   match *c {
+                       Same(ref w) => format!("={}", w),
+                       Added(ref w) => format!("+{}", w),
+                       Removed(ref w) => format!("-{}", w),
+                   }
+               })
+               .collect()
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(words(&diff("welcome  to #ilc", "welcome to\t#ilc")),
+                   vec!["=welcome", "=to", "=#ilc"]);
+        assert!(diff("", "").is_empty());
+    }
+
+    #[test]
+    fn replaced() {
+        assert_eq!(words(&diff("release 0.1 out now", "release 0.2 out now")),
+                   vec!["=release", "-0.1", "+0.2", "=out", "=now"]);
+    }
+
+    #[test]
+    fn added_and_removed() {
+        assert_eq!(words(&diff("", "hello world")), vec!["+hello", "+world"]);
+        assert_eq!(words(&diff("hello world", "")), vec!["-hello", "-world"]);
+        assert_eq!(words(&diff("a b c", "b c d")), vec!["-a", "=b", "=c", "+d"]);
+    }
+
+    fn topic(nick: &'static str, topic: &'static str, time: i64) -> Event<'static> {
+        event(Type::TopicChange {
+                  nick: Some(Cow::Borrowed(nick)),
+                  new_topic: Cow::Borrowed(topic),
+              },
+              time)
+    }
+
+    #[test]
+    fn only_the_last_is_current() {
+        let undated = |e: Event<'static>| Event { time: Time::Hms(10, 0, 0), ..e };
+        let log = vec![topic("Foo", "hello", 100),
+                       undated(topic("Bar", "hello world", 0)),
+                       undated(topic("Foo", "bye", 0))];
+        let ctx = Context::default();
+        let mut input: &[u8] = &[];
+        let channels = topics(&ctx, &mut input, &mut Events(log)).unwrap();
+        let timeline = &channels["#c"];
+        assert_eq!(timeline.iter().map(|t| t.current).collect::<Vec<_>>(),
+                   vec![false, false, true]);
+        // replaced by an undated topic, so how long it stayed is unknown
+        assert_eq!(timeline[0].lasted(), None);
+    }
+}
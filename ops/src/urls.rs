@@ -0,0 +1,311 @@
+//! Link extraction
+use ilc_base::{self, Context, Decode, Time};
+use identity::Identities;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use serde::ser::{MapVisitor, Serialize, Serializer};
+
+/// Remove mIRC formatting: bold, colours, italics, underline, reverse and reset.
+pub fn strip_formatting(s: &str) -> Cow<str> {
+    if !s.contains(|c| c < ' ') {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x03' => {
+                // up to two digits of foreground, optionally a comma and two digits of background
+                for _ in 0..2 {
+                    if chars.peek().map_or(false, |c| c.is_digit(10)) {
+                        chars.next();
+                    }
+                }
+                if chars.peek() == Some(&',') {
+                    let mut ahead = chars.clone();
+                    ahead.next();
+                    if ahead.peek().map_or(false, |c| c.is_digit(10)) {
+                        chars.next();
+                        for _ in 0..2 {
+                            if chars.peek().map_or(false, |c| c.is_digit(10)) {
+                                chars.next();
+                            }
+                        }
+                    }
+                }
+            }
+            '\x02' | '\x0f' | '\x11' | '\x16' | '\x1d' | '\x1e' | '\x1f' => (),
+            c => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Cut a candidate URL at punctuation that most likely belongs to the surrounding text.
+/// Closing parentheses are only kept if they close one inside the URL.
+fn trim_url(mut url: &str) -> &str {
+    loop {
+        let before = url.len();
+        url = url.trim_right_matches(|c| ".,;:!?'\"<>]}*".contains(c));
+        if url.ends_with(')') && url.matches('(').count() < url.matches(')').count() {
+            url = &url[..url.len() - 1];
+        }
+        if url.len() == before {
+            return url;
+        }
+    }
+}
+
+/// URLs contained in `s`, without surrounding punctuation or formatting.
+pub fn find_urls(s: &str) -> Vec<String> {
+    let s = strip_formatting(s);
+    let mut urls = Vec::new();
+    for word in s.split_whitespace() {
+        let start = ["http://", "https://", "ftp://", "www."]
+                        .iter()
+                        .filter_map(|p| word.find(p))
+                        .min();
+        if let Some(start) = start {
+            // "www." also matches inside "http://www."
+            let url = trim_url(&word[start..]);
+            // the trailing dot of a lone "www." is trimmed as punctuation
+            let scheme_only = url.ends_with("://") || url == "www";
+            if !scheme_only {
+                urls.push(url.to_owned());
+            }
+        }
+    }
+    urls
+}
+
+/// The host part of `url`, in lowercase.
+pub fn domain(url: &str) -> String {
+    let rest = match url.find("://") {
+        Some(i) => &url[i + 3..],
+        None => url,
+    };
+    let host = rest.split(|c| c == '/' || c == '?' || c == '#').next().unwrap_or(rest);
+    let host = host.rsplit('@').next().unwrap_or(host);
+    let host = match host.rfind(':') {
+        Some(i) if !host.ends_with(']') => &host[..i],
+        _ => host,
+    };
+    host.to_lowercase()
+}
+
+struct TimeSer<'a>(&'a Time);
+
+impl<'a> Serialize for TimeSer<'a> {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        match self.0 {
+            &Time::Timestamp(t) => s.serialize_i64(t),
+            &Time::Hms(h, m, sec) => s.serialize_str(&format!("{:02}:{:02}:{:02}", h, m, sec)),
+            &Time::Unknown => s.serialize_unit(),
+        }
+    }
+}
+
+/// A URL, and who posted it where.
+pub struct Link {
+    pub url: String,
+    pub time: Time,
+    pub nick: Option<String>,
+    pub channel: Option<String>,
+}
+
+impl Serialize for Link {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a Link);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("url", &self.0.url));
+                try!(s.serialize_struct_elt("time", TimeSer(&self.0.time)));
+                try!(s.serialize_struct_elt("nick", &self.0.nick));
+                try!(s.serialize_struct_elt("channel", &self.0.channel));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(4)
+            }
+        }
+
+        s.serialize_struct("Link", Visitor(self))
+    }
+}
+
+pub struct UrlSummary {
+    pub count: u32,
+    pub first: Time,
+    pub last: Time,
+}
+
+impl Serialize for UrlSummary {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a UrlSummary);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("count", self.0.count));
+                try!(s.serialize_struct_elt("first", TimeSer(&self.0.first)));
+                try!(s.serialize_struct_elt("last", TimeSer(&self.0.last)));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(3)
+            }
+        }
+
+        s.serialize_struct("UrlSummary", Visitor(self))
+    }
+}
+
+/// Link counts, per domain, nick and URL.
+pub struct Summary {
+    pub domains: HashMap<String, u32>,
+    pub nicks: HashMap<String, u32>,
+    /// First and last are in the order of the log
+    pub urls: HashMap<String, UrlSummary>,
+}
+
+impl Serialize for Summary {
+    fn serialize<S>(&self, s: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        struct Visitor<'a>(&'a Summary);
+        impl<'a> MapVisitor for Visitor<'a> {
+            fn visit<S>(&mut self, s: &mut S) -> Result<Option<()>, S::Error>
+                where S: Serializer
+            {
+                try!(s.serialize_struct_elt("domains", &self.0.domains));
+                try!(s.serialize_struct_elt("nicks", &self.0.nicks));
+                try!(s.serialize_struct_elt("urls", &self.0.urls));
+                Ok(None)
+            }
+
+            fn len(&self) -> Option<usize> {
+                Some(3)
+            }
+        }
+
+        s.serialize_struct("Summary", Visitor(self))
+    }
+}
+
+/// Count what `links` contains. Nicks are compared according to the context's case mapping.
+pub fn summary(ctx: &Context, links: &[Link]) -> Summary {
+    let mut domains = HashMap::new();
+    let mut nicks: HashMap<String, u32> = HashMap::new();
+    let mut spelling: HashMap<String, String> = HashMap::new();
+    let mut urls: HashMap<String, UrlSummary> = HashMap::new();
+    for link in links {
+        *domains.entry(domain(&link.url)).or_insert(0) += 1;
+        if let Some(ref nick) = link.nick {
+            let key = ctx.casemapping.to_lower(nick);
+            let nick = spelling.entry(key).or_insert_with(|| nick.clone()).clone();
+            *nicks.entry(nick).or_insert(0) += 1;
+        }
+        let u = urls.entry(link.url.clone()).or_insert_with(|| {
+            UrlSummary {
+                count: 0,
+                first: link.time.clone(),
+                last: link.time.clone(),
+            }
+        });
+        u.count += 1;
+        u.last = link.time.clone();
+    }
+    Summary {
+        domains: domains,
+        nicks: nicks,
+        urls: urls,
+    }
+}
+
+/// Every URL in the text of every event, in the order of the log.
+/// With `identities`, nicks are replaced by the canonical name of their person.
+pub fn links(ctx: &Context,
+             input: &mut BufRead,
+             decoder: &mut Decode,
+             mut identities: Option<&mut Identities>)
+             -> ilc_base::Result<Vec<Link>> {
+    let mut links = Vec::new();
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        if let Some(ref mut ids) = identities {
+            ids.observe(&e);
+        }
+        if let Some(text) = e.ty.text() {
+            for url in find_urls(text) {
+                links.push(Link {
+                    url: url,
+                    time: e.time.clone(),
+                    nick: e.ty.actor().map(str::to_owned),
+                    channel: e.channel
+                              .as_ref()
+                              .map(|c| c.to_string())
+                              .or_else(|| ctx.channel.clone()),
+                });
+            }
+        }
+    }
+
+    if let Some(ids) = identities {
+        for link in &mut links {
+            link.nick = link.nick.as_ref().map(|n| ids.resolve(n));
+        }
+    }
+    Ok(links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{domain, find_urls, strip_formatting, trim_url};
+
+    #[test]
+    fn trim() {
+        assert_eq!(trim_url("http://example.com/."), "http://example.com/");
+        assert_eq!(trim_url("http://example.com/a\","), "http://example.com/a");
+        assert_eq!(trim_url("http://example.com/a)"), "http://example.com/a");
+        assert_eq!(trim_url("http://en.wikipedia.org/wiki/Foo_(bar))."),
+                   "http://en.wikipedia.org/wiki/Foo_(bar)");
+        assert_eq!(trim_url("http://example.com/?a=b"), "http://example.com/?a=b");
+    }
+
+    #[test]
+    fn find() {
+        assert_eq!(find_urls("see http://example.com/a, and (www.example.org)!"),
+                   vec!["http://example.com/a", "www.example.org"]);
+        assert_eq!(find_urls("<https://example.com/x>"), vec!["https://example.com/x"]);
+        assert_eq!(find_urls("http://www.example.com"), vec!["http://www.example.com"]);
+        assert_eq!(find_urls("\x02http://example.com\x02 \x0304,01ftp://example.net\x03"),
+                   vec!["http://example.com", "ftp://example.net"]);
+        assert!(find_urls("nothing but http:// and www.").is_empty());
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(strip_formatting("\x0312,04blue\x0f \x1fline\x1f 3,5"), "blue line 3,5");
+        assert_eq!(strip_formatting("\x034,text"), ",text");
+    }
+
+    #[test]
+    fn domains() {
+        assert_eq!(domain("https://user@Example.COM:8080/path?q#f"), "example.com");
+        assert_eq!(domain("www.example.org/foo"), "www.example.org");
+        assert_eq!(domain("http://[::1]/"), "[::1]");
+    }
+}
@@ -0,0 +1,175 @@
+//! Word frequencies and vocabulary
+use ilc_base::{self, Context, Decode, Event};
+use ilc_base::event::Type;
+use identity::{Identities, Mergeable};
+use stats::{strip_nick, words};
+
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+/// Used when no other stopwords are given.
+pub const STOPWORDS: &'static [&'static str] = &["a", "about", "after", "all", "also", "am", "an",
+                                                 "and", "any", "are", "as", "at", "be", "because",
+                                                 "been", "but", "by", "can", "could", "did", "do",
+                                                 "does", "for", "from", "had", "has", "have", "he",
+                                                 "her", "him", "his", "how", "i", "if", "in",
+                                                 "is", "it", "its", "just", "me", "my", "no",
+                                                 "not", "of", "on", "or", "our", "she", "so",
+                                                 "that", "the", "their", "them", "then", "there",
+                                                 "they", "this", "to", "too", "up", "us", "was",
+                                                 "we", "were", "what", "when", "which", "who",
+                                                 "will", "with", "would", "you", "your"];
+
+/// Words that are too common to be interesting, in lowercase.
+pub struct Stopwords(HashSet<String>);
+
+impl Default for Stopwords {
+    fn default() -> Stopwords {
+        Stopwords(STOPWORDS.iter().map(|&w| w.to_owned()).collect())
+    }
+}
+
+impl Stopwords {
+    /// One word per line, empty lines and lines starting with `#` are ignored.
+    pub fn read(input: &mut BufRead) -> ilc_base::Result<Stopwords> {
+        let mut words = HashSet::new();
+        for line in input.lines() {
+            let line = try!(line);
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                words.insert(line.to_lowercase());
+            }
+        }
+        Ok(Stopwords(words))
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.0.contains(word)
+    }
+}
+
+#[derive(Default)]
+pub struct WordCount {
+    /// Occurrences of each word, in lowercase
+    pub counts: HashMap<String, u32>,
+    pub total: u32,
+}
+
+impl WordCount {
+    fn add(&mut self, word: &str, n: u32) {
+        *self.counts.entry(word.to_owned()).or_insert(0) += n;
+        self.total += n;
+    }
+
+    /// Number of distinct words.
+    pub fn vocabulary(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Distinct words per word, between 0 and 1.
+    pub fn diversity(&self) -> f32 {
+        if self.total == 0 { 0.0 } else { self.vocabulary() as f32 / self.total as f32 }
+    }
+
+    /// The `n` most used words that aren't stopwords, most used first.
+    pub fn top(&self, n: usize, stopwords: &Stopwords) -> Vec<(&str, u32)> {
+        let mut top: Vec<(&str, u32)> = self.counts
+                                            .iter()
+                                            .filter(|&(w, _)| !stopwords.contains(w))
+                                            .map(|(w, n)| (w.as_str(), *n))
+                                            .collect();
+        top.sort_by(|&(wa, a), &(wb, b)| (b, wa).cmp(&(a, wb)));
+        top.truncate(n);
+        top
+    }
+}
+
+pub struct NickWords {
+    pub nick: String,
+    pub words: WordCount,
+    /// Words nobody used before this nick
+    pub introduced: Vec<String>,
+}
+
+impl Mergeable for NickWords {
+    fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    fn empty(person: &str) -> NickWords {
+        NickWords {
+            nick: person.to_owned(),
+            words: WordCount::default(),
+            introduced: Vec::new(),
+        }
+    }
+
+    fn merge(&mut self, other: NickWords) {
+        for (w, c) in other.words.counts {
+            self.words.add(&w, c);
+        }
+        self.introduced.extend(other.introduced);
+    }
+}
+
+pub struct Vocabulary {
+    pub words: WordCount,
+    /// Keyed by the nick in canonical case
+    pub nicks: HashMap<String, NickWords>,
+}
+
+fn nick_words<'a>(nicks: &'a mut HashMap<String, NickWords>,
+                  ctx: &Context,
+                  nick: &str)
+                  -> &'a mut NickWords {
+    let nick = strip_nick(nick);
+    nicks.entry(ctx.casemapping.to_lower(nick)).or_insert_with(|| {
+        NickWords {
+            nick: nick.to_owned(),
+            words: WordCount::default(),
+            introduced: Vec::new(),
+        }
+    })
+}
+
+/// Count the words of messages and actions, globally and per nick.
+/// With `identities`, per person instead of per nick.
+pub fn vocabulary(ctx: &Context,
+                  input: &mut BufRead,
+                  decoder: &mut Decode,
+                  mut identities: Option<&mut Identities>)
+                  -> ilc_base::Result<Vocabulary> {
+    let mut total = WordCount::default();
+    let mut nicks: HashMap<String, NickWords> = HashMap::new();
+
+    for e in decoder.decode(&ctx, input) {
+        let e = try!(e);
+        if let Some(ref mut ids) = identities {
+            ids.observe(&e);
+        }
+        match e {
+            Event { ty: Type::Msg { ref from, ref content }, .. } |
+            Event { ty: Type::Action { ref from, ref content }, .. } => {
+                let n = nick_words(&mut nicks, ctx, from);
+                for w in words(content) {
+                    let w = w.to_lowercase();
+                    if !total.counts.contains_key(&w) {
+                        n.introduced.push(w.clone());
+                    }
+                    total.add(&w, 1);
+                    n.words.add(&w, 1);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(ids) = identities {
+        nicks = ids.merge(nicks);
+    }
+
+    Ok(Vocabulary {
+        words: total,
+        nicks: nicks,
+    })
+}
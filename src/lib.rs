@@ -15,5 +15,5 @@ pub use ilc_ops::parse::{self, parse};
 pub use ilc_ops::sort::{self, sort};
 pub use ilc_ops::merge::{self, merge};
 
-pub use ilc_format_weechat::Weechat;
+pub use ilc_format_weechat::{Prefixes, Weechat};
 pub use ilc_format_energymech::Energymech;
@@ -14,9 +14,11 @@ fn identity() {
 
     convert(&Context::default(),
             &mut (&original as &[u8]),
-            &mut Energymech,
+            &mut Energymech::default(),
             &mut output,
-            &Energymech)
+            &Energymech::default(),
+            None,
+            false)
         .expect("Conversion failed");
 
     files::write("identity.out", &output);
@@ -34,9 +36,9 @@ fn identity() {
  *
  * merge(&Context::default(),
  * vec![&mut part1, &mut part2],
- * &mut Energymech,
+ * &mut Energymech::default(),
  * &mut output,
- * &Energymech)
+ * &Energymech::default())
  * .expect("Merge failed");
  *
  * files::write("merged.out", &output);